@@ -0,0 +1,41 @@
+//! Runs the server with an extra `dimmer` device kind:
+//!
+//! ```text
+//! curl -X PUT -H 'content-type: application/json' \
+//!     -d '{"device": "dimmer", "level": 40}' localhost:4083/api/v1/rooms/R/devices/D
+//! ```
+
+use http_home::device_kind::{DeviceKind, DeviceRegistry, FieldSpec};
+use http_home::storage::Storage;
+use http_home::{home::Home, run, ServerOptions};
+use std::net::TcpListener;
+
+struct Dimmer;
+
+impl DeviceKind for Dimmer {
+    fn name(&self) -> &str {
+        "dimmer"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        vec![
+            FieldSpec::number("level")
+                .range(Some(0.), Some(100.))
+                .unit("%")
+                .required(),
+            FieldSpec::boolean("on"),
+        ]
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let mut registry = DeviceRegistry::default();
+    registry.register(Dimmer).expect("dimmer is not built in");
+    let listener = TcpListener::bind(("127.0.0.1", http_home::config::DEFAULT_PORT))?;
+    let options = ServerOptions {
+        registry,
+        ..Default::default()
+    };
+    run(listener, Home::restore(), Storage::memory(), options)?.await
+}
@@ -1,28 +1,18 @@
-use reqwest::Client;
+use clap::Parser;
+use http_home::client::HomeClient;
+use http_home::config::ClientArgs;
 
 #[tokio::main]
 async fn main() {
-    let client = Client::new();
+    let args = ClientArgs::parse();
+    let client = HomeClient::new(&args.url(""));
+    println!("Ask for room list: {:?}", client.rooms().await);
     println!(
-        "Ask for room_list: Server says '{}'",
-        ask_list(&client, "room_list").await
+        "Ask for device list in room 'R': {:?}",
+        client.devices("R").await
     );
-    println!(
-        "Ask for device_list in room 'R': Server says '{}'",
-        ask_list(&client, "device_list/R").await
-    );
-    println!(
-        "Ask for device_list in invalid room: Server says '{}'",
-        ask_list(&client, "device_list/no room").await
-    );
-}
-
-async fn ask_list(client: &Client, path: &str) -> String {
-    let resp = client
-        .get(format!("http://127.0.0.1:4083/{path}"))
-        .send()
-        .await
-        .expect("Error: request failed");
-    println!("{:#?}", resp);
-    resp.text().await.unwrap()
+    match client.devices("no room").await {
+        Ok(devices) => println!("Device list in invalid room: {devices:?}"),
+        Err(e) => println!("Ask for device list in invalid room: Server says '{e}'"),
+    }
 }
@@ -1,13 +1,13 @@
-use reqwest::Client;
+use clap::Parser;
+use http_home::client::HomeClient;
+use http_home::config::ClientArgs;
 
 #[tokio::main]
 async fn main() {
-    let client = Client::new();
-    let resp = client
-        .get("http://127.0.0.1:4083/report")
-        .send()
-        .await
-        .expect("Error: request failed");
-    println!("{:#?}", resp);
-    println!("Server reports:\n{}", resp.text().await.unwrap());
+    let args = ClientArgs::parse();
+    let client = HomeClient::new(&args.url(""));
+    match client.report().await {
+        Ok(report) => println!("Server reports:\n{report}"),
+        Err(e) => eprintln!("Error: {e}"),
+    }
 }
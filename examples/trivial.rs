@@ -1,8 +1,12 @@
+use clap::Parser;
+use http_home::config::ClientArgs;
+
 #[tokio::main]
 async fn main() {
+    let args = ClientArgs::parse();
     let client = reqwest::Client::new();
     let resp = client
-        .get("http://127.0.0.1:4083/")
+        .get(args.url(""))
         .send()
         .await
         .expect("Error: request failed");
@@ -0,0 +1,2066 @@
+//! Resource-oriented API mounted under `/api/v1`.
+//!
+//! `/rooms/{room_name}` and `/rooms/{room_name}/devices/{device_name}` are resources
+//! manipulated with GET, PUT, PATCH and DELETE, unlike the legacy verb-in-path routes.
+//! `/device-types` describes the fields every device type accepts
+//! and `/events` streams changes over a WebSocket, `/events/stream` as Server-Sent Events.
+//! `/rules/{rule_name}` are the automation rules run on every change
+//! and `/schedules/{schedule_name}` the actions run on a timetable.
+//! `/scenes/{scene_name}` are target states of devices, captured and applied as a whole.
+//! `/energy` is what the sockets used, priced with the tariff at `/energy/tariff`.
+//! `/alerts` lists the open contacts and wet leak detectors.
+//! `/rooms/{room_name}/devices/{device_name}/channels/{channel}` are the outlets
+//! of a power strip, numbered from 1.
+
+use crate::clock::Clock;
+use crate::device_kind::{DeviceRegistry, KindSchema};
+use crate::energy::{EnergyUsage, Tariff};
+use crate::error::HomeError;
+use crate::events::{self, ChangeEvent, EventBus};
+use crate::home::Home;
+use crate::payload::{ChannelPayload, DevicePayload, PowerStripPayload};
+use crate::problem::Problem;
+use crate::rules::Rule;
+use crate::scene::{Scene, SceneEdit, SceneReport};
+use crate::scheduler::Schedule;
+use crate::sensors::{self, Alert};
+use crate::smart_device::{Channel, Device, PowerStrip};
+use crate::storage::Storage;
+use crate::telemetry::{DeviceHistory, HistoryQuery, Telemetry};
+use crate::web_routes::{
+    device_payload, save_and_publish, save_home, HandleRequestError, HandleRequestResult,
+};
+use crate::SmartHome;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use jiff::civil::DateTime;
+use jiff::Zoned;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+pub const PREFIX: &str = "/api/v1";
+
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/rooms", web::get().to(list_rooms))
+        .service(
+            web::resource("/rooms/{room_name}")
+                .route(web::get().to(get_room))
+                .route(web::put().to(put_room))
+                .route(web::delete().to(delete_room)),
+        )
+        .route("/rooms/{room_name}/devices", web::get().to(list_devices))
+        .service(
+            web::resource("/rooms/{room_name}/devices/{device_name}")
+                .route(web::get().to(get_device))
+                .route(web::put().to(put_device))
+                .route(web::patch().to(patch_device))
+                .route(web::delete().to(delete_device)),
+        )
+        .route(
+            "/rooms/{room_name}/devices/{device_name}/channels",
+            web::get().to(list_channels),
+        )
+        .service(
+            web::resource("/rooms/{room_name}/devices/{device_name}/channels/{channel}")
+                .route(web::get().to(get_channel))
+                .route(web::patch().to(patch_channel)),
+        )
+        .route(
+            "/rooms/{room_name}/devices/{device_name}/history",
+            web::get().to(device_history),
+        )
+        .route("/device-types", web::get().to(list_device_types))
+        .route("/device-types/{type_name}", web::get().to(get_device_type))
+        .route("/events", web::get().to(events::websocket))
+        .route("/events/stream", web::get().to(events::event_stream))
+        .route("/rules", web::get().to(list_rules))
+        .service(
+            web::resource("/rules/{rule_name}")
+                .route(web::get().to(get_rule))
+                .route(web::put().to(put_rule))
+                .route(web::delete().to(delete_rule)),
+        )
+        .route("/schedules", web::get().to(list_schedules))
+        .service(
+            web::resource("/schedules/{schedule_name}")
+                .route(web::get().to(get_schedule))
+                .route(web::put().to(put_schedule))
+                .route(web::delete().to(delete_schedule)),
+        )
+        .route("/scenes", web::get().to(list_scenes))
+        .service(
+            web::resource("/scenes/{scene_name}")
+                .route(web::get().to(get_scene))
+                .route(web::put().to(put_scene))
+                .route(web::patch().to(patch_scene))
+                .route(web::delete().to(delete_scene)),
+        )
+        .route(
+            "/scenes/{scene_name}/capture",
+            web::post().to(capture_scene),
+        )
+        .route("/scenes/{scene_name}/apply", web::post().to(apply_scene))
+        .route("/energy", web::get().to(energy_usage))
+        .service(
+            web::resource("/energy/tariff")
+                .route(web::get().to(get_tariff))
+                .route(web::put().to(put_tariff))
+                .route(web::delete().to(delete_tariff)),
+        )
+        .route("/alerts", web::get().to(list_alerts));
+}
+
+#[derive(Debug, OpenApi)]
+#[openapi(paths(
+    list_rooms,
+    get_room,
+    put_room,
+    delete_room,
+    list_devices,
+    get_device,
+    put_device,
+    patch_device,
+    delete_device,
+    list_channels,
+    get_channel,
+    patch_channel,
+    device_history,
+    list_device_types,
+    get_device_type,
+    events::websocket,
+    events::event_stream,
+    list_rules,
+    get_rule,
+    put_rule,
+    delete_rule,
+    list_schedules,
+    get_schedule,
+    put_schedule,
+    delete_schedule,
+    list_scenes,
+    get_scene,
+    put_scene,
+    patch_scene,
+    delete_scene,
+    capture_scene,
+    apply_scene,
+    energy_usage,
+    get_tariff,
+    put_tariff,
+    delete_tariff,
+    list_alerts,
+))]
+pub struct ApiDoc;
+
+/// A schedule with the next time it runs.
+#[derive(Debug, Serialize, ToSchema)]
+struct ScheduleView<'a> {
+    #[serde(flatten)]
+    #[schema(inline)]
+    schedule: &'a Schedule,
+    #[schema(value_type = Option<String>, example = "2024-03-04T07:00:00")]
+    next_run: Option<DateTime>,
+}
+
+impl<'a> ScheduleView<'a> {
+    fn new(schedule: &'a Schedule, now: &Zoned) -> Self {
+        Self {
+            schedule,
+            next_run: schedule.next_run(now),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RoomView<'a> {
+    #[schema(value_type = String)]
+    name: &'a str,
+    /// Dict of every device by its name.
+    #[schema(value_type = HashMap<String, HashMap<String, String>>)]
+    devices: HashMap<&'a String, HashMap<String, String>>,
+}
+
+/// An outlet of a power strip.
+#[derive(Debug, Serialize, ToSchema)]
+struct ChannelView {
+    channel: usize,
+    on: bool,
+    /// Amperes drawn while on.
+    current: f64,
+    /// Watts drawn now.
+    power: f64,
+}
+
+impl ChannelView {
+    fn new(strip: &PowerStrip, number: usize, channel: &Channel) -> Self {
+        Self {
+            channel: number,
+            on: channel.is_on(),
+            current: channel.get_current(),
+            power: strip.channel_power(channel),
+        }
+    }
+}
+
+pub fn room_location(room_name: &str) -> String {
+    format!("{PREFIX}/rooms/{}", encode(room_name))
+}
+
+pub fn rule_location(rule_name: &str) -> String {
+    format!("{PREFIX}/rules/{}", encode(rule_name))
+}
+
+pub fn schedule_location(schedule_name: &str) -> String {
+    format!("{PREFIX}/schedules/{}", encode(schedule_name))
+}
+
+pub fn scene_location(scene_name: &str) -> String {
+    format!("{PREFIX}/scenes/{}", encode(scene_name))
+}
+
+pub fn device_location(room_name: &str, device_name: &str) -> String {
+    format!(
+        "{}/devices/{}",
+        room_location(room_name),
+        encode(device_name)
+    )
+}
+
+fn encode(segment: &str) -> String {
+    utf8_percent_encode(segment, NON_ALPHANUMERIC).to_string()
+}
+
+/// Parses a JSON body, blaming malformed JSON on the client and
+/// well-formed JSON of the wrong shape on the resource with `invalid`.
+fn json_body<T: DeserializeOwned>(
+    body: &[u8],
+    invalid: fn(String) -> HandleRequestError,
+) -> HandleRequestResult<T> {
+    serde_json::from_slice(body).map_err(|e| {
+        if e.is_data() {
+            invalid(e.to_string())
+        } else {
+            HomeError::bad_json(e).into()
+        }
+    })
+}
+
+fn path_param<'a>(req: &'a HttpRequest, name: &str) -> &'a str {
+    req.match_info().get(name).unwrap_or_default()
+}
+
+#[utoipa::path(
+    get, path = "/rooms", tag = "rooms",
+    responses((status = 200, description = "Names of all rooms", body = Vec<String>))
+)]
+async fn list_rooms(home: web::Data<SmartHome>) -> HttpResponse {
+    let home = home.read().await;
+    let room_list: Vec<_> = home.room_names_list().collect();
+    HttpResponse::Ok().json(room_list)
+}
+
+#[utoipa::path(
+    get, path = "/rooms/{room_name}", tag = "rooms",
+    params(("room_name" = String, Path, description = "Name of the room")),
+    responses(
+        (status = 200, description = "The room with the dicts of its devices", body = RoomView),
+        (status = 404, description = "No such room", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_room(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    registry: web::Data<DeviceRegistry>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let home = home.read().await;
+    let room = home
+        .get_room_by_name(room_name)
+        .ok_or_else(|| HomeError::room_not_found(room_name))?;
+    Ok(HttpResponse::Ok().json(RoomView {
+        name: room_name,
+        devices: room
+            .devices()
+            .map(|(name, device)| (name, registry.device_dict(device)))
+            .collect(),
+    }))
+}
+
+/// Creates the room (201) or leaves an existing one as it is (204).
+#[utoipa::path(
+    put, path = "/rooms/{room_name}", tag = "rooms",
+    params(("room_name" = String, Path, description = "Name of the room")),
+    responses(
+        (status = 201, description = "Room created", headers(("Location" = String))),
+        (status = 204, description = "Room already exists"),
+    )
+)]
+async fn put_room(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let mut home = home.write().await;
+    if home.get_room_by_name(room_name).is_some() {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    home.add_room(room_name)?;
+    let added = ChangeEvent::RoomAdded {
+        room: room_name.into(),
+    };
+    save_and_publish(
+        &storage,
+        home,
+        &registry,
+        &bus,
+        [added],
+        HttpResponse::Created()
+            .insert_header((header::LOCATION, room_location(room_name)))
+            .finish(),
+    )
+    .await
+}
+
+#[utoipa::path(
+    delete, path = "/rooms/{room_name}", tag = "rooms",
+    params(("room_name" = String, Path, description = "Name of the room")),
+    responses((status = 204, description = "Room removed"), (status = 404, description = "No such room", body = Problem, content_type = "application/problem+json"))
+)]
+async fn delete_room(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let mut home = home.write().await;
+    let room = home.remove_room(room_name)?;
+    save_and_publish(
+        &storage,
+        home,
+        &registry,
+        &bus,
+        events::room_removed(&registry, room_name, &room),
+        HttpResponse::NoContent().finish(),
+    )
+    .await
+}
+
+#[utoipa::path(
+    get, path = "/rooms/{room_name}/devices", tag = "devices",
+    params(("room_name" = String, Path, description = "Name of the room")),
+    responses(
+        (status = 200, description = "Names of the devices in the room", body = Vec<String>),
+        (status = 404, description = "No such room", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn list_devices(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let home = home.read().await;
+    let list = home
+        .device_names_list(room_name)
+        .ok_or_else(|| HomeError::room_not_found(room_name))?;
+    Ok(HttpResponse::Ok().json(list))
+}
+
+#[utoipa::path(
+    get, path = "/rooms/{room_name}/devices/{device_name}", tag = "devices",
+    params(
+        ("room_name" = String, Path, description = "Name of the room"),
+        ("device_name" = String, Path, description = "Name of the device in the room"),
+    ),
+    responses(
+        (status = 200, description = "Device dict", body = HashMap<String, String>),
+        (status = 404, description = "No such room or device", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_device(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    registry: web::Data<DeviceRegistry>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let device_name = path_param(&req, "device_name");
+    let home = home.read().await;
+    let device = home
+        .get_device_by_path(room_name, device_name)
+        .ok_or_else(|| HomeError::device_not_found(room_name, device_name))?;
+    Ok(HttpResponse::Ok().json(registry.device_dict(device)))
+}
+
+/// Creates the device (201) or replaces an existing one with the same name (204).
+/// With `If-None-Match: *` an existing device is left alone and 409 is returned.
+#[utoipa::path(
+    put, path = "/rooms/{room_name}/devices/{device_name}", tag = "devices",
+    params(
+        ("room_name" = String, Path, description = "Name of the room"),
+        ("device_name" = String, Path, description = "Name of the device in the room"),
+        ("If-None-Match" = Option<String>, Header, description = "`*` to only create the device"),
+    ),
+    request_body(content = DevicePayload, description = "Device fields as JSON, or the same fields in the query string"),
+    responses(
+        (status = 201, description = "Device created", headers(("Location" = String))),
+        (status = 204, description = "Device replaced"),
+        (status = 400, description = "Malformed JSON body", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "No such room", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Device exists and `If-None-Match: *` was given", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid or missing device field", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn put_device(
+    req: HttpRequest,
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let device_name = path_param(&req, "device_name");
+    let new_device = device_payload(&req, &body, &registry)?.into_device()?;
+    let create_only = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .is_some_and(|value| value == "*");
+    let mut home = home.write().await;
+    let replaced = if create_only {
+        home.add_device(room_name, device_name, new_device)?;
+        None
+    } else {
+        home.replace_device(room_name, device_name, new_device)?
+    };
+    let new_device = home
+        .get_device_by_path(room_name, device_name)
+        .expect("the device was just put");
+    let (changes, response) = match replaced {
+        Some(old) => (
+            events::device_changed(&registry, room_name, device_name, &old, new_device),
+            HttpResponse::NoContent().finish(),
+        ),
+        None => (
+            vec![events::device_added(
+                &registry,
+                room_name,
+                device_name,
+                new_device,
+            )],
+            HttpResponse::Created()
+                .insert_header((header::LOCATION, device_location(room_name, device_name)))
+                .finish(),
+        ),
+    };
+    save_and_publish(&storage, home, &registry, &bus, changes, response).await
+}
+
+/// Changes only the given properties of an existing device.
+#[utoipa::path(
+    patch, path = "/rooms/{room_name}/devices/{device_name}", tag = "devices",
+    params(
+        ("room_name" = String, Path, description = "Name of the room"),
+        ("device_name" = String, Path, description = "Name of the device in the room"),
+    ),
+    request_body(content = DevicePayload, description = "Device fields as JSON, or the same fields in the query string"),
+    responses(
+        (status = 200, description = "Updated device dict", body = HashMap<String, String>),
+        (status = 400, description = "Malformed JSON body", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "No such room or device", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The device is of another type", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid or missing device field", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn patch_device(
+    req: HttpRequest,
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let device_name = path_param(&req, "device_name");
+    let payload = device_payload(&req, &body, &registry)?;
+    let mut home = home.write().await;
+    let changes = events::update_device(&mut home, &registry, room_name, device_name, &payload)?;
+    let device = home
+        .get_device_by_path(room_name, device_name)
+        .expect("the device was just updated");
+    let response = HttpResponse::Ok().json(registry.device_dict(device));
+    save_and_publish(&storage, home, &registry, &bus, changes, response).await
+}
+
+#[utoipa::path(
+    delete, path = "/rooms/{room_name}/devices/{device_name}", tag = "devices",
+    params(
+        ("room_name" = String, Path, description = "Name of the room"),
+        ("device_name" = String, Path, description = "Name of the device in the room"),
+    ),
+    responses((status = 204, description = "Device removed"), (status = 404, description = "No such room or device", body = Problem, content_type = "application/problem+json"))
+)]
+async fn delete_device(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let device_name = path_param(&req, "device_name");
+    let mut home = home.write().await;
+    let device = home.remove_device(room_name, device_name)?;
+    let removed = events::device_removed(&registry, room_name, device_name, &device);
+    save_and_publish(
+        &storage,
+        home,
+        &registry,
+        &bus,
+        [removed],
+        HttpResponse::NoContent().finish(),
+    )
+    .await
+}
+
+/// Recorded values of the device properties, optionally downsampled.
+#[utoipa::path(
+    get, path = "/rooms/{room_name}/devices/{device_name}/history", tag = "devices",
+    params(
+        ("room_name" = String, Path, description = "Name of the room"),
+        ("device_name" = String, Path, description = "Name of the device in the room"),
+        HistoryQuery,
+    ),
+    responses(
+        (status = 200, description = "Values of each property, oldest first", body = DeviceHistory),
+        (status = 400, description = "Invalid query parameters", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "No such room or device", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn device_history(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    telemetry: web::Data<Telemetry>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let device_name = path_param(&req, "device_name");
+    let query = web::Query::<HistoryQuery>::from_query(req.query_string())
+        .map_err(|e| HandleRequestError::InvalidQuery(e.to_string()))?;
+    query.validate().map_err(HandleRequestError::InvalidQuery)?;
+    home.read()
+        .await
+        .get_device_by_path(room_name, device_name)
+        .ok_or_else(|| HomeError::device_not_found(room_name, device_name))?;
+    Ok(HttpResponse::Ok().json(telemetry.history(room_name, device_name, &query)))
+}
+
+fn power_strip<'a>(
+    home: &'a Home,
+    room_name: &str,
+    device_name: &str,
+) -> HandleRequestResult<&'a PowerStrip> {
+    match home.get_device_by_path(room_name, device_name) {
+        Some(Device::PowerStrip(strip)) => Ok(strip),
+        Some(device) => Err(HomeError::TypeMismatch {
+            room: room_name.into(),
+            device: device_name.into(),
+            expected: "power_strip".into(),
+            actual: device.type_name().into(),
+        }
+        .into()),
+        None => Err(HomeError::device_not_found(room_name, device_name).into()),
+    }
+}
+
+/// The outlet the request is about, with its number.
+fn strip_channel<'a>(
+    req: &HttpRequest,
+    home: &'a Home,
+) -> HandleRequestResult<(&'a PowerStrip, usize, &'a Channel)> {
+    let room_name = path_param(req, "room_name");
+    let device_name = path_param(req, "device_name");
+    let strip = power_strip(home, room_name, device_name)?;
+    let channel = path_param(req, "channel");
+    channel
+        .parse()
+        .ok()
+        .and_then(|number| Some((strip, number, strip.channel(number)?)))
+        .ok_or_else(|| {
+            HandleRequestError::ChannelNotFound(format!("{room_name}/{device_name}/{channel}"))
+        })
+}
+
+#[utoipa::path(
+    get, path = "/rooms/{room_name}/devices/{device_name}/channels", tag = "devices",
+    params(
+        ("room_name" = String, Path, description = "Name of the room"),
+        ("device_name" = String, Path, description = "Name of the power strip in the room"),
+    ),
+    responses(
+        (status = 200, description = "Every outlet of the strip", body = Vec<ChannelView>),
+        (status = 404, description = "No such room or device", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The device is not a power strip", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn list_channels(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let device_name = path_param(&req, "device_name");
+    let home = home.read().await;
+    let strip = power_strip(&home, room_name, device_name)?;
+    let channels: Vec<_> = strip
+        .channels()
+        .map(|(number, channel)| ChannelView::new(strip, number, channel))
+        .collect();
+    Ok(HttpResponse::Ok().json(channels))
+}
+
+#[utoipa::path(
+    get, path = "/rooms/{room_name}/devices/{device_name}/channels/{channel}", tag = "devices",
+    params(
+        ("room_name" = String, Path, description = "Name of the room"),
+        ("device_name" = String, Path, description = "Name of the power strip in the room"),
+        ("channel" = usize, Path, description = "Number of the outlet, from 1"),
+    ),
+    responses(
+        (status = 200, description = "The outlet", body = ChannelView),
+        (status = 404, description = "No such room, device or outlet", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The device is not a power strip", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_channel(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+) -> HandleRequestResult<HttpResponse> {
+    let home = home.read().await;
+    let (strip, number, channel) = strip_channel(&req, &home)?;
+    Ok(HttpResponse::Ok().json(ChannelView::new(strip, number, channel)))
+}
+
+/// Switches an outlet or changes what it draws. A change that would make the outlets
+/// switched on draw more than the strip is rated for is refused with 422.
+#[utoipa::path(
+    patch, path = "/rooms/{room_name}/devices/{device_name}/channels/{channel}", tag = "devices",
+    params(
+        ("room_name" = String, Path, description = "Name of the room"),
+        ("device_name" = String, Path, description = "Name of the power strip in the room"),
+        ("channel" = usize, Path, description = "Number of the outlet, from 1"),
+    ),
+    request_body = ChannelPayload,
+    responses(
+        (status = 200, description = "The updated outlet", body = ChannelView),
+        (status = 400, description = "Malformed JSON body", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "No such room, device or outlet", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The device is not a power strip", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid value or the strip would be overloaded", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn patch_channel(
+    req: HttpRequest,
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = path_param(&req, "room_name");
+    let device_name = path_param(&req, "device_name");
+    let change: ChannelPayload = json_body(&body, |reason| {
+        HomeError::invalid_value("channel", &reason).into()
+    })?;
+    let mut home = home.write().await;
+    let (_, number, _) = strip_channel(&req, &home)?;
+    let payload = DevicePayload::PowerStrip(PowerStripPayload {
+        outlets: [(number, change)].into(),
+        ..Default::default()
+    });
+    let changes = events::update_device(&mut home, &registry, room_name, device_name, &payload)?;
+    let (strip, number, channel) = strip_channel(&req, &home)?;
+    let response = HttpResponse::Ok().json(ChannelView::new(strip, number, channel));
+    save_and_publish(&storage, home, &registry, &bus, changes, response).await
+}
+
+#[utoipa::path(
+    get, path = "/device-types", tag = "device types",
+    responses((status = 200, description = "Fields of every supported device type", body = Vec<KindSchema>))
+)]
+async fn list_device_types(registry: web::Data<DeviceRegistry>) -> HttpResponse {
+    HttpResponse::Ok().json(registry.schemas())
+}
+
+#[utoipa::path(
+    get, path = "/device-types/{type_name}", tag = "device types",
+    params(("type_name" = String, Path, description = "Value of the `device` field")),
+    responses(
+        (status = 200, description = "Fields of the device type", body = KindSchema),
+        (status = 404, description = "Unknown device type", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_device_type(
+    req: HttpRequest,
+    registry: web::Data<DeviceRegistry>,
+) -> HandleRequestResult<HttpResponse> {
+    let type_name = path_param(&req, "type_name");
+    let kind = registry
+        .get(type_name)
+        .ok_or_else(|| HandleRequestError::UnknownDeviceType(type_name.into()))?;
+    Ok(HttpResponse::Ok().json(KindSchema::of(kind.as_ref())))
+}
+
+#[utoipa::path(
+    get, path = "/rules", tag = "rules",
+    responses((status = 200, description = "Every automation rule by its name", body = BTreeMap<String, Rule>))
+)]
+async fn list_rules(home: web::Data<SmartHome>) -> HttpResponse {
+    let home = home.read().await;
+    HttpResponse::Ok().json(home.rules())
+}
+
+#[utoipa::path(
+    get, path = "/rules/{rule_name}", tag = "rules",
+    params(("rule_name" = String, Path, description = "Name of the rule")),
+    responses(
+        (status = 200, description = "The rule", body = Rule),
+        (status = 404, description = "No such rule", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_rule(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+) -> HandleRequestResult<HttpResponse> {
+    let rule_name = path_param(&req, "rule_name");
+    let home = home.read().await;
+    let rule = home
+        .get_rule(rule_name)
+        .ok_or_else(|| HandleRequestError::RuleNotFound(rule_name.into()))?;
+    Ok(HttpResponse::Ok().json(rule))
+}
+
+/// Creates the rule (201) or replaces an existing one with the same name (204).
+#[utoipa::path(
+    put, path = "/rules/{rule_name}", tag = "rules",
+    params(("rule_name" = String, Path, description = "Name of the rule")),
+    request_body(content = Rule, description = "Triggers, conditions and actions of the rule"),
+    responses(
+        (status = 201, description = "Rule created", headers(("Location" = String))),
+        (status = 204, description = "Rule replaced"),
+        (status = 400, description = "Malformed JSON body", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid rule", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn put_rule(
+    req: HttpRequest,
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+) -> HandleRequestResult<HttpResponse> {
+    let rule_name = path_param(&req, "rule_name");
+    let rule: Rule = json_body(&body, HandleRequestError::InvalidRule)?;
+    rule.validate().map_err(HandleRequestError::InvalidRule)?;
+    let mut home = home.write().await;
+    let response = match home.put_rule(rule_name, rule) {
+        Some(_) => HttpResponse::NoContent().finish(),
+        None => HttpResponse::Created()
+            .insert_header((header::LOCATION, rule_location(rule_name)))
+            .finish(),
+    };
+    save_home(&storage, home, response).await
+}
+
+#[utoipa::path(
+    delete, path = "/rules/{rule_name}", tag = "rules",
+    params(("rule_name" = String, Path, description = "Name of the rule")),
+    responses((status = 204, description = "Rule removed"), (status = 404, description = "No such rule", body = Problem, content_type = "application/problem+json"))
+)]
+async fn delete_rule(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+) -> HandleRequestResult<HttpResponse> {
+    let rule_name = path_param(&req, "rule_name");
+    let mut home = home.write().await;
+    home.remove_rule(rule_name)
+        .ok_or_else(|| HandleRequestError::RuleNotFound(rule_name.into()))?;
+    save_home(&storage, home, HttpResponse::NoContent().finish()).await
+}
+
+#[utoipa::path(
+    get, path = "/schedules", tag = "schedules",
+    responses((status = 200, description = "Every schedule by its name", body = BTreeMap<String, ScheduleView>))
+)]
+async fn list_schedules(home: web::Data<SmartHome>, clock: web::Data<dyn Clock>) -> HttpResponse {
+    let home = home.read().await;
+    let now = clock.now();
+    let schedules: BTreeMap<_, _> = home
+        .schedules()
+        .iter()
+        .map(|(name, schedule)| (name, ScheduleView::new(schedule, &now)))
+        .collect();
+    HttpResponse::Ok().json(schedules)
+}
+
+#[utoipa::path(
+    get, path = "/schedules/{schedule_name}", tag = "schedules",
+    params(("schedule_name" = String, Path, description = "Name of the schedule")),
+    responses(
+        (status = 200, description = "The schedule", body = ScheduleView),
+        (status = 404, description = "No such schedule", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_schedule(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    clock: web::Data<dyn Clock>,
+) -> HandleRequestResult<HttpResponse> {
+    let schedule_name = path_param(&req, "schedule_name");
+    let home = home.read().await;
+    let schedule = home
+        .get_schedule(schedule_name)
+        .ok_or_else(|| HandleRequestError::ScheduleNotFound(schedule_name.into()))?;
+    Ok(HttpResponse::Ok().json(ScheduleView::new(schedule, &clock.now())))
+}
+
+/// Creates the schedule (201) or replaces an existing one with the same name (204).
+/// Either way, only occurrences after now are run.
+#[utoipa::path(
+    put, path = "/schedules/{schedule_name}", tag = "schedules",
+    params(("schedule_name" = String, Path, description = "Name of the schedule")),
+    request_body(content = Schedule, description = "When to run which actions"),
+    responses(
+        (status = 201, description = "Schedule created", headers(("Location" = String))),
+        (status = 204, description = "Schedule replaced"),
+        (status = 400, description = "Malformed JSON body", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid schedule", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn put_schedule(
+    req: HttpRequest,
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    clock: web::Data<dyn Clock>,
+) -> HandleRequestResult<HttpResponse> {
+    let schedule_name = path_param(&req, "schedule_name");
+    let mut schedule: Schedule = json_body(&body, HandleRequestError::InvalidSchedule)?;
+    let now = clock.now();
+    schedule
+        .validate(&now)
+        .map_err(HandleRequestError::InvalidSchedule)?;
+    schedule.checked = Some(now.timestamp());
+    let mut home = home.write().await;
+    let response = match home.put_schedule(schedule_name, schedule) {
+        Some(_) => HttpResponse::NoContent().finish(),
+        None => HttpResponse::Created()
+            .insert_header((header::LOCATION, schedule_location(schedule_name)))
+            .finish(),
+    };
+    save_home(&storage, home, response).await
+}
+
+#[utoipa::path(
+    delete, path = "/schedules/{schedule_name}", tag = "schedules",
+    params(("schedule_name" = String, Path, description = "Name of the schedule")),
+    responses((status = 204, description = "Schedule removed"), (status = 404, description = "No such schedule", body = Problem, content_type = "application/problem+json"))
+)]
+async fn delete_schedule(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+) -> HandleRequestResult<HttpResponse> {
+    let schedule_name = path_param(&req, "schedule_name");
+    let mut home = home.write().await;
+    home.remove_schedule(schedule_name)
+        .ok_or_else(|| HandleRequestError::ScheduleNotFound(schedule_name.into()))?;
+    save_home(&storage, home, HttpResponse::NoContent().finish()).await
+}
+
+#[utoipa::path(
+    get, path = "/scenes", tag = "scenes",
+    responses((status = 200, description = "Every scene by its name", body = BTreeMap<String, Scene>))
+)]
+async fn list_scenes(home: web::Data<SmartHome>) -> HttpResponse {
+    let home = home.read().await;
+    HttpResponse::Ok().json(home.scenes())
+}
+
+#[utoipa::path(
+    get, path = "/scenes/{scene_name}", tag = "scenes",
+    params(("scene_name" = String, Path, description = "Name of the scene")),
+    responses(
+        (status = 200, description = "The scene", body = Scene),
+        (status = 404, description = "No such scene", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_scene(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+) -> HandleRequestResult<HttpResponse> {
+    let scene_name = path_param(&req, "scene_name");
+    let home = home.read().await;
+    let scene = home
+        .get_scene(scene_name)
+        .ok_or_else(|| HomeError::SceneNotFound {
+            scene: scene_name.into(),
+        })?;
+    Ok(HttpResponse::Ok().json(scene))
+}
+
+/// Creates the scene (201) or replaces an existing one with the same name (204).
+/// The scene must be applicable to the home as it is.
+#[utoipa::path(
+    put, path = "/scenes/{scene_name}", tag = "scenes",
+    params(("scene_name" = String, Path, description = "Name of the scene")),
+    request_body(content = Scene, description = "Target properties of each device"),
+    responses(
+        (status = 201, description = "Scene created", headers(("Location" = String))),
+        (status = 204, description = "Scene replaced"),
+        (status = 400, description = "Malformed JSON body", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "No such room or device", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid scene or device field", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn put_scene(
+    req: HttpRequest,
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+) -> HandleRequestResult<HttpResponse> {
+    let scene_name = path_param(&req, "scene_name");
+    let scene: Scene = json_body(&body, HandleRequestError::InvalidScene)?;
+    let mut home = home.write().await;
+    scene.plan(&home, &registry)?;
+    let response = match home.put_scene(scene_name, scene) {
+        Some(_) => HttpResponse::NoContent().finish(),
+        None => HttpResponse::Created()
+            .insert_header((header::LOCATION, scene_location(scene_name)))
+            .finish(),
+    };
+    save_home(&storage, home, response).await
+}
+
+/// Merges the given properties into the scene; a device given as `null` is dropped from it.
+#[utoipa::path(
+    patch, path = "/scenes/{scene_name}", tag = "scenes",
+    params(("scene_name" = String, Path, description = "Name of the scene")),
+    request_body(content = SceneEdit, description = "Properties to change by room and device name"),
+    responses(
+        (status = 200, description = "The edited scene", body = Scene),
+        (status = 400, description = "Malformed JSON body", body = Problem, content_type = "application/problem+json"),
+        (status = 404, description = "No such scene, room or device", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid scene or device field", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn patch_scene(
+    req: HttpRequest,
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+) -> HandleRequestResult<HttpResponse> {
+    let scene_name = path_param(&req, "scene_name");
+    let edit: SceneEdit = json_body(&body, HandleRequestError::InvalidScene)?;
+    let mut home = home.write().await;
+    let mut scene =
+        home.get_scene(scene_name)
+            .cloned()
+            .ok_or_else(|| HomeError::SceneNotFound {
+                scene: scene_name.into(),
+            })?;
+    scene.edit(edit);
+    scene.plan(&home, &registry)?;
+    let response = HttpResponse::Ok().json(&scene);
+    home.put_scene(scene_name, scene);
+    save_home(&storage, home, response).await
+}
+
+#[utoipa::path(
+    delete, path = "/scenes/{scene_name}", tag = "scenes",
+    params(("scene_name" = String, Path, description = "Name of the scene")),
+    responses((status = 204, description = "Scene removed"), (status = 404, description = "No such scene", body = Problem, content_type = "application/problem+json"))
+)]
+async fn delete_scene(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+) -> HandleRequestResult<HttpResponse> {
+    let scene_name = path_param(&req, "scene_name");
+    let mut home = home.write().await;
+    home.remove_scene(scene_name)
+        .ok_or_else(|| HomeError::SceneNotFound {
+            scene: scene_name.into(),
+        })?;
+    save_home(&storage, home, HttpResponse::NoContent().finish()).await
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CaptureQuery {
+    /// Only capture the devices in this room.
+    room: Option<String>,
+}
+
+/// Saves the current state of the devices as the scene, creating (201) or replacing (200) it.
+#[utoipa::path(
+    post, path = "/scenes/{scene_name}/capture", tag = "scenes",
+    params(("scene_name" = String, Path, description = "Name of the scene"), CaptureQuery),
+    responses(
+        (status = 200, description = "Scene replaced by the captured one", body = Scene),
+        (status = 201, description = "Scene captured", body = Scene, headers(("Location" = String))),
+        (status = 404, description = "No such room", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn capture_scene(
+    req: HttpRequest,
+    query: web::Query<CaptureQuery>,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+) -> HandleRequestResult<HttpResponse> {
+    let scene_name = path_param(&req, "scene_name");
+    let mut home = home.write().await;
+    let scene = Scene::capture(&home, &registry, query.room.as_deref())?;
+    let mut response = match home.get_scene(scene_name) {
+        Some(_) => HttpResponse::Ok(),
+        None => {
+            let mut response = HttpResponse::Created();
+            response.insert_header((header::LOCATION, scene_location(scene_name)));
+            response
+        }
+    };
+    let response = response.json(&scene);
+    home.put_scene(scene_name, scene);
+    save_home(&storage, home, response).await
+}
+
+/// Sets every device of the scene under one lock, or none of them if any cannot be set.
+#[utoipa::path(
+    post, path = "/scenes/{scene_name}/apply", tag = "scenes",
+    params(("scene_name" = String, Path, description = "Name of the scene")),
+    responses(
+        (status = 200, description = "Which devices changed", body = SceneReport),
+        (status = 404, description = "No such scene, room or device; nothing was changed", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "A device has another type now; nothing was changed", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "A target property is invalid; nothing was changed", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn apply_scene(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
+    let scene_name = path_param(&req, "scene_name");
+    let mut home = home.write().await;
+    let scene = home
+        .get_scene(scene_name)
+        .cloned()
+        .ok_or_else(|| HomeError::SceneNotFound {
+            scene: scene_name.into(),
+        })?;
+    let (report, changes) = scene.apply(&mut home, &registry)?;
+    let response = HttpResponse::Ok().json(report);
+    save_and_publish(&storage, home, &registry, &bus, changes, response).await
+}
+
+/// Energy used by every socket, room and the whole home until now.
+#[utoipa::path(
+    get, path = "/energy", tag = "energy",
+    responses((status = 200, description = "Energy in kWh, and its cost when a tariff is set", body = EnergyUsage))
+)]
+async fn energy_usage(home: web::Data<SmartHome>, clock: web::Data<dyn Clock>) -> HttpResponse {
+    let home = home.read().await;
+    HttpResponse::Ok().json(home.energy().usage(&clock.now()))
+}
+
+#[utoipa::path(
+    get, path = "/energy/tariff", tag = "energy",
+    responses(
+        (status = 200, description = "The tariff energy is priced with", body = Tariff),
+        (status = 404, description = "No tariff is set", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn get_tariff(home: web::Data<SmartHome>) -> HandleRequestResult<HttpResponse> {
+    let home = home.read().await;
+    let tariff = home
+        .energy()
+        .tariff()
+        .ok_or(HandleRequestError::TariffNotFound)?;
+    Ok(HttpResponse::Ok().json(tariff))
+}
+
+/// Sets the tariff (201) or replaces it (204); energy used until now keeps its old price.
+#[utoipa::path(
+    put, path = "/energy/tariff", tag = "energy",
+    request_body(content = Tariff, description = "Flat or time of day prices per kWh"),
+    responses(
+        (status = 201, description = "Tariff set", headers(("Location" = String))),
+        (status = 204, description = "Tariff replaced"),
+        (status = 400, description = "Malformed JSON body", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid tariff", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+async fn put_tariff(
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    clock: web::Data<dyn Clock>,
+) -> HandleRequestResult<HttpResponse> {
+    let tariff: Tariff = json_body(&body, HandleRequestError::InvalidTariff)?;
+    tariff
+        .validate()
+        .map_err(HandleRequestError::InvalidTariff)?;
+    let mut home = home.write().await;
+    let response = match home.set_tariff(Some(tariff), &clock.now()) {
+        Some(_) => HttpResponse::NoContent().finish(),
+        None => HttpResponse::Created()
+            .insert_header((header::LOCATION, format!("{PREFIX}/energy/tariff")))
+            .finish(),
+    };
+    save_home(&storage, home, response).await
+}
+
+/// Stops pricing energy from now on.
+#[utoipa::path(
+    delete, path = "/energy/tariff", tag = "energy",
+    responses((status = 204, description = "Tariff removed"), (status = 404, description = "No tariff is set", body = Problem, content_type = "application/problem+json"))
+)]
+async fn delete_tariff(
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    clock: web::Data<dyn Clock>,
+) -> HandleRequestResult<HttpResponse> {
+    let mut home = home.write().await;
+    if home.energy().tariff().is_none() {
+        return Err(HandleRequestError::TariffNotFound);
+    }
+    home.set_tariff(None, &clock.now());
+    save_home(&storage, home, HttpResponse::NoContent().finish()).await
+}
+
+/// Sensors alerting now, by room and device name.
+#[utoipa::path(
+    get, path = "/alerts", tag = "alerts",
+    responses((status = 200, description = "Open contacts and wet leak detectors", body = Vec<Alert>))
+)]
+async fn list_alerts(
+    home: web::Data<SmartHome>,
+    registry: web::Data<DeviceRegistry>,
+) -> HttpResponse {
+    let home = home.read().await;
+    HttpResponse::Ok().json(sensors::alerts(&home, &registry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{ManualClock, SystemClock};
+    use crate::device_kind::{DeviceKind, FieldSpec};
+    use crate::home::Home;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use jiff::SignedDuration;
+    use std::sync::Arc;
+
+    macro_rules! app {
+        () => {
+            app!(DeviceRegistry::default())
+        };
+        ($registry:expr) => {
+            app!($registry, EventBus::new())
+        };
+        ($registry:expr, $bus:expr) => {
+            app!($registry, $bus, Arc::new(SystemClock))
+        };
+        ($registry:expr, $bus:expr, $clock:expr) => {
+            test::init_service({
+                let bus = $bus;
+                App::new()
+                    .app_data(web::Data::new(SmartHome::new(Home::restore())))
+                    .app_data(web::Data::new(Storage::memory()))
+                    .app_data(web::Data::new($registry))
+                    .app_data(web::Data::new(Telemetry::new(&bus)))
+                    .app_data(web::Data::new(bus))
+                    .app_data(web::Data::from($clock as Arc<dyn Clock>))
+                    .service(web::scope(PREFIX).configure(routes))
+            })
+            .await
+        };
+    }
+
+    macro_rules! call {
+        ($app:expr, $method:ident, $uri:expr) => {
+            test::call_service(&$app, test::TestRequest::$method().uri($uri).to_request()).await
+        };
+    }
+
+    #[actix_web::test]
+    async fn test_rooms() {
+        let app = app!();
+        let resp = call!(app, put, "/api/v1/rooms/new%20room");
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!(
+            "/api/v1/rooms/new%20room",
+            resp.headers().get(header::LOCATION).unwrap()
+        );
+        let resp = call!(app, put, "/api/v1/rooms/new%20room");
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+
+        let resp = call!(app, get, "/api/v1/rooms/R");
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("socket", body["devices"]["S"]["device"]);
+
+        let resp = call!(app, delete, "/api/v1/rooms/new%20room");
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = call!(app, get, "/api/v1/rooms/new%20room");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let resp = call!(app, delete, "/api/v1/rooms/new%20room");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_unsaved_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let bus = EventBus::new();
+        let mut events = bus.subscribe();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(SmartHome::new(Home::restore())))
+                .app_data(web::Data::new(Storage::file(
+                    dir.path().join("missing").join("home.json"),
+                )))
+                .app_data(web::Data::new(DeviceRegistry::default()))
+                .app_data(web::Data::new(bus))
+                .service(web::scope(PREFIX).configure(routes)),
+        )
+        .await;
+        let resp = call!(app, put, "/api/v1/rooms/Hall");
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!("storage-error", problem.code());
+        assert!(problem.detail.contains("applied"));
+        let resp = call!(app, get, "/api/v1/rooms/Hall");
+        assert_eq!(StatusCode::OK, resp.status());
+        assert!(matches!(
+            events.try_recv().unwrap().event,
+            ChangeEvent::RoomAdded { .. }
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_devices() {
+        let app = app!();
+        let uri = "/api/v1/rooms/R/devices/T2?device=thermometer&temperature=21.5";
+        let resp = call!(app, put, uri);
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!(
+            "/api/v1/rooms/R/devices/T2",
+            resp.headers().get(header::LOCATION).unwrap()
+        );
+        let resp = call!(app, put, uri);
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let req = test::TestRequest::put()
+            .uri(uri)
+            .insert_header((header::IF_NONE_MATCH, "*"));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        let resp = call!(
+            app,
+            put,
+            "/api/v1/rooms/X/devices/T2?device=thermometer&temperature=1"
+        );
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/T2?device=thermometer&temperature=30"
+        );
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("30", body["temperature"]);
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/T2?device=socket&state=on"
+        );
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/No?device=socket&state=on"
+        );
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/rooms/R/devices/S")
+            .set_json(serde_json::json!({"device": "socket", "on": true, "current": 2}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("on", body["state"]);
+        assert_eq!("2", body["current"]);
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/rooms/R/devices/S")
+            .set_json(serde_json::json!({"device": "socket", "voltage": "high"}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!("invalid-field", problem.code());
+        assert_eq!(Some("voltage".into()), problem.field);
+
+        let resp = call!(app, delete, "/api/v1/rooms/R/devices/T2");
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/T2");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_light() {
+        let app = app!();
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rooms/R/devices/L")
+            .set_json(serde_json::json!({"device": "light", "on": true, "brightness": 60}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/rooms/R/devices/L")
+            .set_json(serde_json::json!({"device": "light", "color": "#ff8800", "transition": 2}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("60", body["brightness"]);
+        assert_eq!("#ff8800", body["color"]);
+        assert_eq!("2", body["transition"]);
+
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/L?device=light&color_temperature=20000"
+        );
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!(Some("color_temperature".into()), problem.field);
+
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/L?device=light&state=off&color_temperature=2700"
+        );
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("off", body["state"]);
+        assert_eq!("2700", body["color_temperature"]);
+        assert!(!body.contains_key("color"));
+        assert_eq!("0", body["transition"]);
+    }
+
+    #[actix_web::test]
+    async fn test_climate() {
+        let app = app!();
+        let resp = call!(
+            app,
+            put,
+            "/api/v1/rooms/R/devices/C?device=climate&temperature=21&humidity=50&co2=600"
+        );
+        assert_eq!(StatusCode::CREATED, resp.status());
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/C");
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("10.2", body["dew_point"]);
+        assert_eq!("comfortable", body["comfort"]);
+
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/rooms/R/devices/C")
+            .set_json(serde_json::json!({"device": "climate", "humidity": 75}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("humid", body["comfort"]);
+
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/rooms/R/devices/C")
+            .set_json(serde_json::json!({"device": "climate", "co2": 20000}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!(Some("co2".into()), problem.field);
+    }
+
+    #[actix_web::test]
+    async fn test_power_strip() {
+        let clock = Arc::new(ManualClock::new("2024-03-01T07:00[UTC]".parse().unwrap()));
+        let bus = EventBus::with_clock(clock.clone());
+        let app = app!(DeviceRegistry::default(), bus, clock.clone());
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rooms/R/devices/Strip")
+            .set_json(serde_json::json!({
+                "device": "power_strip", "voltage": 230, "max_current": 16, "channels": 4
+            }));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/rooms/R/devices/Strip/channels/1")
+            .set_json(serde_json::json!({"on": true, "current": 10}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            serde_json::json!({"channel": 1, "on": true, "current": 10.0, "power": 2300.0}),
+            body
+        );
+
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/rooms/R/devices/Strip/channels/2")
+            .set_json(serde_json::json!({"on": true, "current": 8}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!(Some("max_current".into()), problem.field);
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/Strip/channels");
+        let body: Vec<serde_json::Value> = test::read_body_json(resp).await;
+        assert_eq!(4, body.len());
+        assert_eq!(serde_json::json!(false), body[1]["on"]);
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/Strip");
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("2300", body["power"]);
+
+        for uri in [
+            "/api/v1/rooms/R/devices/Strip/channels/0",
+            "/api/v1/rooms/R/devices/Strip/channels/5",
+            "/api/v1/rooms/R/devices/Strip/channels/x",
+        ] {
+            let resp = call!(app, get, uri);
+            assert_eq!(StatusCode::NOT_FOUND, resp.status());
+            let problem: Problem = test::read_body_json(resp).await;
+            assert_eq!("channel-not-found", problem.code());
+        }
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/S/channels");
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+
+        clock.advance(SignedDuration::from_hours(1));
+        let resp = call!(app, get, "/api/v1/energy");
+        let usage: EnergyUsage = test::read_body_json(resp).await;
+        let sockets = &usage.rooms["R"].sockets;
+        assert_eq!(2.3, sockets["Strip/channels/1"].usage.kwh);
+        assert_eq!(0., sockets["Strip/channels/2"].usage.kwh);
+    }
+
+    #[actix_web::test]
+    async fn test_alerts() {
+        let clock = Arc::new(ManualClock::new("2024-03-01T07:00[UTC]".parse().unwrap()));
+        let bus = EventBus::with_clock(clock.clone());
+        let mut events = bus.subscribe();
+        let app = app!(DeviceRegistry::default(), bus, clock.clone());
+        let resp = call!(
+            app,
+            put,
+            "/api/v1/rooms/R/devices/Door?device=contact&open=false"
+        );
+        assert_eq!(StatusCode::CREATED, resp.status());
+        let resp = call!(
+            app,
+            put,
+            "/api/v1/rooms/R/devices/Sink?device=leak&wet=false"
+        );
+        assert_eq!(StatusCode::CREATED, resp.status());
+        let resp = call!(app, get, "/api/v1/alerts");
+        let alerts: Vec<Alert> = test::read_body_json(resp).await;
+        assert!(alerts.is_empty());
+
+        clock.advance(SignedDuration::from_mins(5));
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/rooms/R/devices/Sink")
+            .set_json(serde_json::json!({"device": "leak", "wet": true}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("wet", body["state"]);
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/Sink");
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("2024-03-01T07:05:00Z", body["last_triggered"]);
+
+        let resp = call!(app, get, "/api/v1/alerts");
+        let alerts: Vec<Alert> = test::read_body_json(resp).await;
+        assert_eq!(1, alerts.len());
+        assert_eq!("leak", alerts[0].alert);
+        assert_eq!(Some(clock.now().timestamp()), alerts[0].since);
+        let mut raised = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let ChangeEvent::Alert { device, active, .. } = event.event {
+                raised.push((device, active));
+            }
+        }
+        assert_eq!(vec![("Sink".to_string(), true)], raised);
+
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/Door?device=contact&changed=now"
+        );
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_thermostat() {
+        let app = app!();
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rooms/R/devices/H")
+            .set_json(serde_json::json!({
+                "device": "thermostat", "setpoint": 21, "sensor": "R/S", "actuator": "R/S"
+            }));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!(Some("sensor".into()), problem.field);
+
+        call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/T?device=thermometer&temperature=25"
+        );
+        let resp = call!(
+            app,
+            put,
+            "/api/v1/rooms/R/devices/H?device=thermostat&setpoint=21&sensor=R/T&actuator=R/S"
+        );
+        assert_eq!(StatusCode::CREATED, resp.status());
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/H");
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("idle", body["demand"]);
+
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/T?device=thermometer&temperature=18"
+        );
+        assert_eq!(StatusCode::OK, resp.status());
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/H");
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("heating", body["demand"]);
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/S");
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("on", body["state"]);
+
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/H?device=thermostat&actuator=R/T"
+        );
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/H?device=thermostat&mode=off"
+        );
+        assert_eq!(StatusCode::OK, resp.status());
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/S");
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("off", body["state"]);
+    }
+
+    #[actix_web::test]
+    async fn test_thermostat_bindings() {
+        let app = app!();
+        call!(app, put, "/api/v1/rooms/K");
+        let resp = call!(
+            app,
+            put,
+            "/api/v1/rooms/K/devices/H?device=thermostat&setpoint=21&sensor=R/T&actuator=R/S"
+        );
+        assert_eq!(StatusCode::CREATED, resp.status());
+
+        let resp = call!(app, delete, "/api/v1/rooms/R/devices/T");
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!("device-in-use", problem.code());
+        assert_eq!(Some("T".into()), problem.device);
+        let resp = call!(app, delete, "/api/v1/rooms/R");
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rooms/R/devices/S")
+            .set_json(serde_json::json!({"device": "thermometer", "temperature": 20}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rooms/R/devices/T")
+            .set_json(serde_json::json!({
+                "device": "socket", "on": false, "voltage": 220, "current": 0
+            }));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/T");
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("thermometer", body["device"]);
+
+        // Replacing with the same type keeps the binding valid.
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rooms/R/devices/T")
+            .set_json(serde_json::json!({"device": "thermometer", "temperature": 19}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+
+        // Without the thermostat nothing is bound any more.
+        let resp = call!(app, delete, "/api/v1/rooms/K");
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = call!(app, delete, "/api/v1/rooms/R/devices/T");
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_events() {
+        let bus = EventBus::new();
+        let mut events = bus.subscribe();
+        let app = app!(DeviceRegistry::default(), bus);
+        call!(app, put, "/api/v1/rooms/K");
+        call!(app, put, "/api/v1/rooms/K");
+        call!(
+            app,
+            put,
+            "/api/v1/rooms/K/devices/T?device=thermometer&temperature=20"
+        );
+        call!(
+            app,
+            patch,
+            "/api/v1/rooms/K/devices/T?device=thermometer&temperature=22"
+        );
+        call!(
+            app,
+            patch,
+            "/api/v1/rooms/K/devices/T?device=socket&state=on"
+        );
+        call!(app, delete, "/api/v1/rooms/K");
+
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event.event);
+        }
+        let (room, device, device_type) = ("K".to_string(), "T".to_string(), "thermometer");
+        assert_eq!(
+            vec![
+                ChangeEvent::RoomAdded { room: room.clone() },
+                ChangeEvent::DeviceAdded {
+                    room: room.clone(),
+                    device: device.clone(),
+                    device_type: device_type.into(),
+                },
+                ChangeEvent::PropertyChanged {
+                    room: room.clone(),
+                    device: device.clone(),
+                    device_type: device_type.into(),
+                    property: "temperature".into(),
+                    old: Some("20".into()),
+                    new: Some("22".into()),
+                },
+                ChangeEvent::DeviceRemoved {
+                    room: room.clone(),
+                    device,
+                    device_type: device_type.into(),
+                },
+                ChangeEvent::RoomRemoved { room },
+            ],
+            received
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_rules() {
+        let app = app!();
+        let rule = serde_json::json!({
+            "triggers": [{"type": "property", "room": "R", "device": "T", "property": "temperature"}],
+            "conditions": [{"room": "R", "device": "T", "property": "temperature", "op": "ge", "value": 28}],
+            "actions": [{"type": "switch", "room": "R", "device": "S", "on": true}]
+        });
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rules/cool%20down")
+            .set_json(&rule);
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!(
+            "/api/v1/rules/cool%20down",
+            resp.headers().get(header::LOCATION).unwrap()
+        );
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rules/cool%20down")
+            .set_json(&rule);
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = call!(app, get, "/api/v1/rules");
+        let rules: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(true, rules["cool down"]["enabled"]);
+
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/T?device=thermometer&temperature=30"
+        );
+        assert_eq!(StatusCode::OK, resp.status());
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/S");
+        let socket: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("on", socket["state"]);
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rules/broken")
+            .set_json(serde_json::json!({"triggers": [], "actions": []}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!("invalid-rule", problem.code());
+
+        let resp = call!(app, delete, "/api/v1/rules/cool%20down");
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = call!(app, get, "/api/v1/rules/cool%20down");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_schedules() {
+        let clock = Arc::new(ManualClock::new("2024-03-01T06:00[UTC]".parse().unwrap()));
+        let app = app!(DeviceRegistry::default(), EventBus::new(), clock.clone());
+        let req = test::TestRequest::put()
+            .uri("/api/v1/schedules/morning")
+            .set_json(serde_json::json!({
+                "cron": "0 7 * * MON-FRI",
+                "actions": [{"type": "switch", "room": "R", "device": "S", "on": true}]
+            }));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!(
+            "/api/v1/schedules/morning",
+            resp.headers().get(header::LOCATION).unwrap()
+        );
+        let resp = call!(app, get, "/api/v1/schedules/morning");
+        assert_eq!(StatusCode::OK, resp.status());
+        let schedule: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("0 7 * * MON-FRI", schedule["cron"]);
+        assert_eq!("skip", schedule["missed"]);
+        assert_eq!("2024-03-01T07:00:00", schedule["next_run"]);
+        assert_eq!("2024-03-01T06:00:00Z", schedule["checked"]);
+
+        for (body, status) in [
+            (
+                serde_json::json!({"cron": "0 25 * * *", "actions": [{"type": "emit", "message": "m"}]}),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                serde_json::json!({"at": "2024-02-01T00:00", "actions": [{"type": "emit", "message": "m"}]}),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            let req = test::TestRequest::put()
+                .uri("/api/v1/schedules/bad")
+                .set_json(body);
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(status, resp.status());
+            let problem: Problem = test::read_body_json(resp).await;
+            assert_eq!("invalid-schedule", problem.code());
+        }
+
+        let resp = call!(app, get, "/api/v1/schedules");
+        let schedules: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            vec!["morning"],
+            schedules.as_object().unwrap().keys().collect::<Vec<_>>()
+        );
+        let resp = call!(app, delete, "/api/v1/schedules/morning");
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = call!(app, delete, "/api/v1/schedules/morning");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_device_history() {
+        let clock = Arc::new(ManualClock::new("2024-03-01T07:00[UTC]".parse().unwrap()));
+        let app = app!(
+            DeviceRegistry::default(),
+            EventBus::with_clock(clock.clone()),
+            clock.clone()
+        );
+        for temperature in [21, 23, 22] {
+            let uri =
+                format!("/api/v1/rooms/R/devices/T?device=thermometer&temperature={temperature}");
+            let resp = call!(app, patch, &uri);
+            assert_eq!(StatusCode::OK, resp.status());
+            clock.advance(SignedDuration::from_mins(10));
+        }
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/T/history");
+        assert_eq!(StatusCode::OK, resp.status());
+        let history: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            serde_json::json!([
+                {"time": "2024-03-01T07:00:00Z", "value": 21.0},
+                {"time": "2024-03-01T07:10:00Z", "value": 23.0},
+                {"time": "2024-03-01T07:20:00Z", "value": 22.0},
+            ]),
+            history["properties"]["temperature"]
+        );
+
+        let resp = call!(
+            app,
+            get,
+            "/api/v1/rooms/R/devices/T/history?property=temperature&from=2024-03-01T07:05:00Z&bucket=1h"
+        );
+        let history: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            serde_json::json!([
+                {"start": "2024-03-01T07:00:00Z", "count": 2, "min": 22.0, "max": 23.0, "avg": 22.5},
+            ]),
+            history["properties"]["temperature"]
+        );
+
+        for uri in [
+            "/api/v1/rooms/R/devices/T/history?bucket=soon",
+            "/api/v1/rooms/R/devices/T/history?from=2024-03-02T00:00:00Z&to=2024-03-01T00:00:00Z",
+        ] {
+            let resp = call!(app, get, uri);
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+            let problem: Problem = test::read_body_json(resp).await;
+            assert_eq!("invalid-query", problem.code());
+        }
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/X/history");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    #[actix_web::test]
+    async fn test_energy() {
+        let clock = Arc::new(ManualClock::new("2024-03-01T07:00[UTC]".parse().unwrap()));
+        let app = app!(
+            DeviceRegistry::default(),
+            EventBus::with_clock(clock.clone()),
+            clock.clone()
+        );
+        let req = test::TestRequest::put()
+            .uri("/api/v1/energy/tariff")
+            .set_json(serde_json::json!({"type": "flat", "price": 0.25}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/S?device=socket&state=on&current=4.5"
+        );
+        assert_eq!(StatusCode::OK, resp.status());
+        clock.advance(SignedDuration::from_hours(2));
+
+        let resp = call!(app, get, "/api/v1/energy");
+        assert_eq!(StatusCode::OK, resp.status());
+        let usage: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(
+            serde_json::json!({"kwh": 1.98, "cost": 0.495, "power": 990.0}),
+            usage["rooms"]["R"]["sockets"]["S"]
+        );
+        assert_eq!(1.98, usage["rooms"]["R"]["kwh"]);
+        assert_eq!(1.98, usage["kwh"]);
+        assert_eq!("flat", usage["tariff"]["type"]);
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/energy/tariff")
+            .set_json(serde_json::json!({"type": "time_of_day", "rates": []}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!("invalid-tariff", problem.code());
+
+        let resp = call!(app, delete, "/api/v1/energy/tariff");
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = call!(app, get, "/api/v1/energy/tariff");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let resp = call!(app, get, "/api/v1/energy");
+        let usage: serde_json::Value = test::read_body_json(resp).await;
+        assert!(usage["rooms"]["R"]["sockets"]["S"]["cost"].is_null());
+    }
+
+    #[actix_web::test]
+    async fn test_scenes() {
+        let app = app!();
+        let resp = call!(app, post, "/api/v1/scenes/night/capture?room=R");
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!(
+            "/api/v1/scenes/night",
+            resp.headers().get(header::LOCATION).unwrap()
+        );
+        let scene: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(false, scene["devices"]["R"]["S"]["on"]);
+        let resp = call!(app, post, "/api/v1/scenes/night/capture?room=X");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/scenes/night")
+            .set_json(serde_json::json!({"devices": {"R": {"S": {"on": true}, "T": null}}}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let scene: serde_json::Value = test::read_body_json(resp).await;
+        assert!(scene["devices"]["R"]["T"].is_null());
+
+        let resp = call!(app, post, "/api/v1/scenes/night/apply");
+        assert_eq!(StatusCode::OK, resp.status());
+        let report: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("on", report["changed"][0]["properties"]["state"]["new"]);
+        let resp = call!(app, get, "/api/v1/rooms/R/devices/S");
+        let socket: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("on", socket["state"]);
+        let resp = call!(app, post, "/api/v1/scenes/night/apply");
+        let report: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(0, report["changed"].as_array().unwrap().len());
+
+        for (body, status, code) in [
+            (
+                serde_json::json!({"devices": {"R": {"X": {"on": true}}}}),
+                StatusCode::NOT_FOUND,
+                "device-not-found",
+            ),
+            (
+                serde_json::json!({"devices": {"R": {"T": {"temperature": "hot"}}}}),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid-field",
+            ),
+            (
+                serde_json::json!({"rooms": {}}),
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "invalid-scene",
+            ),
+        ] {
+            let req = test::TestRequest::put()
+                .uri("/api/v1/scenes/bad")
+                .set_json(body);
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(status, resp.status());
+            let problem: Problem = test::read_body_json(resp).await;
+            assert_eq!(code, problem.code());
+        }
+
+        let resp = call!(app, delete, "/api/v1/scenes/night");
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = call!(app, post, "/api/v1/scenes/night/apply");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+    }
+
+    struct Fan;
+
+    impl DeviceKind for Fan {
+        fn name(&self) -> &str {
+            "fan"
+        }
+
+        fn fields(&self) -> Vec<FieldSpec> {
+            vec![
+                FieldSpec::number("speed")
+                    .range(Some(0.), Some(3.))
+                    .required(),
+                FieldSpec::boolean("oscillate"),
+            ]
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_custom_kind() {
+        let mut registry = DeviceRegistry::default();
+        registry.register(Fan).unwrap();
+        let app = app!(registry);
+
+        let req = test::TestRequest::put()
+            .uri("/api/v1/rooms/R/devices/F")
+            .set_json(serde_json::json!({"device": "fan", "speed": 2}));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/F?device=fan&oscillate=on"
+        );
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: HashMap<String, String> = test::read_body_json(resp).await;
+        assert_eq!("fan", body["device"]);
+        assert_eq!("2", body["speed"]);
+        assert_eq!("true", body["oscillate"]);
+
+        let resp = call!(app, patch, "/api/v1/rooms/R/devices/F?device=fan&speed=5");
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!(Some("speed".into()), problem.field);
+        let resp = call!(app, put, "/api/v1/rooms/R/devices/G?device=fan");
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let resp = call!(app, patch, "/api/v1/rooms/R/devices/S?device=fan&speed=1");
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        let resp = call!(
+            app,
+            patch,
+            "/api/v1/rooms/R/devices/F?device=socket&state=on"
+        );
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        let req = test::TestRequest::patch()
+            .uri("/api/v1/rooms/R/devices/F")
+            .set_json(
+                serde_json::json!({"device": "generic", "kind": "fan", "properties": {"speed": 9}}),
+            );
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!(Some("speed".into()), problem.field);
+
+        let resp = call!(app, get, "/api/v1/rooms/R");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!("fan", body["devices"]["F"]["device"]);
+        assert_eq!("2", body["devices"]["F"]["speed"]);
+    }
+
+    #[actix_web::test]
+    async fn test_device_types() {
+        let mut registry = DeviceRegistry::default();
+        registry.register(Fan).unwrap();
+        let app = app!(registry);
+
+        let resp = call!(app, get, "/api/v1/device-types");
+        assert_eq!(StatusCode::OK, resp.status());
+        let schemas: Vec<KindSchema> = test::read_body_json(resp).await;
+        let names: Vec<_> = schemas.iter().map(|schema| schema.name.as_str()).collect();
+        assert_eq!(
+            vec![
+                "climate",
+                "contact",
+                "fan",
+                "generic",
+                "leak",
+                "light",
+                "motion",
+                "power_strip",
+                "socket",
+                "thermometer",
+                "thermostat"
+            ],
+            names
+        );
+
+        let resp = call!(app, get, "/api/v1/device-types/socket");
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let fields = body["fields"].as_array().unwrap();
+        let field = |name: &str| fields.iter().find(|f| f["name"] == name).unwrap();
+        assert_eq!("bool", field("on")["type"]);
+        assert_eq!(true, field("on")["required"]);
+        assert_eq!("state", field("on")["aliases"][0]);
+        assert!(field("on")["accepts"]
+            .as_array()
+            .unwrap()
+            .contains(&"выкл".into()));
+        assert_eq!("V", field("voltage")["unit"]);
+        assert_eq!(0., field("voltage")["min"]);
+        assert_eq!(true, field("power")["read_only"]);
+
+        let resp = call!(app, get, "/api/v1/device-types/kettle");
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let problem: Problem = test::read_body_json(resp).await;
+        assert_eq!("device-type-not-found", problem.code());
+    }
+}
@@ -0,0 +1,448 @@
+//! `home-ctl`: manage a running http_home server from the shell.
+//! Built with the `client` feature: `cargo install --path . --features client`.
+//!
+//! ```text
+//! home-ctl rooms add Kitchen
+//! home-ctl devices add Kitchen Kettle socket state=off voltage=230 current=0
+//! home-ctl -o json devices show Kitchen Kettle
+//! home-ctl devices set Kitchen Kettle state=on
+//! home-ctl watch Kitchen
+//! ```
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use http_home::client::{ClientError, HomeClient};
+use http_home::config::ClientArgs;
+use http_home::device_kind::DeviceRegistry;
+use http_home::error::HomeError;
+use http_home::payload::DevicePayload;
+use http_home::smart_device::{Device, DeviceDict};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::process::ExitCode;
+use std::time::Duration;
+
+const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  the server failed or answered unexpectedly
+  2  invalid command line
+  3  room, device or device type not found
+  4  room or device already exists, or device of another type
+  5  invalid device field
+  6  cannot connect to the server
+  7  the server is read-only";
+
+#[derive(Debug, Parser)]
+#[command(name = "home-ctl", version, about = "Manage a running http_home server.", after_help = EXIT_CODES)]
+struct Cli {
+    #[command(flatten)]
+    server: ClientArgs,
+    /// Output format.
+    #[arg(short, long, value_enum, default_value_t = Output::Table, global = true)]
+    output: Output,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List, add or remove rooms.
+    #[command(subcommand)]
+    Rooms(RoomsCommand),
+    /// List, add, remove, show or change devices.
+    #[command(subcommand)]
+    Devices(DevicesCommand),
+    /// Print the report about every device.
+    Report,
+    /// Print changes of the devices as they happen.
+    Watch(WatchArgs),
+}
+
+#[derive(Debug, Subcommand)]
+enum RoomsCommand {
+    List,
+    Add { room: String },
+    Rm { room: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum DevicesCommand {
+    /// List the devices of a room with their fields.
+    List {
+        room: String,
+    },
+    /// Add a device, e.g. `devices add R S socket state=on voltage=220 current=0`.
+    Add {
+        room: String,
+        device: String,
+        /// Device type, see `GET /api/v1/device-types`.
+        device_type: String,
+        /// Fields as `name=value`.
+        fields: Vec<String>,
+    },
+    Rm {
+        room: String,
+        device: String,
+    },
+    Show {
+        room: String,
+        device: String,
+    },
+    /// Change fields of a device, e.g. `devices set R S state=off`.
+    Set {
+        room: String,
+        device: String,
+        /// Fields as `name=value`.
+        #[arg(required = true)]
+        fields: Vec<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+struct WatchArgs {
+    /// Only watch this room.
+    room: Option<String>,
+    /// Seconds between polls.
+    #[arg(short, long, default_value_t = 2.)]
+    interval: f64,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let client = HomeClient::new(&cli.server.url(""));
+    match run(&client, cli.command, cli.output).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("home-ctl: {e}");
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+async fn run(client: &HomeClient, command: Command, output: Output) -> Result<(), ClientError> {
+    match command {
+        Command::Rooms(RoomsCommand::List) => {
+            let rooms = client.rooms().await?;
+            match output {
+                Output::Table => rooms.iter().for_each(|room| println!("{room}")),
+                Output::Json => println!("{}", json!(rooms)),
+            }
+        }
+        Command::Rooms(RoomsCommand::Add { room }) => client.add_room(&room).await?,
+        Command::Rooms(RoomsCommand::Rm { room }) => client.remove_room(&room).await?,
+        Command::Devices(DevicesCommand::List { room }) => {
+            let dicts: BTreeMap<_, _> = client
+                .room(&room)
+                .await?
+                .iter()
+                .map(|(name, device)| (name.clone(), device.device_dict()))
+                .collect();
+            match output {
+                Output::Table => print_table(&dicts),
+                Output::Json => println!("{}", json!(dicts)),
+            }
+        }
+        Command::Devices(DevicesCommand::Add {
+            room,
+            device,
+            device_type,
+            fields,
+        }) => {
+            let registry = client.registry().await?;
+            let payload = payload(&registry, &device_type, &fields)?;
+            client.create_device(&room, &device, &payload).await?;
+        }
+        Command::Devices(DevicesCommand::Rm { room, device }) => {
+            client.remove_device(&room, &device).await?
+        }
+        Command::Devices(DevicesCommand::Show { room, device }) => {
+            let device = client.get_device(&room, &device).await?;
+            print_device(&device, output);
+        }
+        Command::Devices(DevicesCommand::Set {
+            room,
+            device,
+            fields,
+        }) => {
+            let current = client.get_device(&room, &device).await?;
+            let registry = client.registry().await?;
+            let payload = payload(&registry, device_type(&registry, &current), &fields)?;
+            let updated = client.update(&room, &device, &payload).await?;
+            print_device(&updated, output);
+        }
+        Command::Report => {
+            let report = client.report().await?;
+            match output {
+                Output::Table => println!("{report}"),
+                Output::Json => println!("{}", json!({ "report": report })),
+            }
+        }
+        Command::Watch(args) => watch(client, args, output).await?,
+    }
+    Ok(())
+}
+
+/// Builds the payload against the field descriptions served by the server,
+/// so mistakes are reported before anything is sent.
+fn payload(
+    registry: &DeviceRegistry,
+    device_type: &str,
+    fields: &[String],
+) -> Result<DevicePayload, ClientError> {
+    let mut data: HashMap<&str, &str> = HashMap::from([("device", device_type)]);
+    for field in fields {
+        let (name, value) = field
+            .split_once('=')
+            .ok_or_else(|| HomeError::invalid_value(field, "expected name=value"))?;
+        data.insert(name, value);
+    }
+    Ok(DevicePayload::from_query(&data, registry)?)
+}
+
+/// The `device` value to send for an update of `device`: generic devices
+/// of a kind the server does not know are updated as plain generic ones.
+fn device_type<'a>(registry: &DeviceRegistry, device: &'a Device) -> &'a str {
+    match device {
+        Device::Generic(generic) if registry.get(generic.get_kind()).is_some() => {
+            generic.get_kind()
+        }
+        other => other.type_name(),
+    }
+}
+
+fn print_device(device: &Device, output: Output) {
+    let dict: BTreeMap<_, _> = device.device_dict().into_iter().collect();
+    match output {
+        Output::Table => {
+            let width = dict.keys().map(|k| k.chars().count()).max().unwrap_or(0);
+            for (name, value) in dict {
+                println!("{name:width$}  {value}");
+            }
+        }
+        Output::Json => println!("{}", json!(dict)),
+    }
+}
+
+fn print_table(dicts: &BTreeMap<String, HashMap<String, String>>) {
+    let width = dicts
+        .keys()
+        .map(|k| k.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!("{:width$}  {:11}  FIELDS", "NAME", "DEVICE");
+    for (name, dict) in dicts {
+        let fields: BTreeMap<_, _> = dict.iter().filter(|(k, _)| *k != "device").collect();
+        let fields: Vec<_> = fields.iter().map(|(k, v)| format!("{k}={v}")).collect();
+        let device = dict.get("device").map(String::as_str).unwrap_or_default();
+        println!("{name:width$}  {device:11}  {}", fields.join(" "));
+    }
+}
+
+type Snapshot = BTreeMap<(String, String), HashMap<String, String>>;
+
+async fn watch(client: &HomeClient, args: WatchArgs, output: Output) -> Result<(), ClientError> {
+    let interval = Duration::from_secs_f64(args.interval.max(0.1));
+    let mut previous: Option<Snapshot> = None;
+    loop {
+        let current = snapshot(client, args.room.as_deref()).await?;
+        if let Some(previous) = &previous {
+            for change in changes(previous, &current) {
+                match output {
+                    Output::Table => println!("{}", change.text()),
+                    Output::Json => println!("{}", change.json()),
+                }
+            }
+        }
+        previous = Some(current);
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn snapshot(client: &HomeClient, room: Option<&str>) -> Result<Snapshot, ClientError> {
+    let rooms = match room {
+        Some(room) => vec![room.to_string()],
+        None => client.rooms().await?,
+    };
+    let mut snapshot = Snapshot::new();
+    for room in rooms {
+        let devices = match client.room(&room).await {
+            Ok(devices) => devices,
+            // The room was removed between the two requests.
+            Err(e) if e.code() == Some("room-not-found") => continue,
+            Err(e) => return Err(e),
+        };
+        for (name, device) in devices {
+            snapshot.insert((room.clone(), name), device.device_dict());
+        }
+    }
+    Ok(snapshot)
+}
+
+#[derive(Debug, PartialEq)]
+enum Change<'a> {
+    Added(&'a (String, String)),
+    Removed(&'a (String, String)),
+    Changed {
+        path: &'a (String, String),
+        field: &'a str,
+        old: Option<&'a str>,
+        new: Option<&'a str>,
+    },
+}
+
+impl Change<'_> {
+    fn text(&self) -> String {
+        match self {
+            Change::Added((room, device)) => format!("+ {room}/{device}"),
+            Change::Removed((room, device)) => format!("- {room}/{device}"),
+            Change::Changed {
+                path: (room, device),
+                field,
+                old,
+                new,
+            } => format!(
+                "~ {room}/{device} {field}: {} -> {}",
+                old.unwrap_or("-"),
+                new.unwrap_or("-")
+            ),
+        }
+    }
+
+    fn json(&self) -> serde_json::Value {
+        match self {
+            Change::Added((room, device)) => {
+                json!({"event": "added", "room": room, "device": device})
+            }
+            Change::Removed((room, device)) => {
+                json!({"event": "removed", "room": room, "device": device})
+            }
+            Change::Changed {
+                path: (room, device),
+                field,
+                old,
+                new,
+            } => json!({
+                "event": "changed", "room": room, "device": device,
+                "field": field, "old": old, "new": new,
+            }),
+        }
+    }
+}
+
+fn changes<'a>(previous: &'a Snapshot, current: &'a Snapshot) -> Vec<Change<'a>> {
+    let mut changes = Vec::new();
+    for (path, old) in previous {
+        match current.get(path) {
+            None => changes.push(Change::Removed(path)),
+            Some(new) => {
+                let fields: std::collections::BTreeSet<_> = old.keys().chain(new.keys()).collect();
+                for field in fields {
+                    let (old, new) = (old.get(field), new.get(field));
+                    if old != new {
+                        changes.push(Change::Changed {
+                            path,
+                            field,
+                            old: old.map(String::as_str),
+                            new: new.map(String::as_str),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    for path in current.keys().filter(|path| !previous.contains_key(*path)) {
+        changes.push(Change::Added(path));
+    }
+    changes
+}
+
+fn exit_code(error: &ClientError) -> u8 {
+    match error {
+        ClientError::Http(e) if e.is_connect() || e.is_timeout() => 6,
+        ClientError::Problem(problem) => match problem.status {
+            404 => 3,
+            409 => 4,
+            400 | 422 => 5,
+            403 => 7,
+            _ => 1,
+        },
+        ClientError::Device(_) => 5,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(room: &str, device: &str) -> (String, String) {
+        (room.into(), device.into())
+    }
+
+    #[test]
+    fn test_cli() {
+        let cli = Cli::try_parse_from([
+            "home-ctl", "-o", "json", "devices", "add", "R", "S", "socket", "state=on",
+        ])
+        .unwrap();
+        assert_eq!(Output::Json, cli.output);
+        assert!(matches!(
+            cli.command,
+            Command::Devices(DevicesCommand::Add { fields, .. }) if fields == ["state=on"]
+        ));
+        assert!(Cli::try_parse_from(["home-ctl", "devices", "set", "R", "S"]).is_err());
+    }
+
+    #[test]
+    fn test_changes() {
+        let dict = |temperature: &str| {
+            HashMap::from([
+                ("device".to_string(), "thermometer".to_string()),
+                ("temperature".to_string(), temperature.to_string()),
+            ])
+        };
+        let previous = Snapshot::from([(path("R", "T"), dict("20")), (path("R", "X"), dict("1"))]);
+        let current = Snapshot::from([(path("R", "T"), dict("21")), (path("K", "T"), dict("1"))]);
+        let (t, x, k) = (path("R", "T"), path("R", "X"), path("K", "T"));
+        assert_eq!(
+            vec![
+                Change::Changed {
+                    path: &t,
+                    field: "temperature",
+                    old: Some("20"),
+                    new: Some("21"),
+                },
+                Change::Removed(&x),
+                Change::Added(&k),
+            ],
+            changes(&previous, &current)
+        );
+        assert_eq!(
+            "~ R/T temperature: 20 -> 21",
+            changes(&previous, &current)[0].text()
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let problem = |status| {
+            ClientError::Problem(Box::new(http_home::problem::Problem::new(
+                actix_web::http::StatusCode::from_u16(status).unwrap(),
+                "x",
+                "x",
+                "x",
+            )))
+        };
+        assert_eq!(3, exit_code(&problem(404)));
+        assert_eq!(4, exit_code(&problem(409)));
+        assert_eq!(5, exit_code(&problem(422)));
+        assert_eq!(1, exit_code(&problem(500)));
+    }
+}
@@ -0,0 +1,487 @@
+//! Typed async client for the `/api/v1` routes, enabled by the `client` feature.
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), http_home::client::ClientError> {
+//! use http_home::client::HomeClient;
+//! use http_home::smart_device::Thermometer;
+//!
+//! let client = HomeClient::new("http://127.0.0.1:4083");
+//! client.add_room("Kitchen").await?;
+//! client
+//!     .add_device("Kitchen", "T", Thermometer::new(21.5).into())
+//!     .await?;
+//! println!("{:?}", client.get_device("Kitchen", "T").await?);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::api_v1::{self, device_location, room_location};
+use crate::device_kind::{DeviceKind, DeviceRegistry, FieldSpec, KindSchema};
+use crate::error::HomeError;
+use crate::payload::DevicePayload;
+use crate::problem::{self, Problem};
+use crate::smart_device::{
+    ClimateSensor, Contact, Device, DevicePath, Generic, Leak, Light, Motion, PowerStrip,
+    PropertyValue, Socket, Thermometer, Thermostat,
+};
+use reqwest::header::{CONTENT_TYPE, IF_NONE_MATCH};
+use reqwest::{RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The server refused the request and explained why.
+    #[error("{}", .0.detail)]
+    Problem(Box<Problem>),
+    #[error("Unexpected response {status}: {body}")]
+    Unexpected { status: u16, body: String },
+    /// Device fields were invalid before sending, or the server answered
+    /// with a device the client cannot represent.
+    #[error("Invalid device: {0}")]
+    Device(#[from] HomeError),
+}
+
+impl ClientError {
+    /// The problem code sent by the server, e.g. `room-not-found`.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Self::Problem(problem) => Some(problem.code()),
+            _ => None,
+        }
+    }
+}
+
+pub type ClientResult<T> = Result<T, ClientError>;
+
+#[derive(Debug, Clone)]
+pub struct HomeClient {
+    http: reqwest::Client,
+    base_url: String,
+}
+
+impl HomeClient {
+    /// `base_url` is the server root, e.g. `http://127.0.0.1:4083`.
+    pub fn new(base_url: &str) -> Self {
+        Self::with_client(reqwest::Client::new(), base_url)
+    }
+
+    pub fn with_client(http: reqwest::Client, base_url: &str) -> Self {
+        Self {
+            http,
+            base_url: base_url.trim_end_matches('/').into(),
+        }
+    }
+
+    pub async fn rooms(&self) -> ClientResult<Vec<String>> {
+        let url = self.url(&format!("{}/rooms", api_v1::PREFIX));
+        json(self.http.get(url)).await
+    }
+
+    /// Creates the room; an existing room is left as it is.
+    pub async fn add_room(&self, room_name: &str) -> ClientResult<()> {
+        let url = self.url(&room_location(room_name));
+        empty(self.http.put(url)).await
+    }
+
+    pub async fn remove_room(&self, room_name: &str) -> ClientResult<()> {
+        let url = self.url(&room_location(room_name));
+        empty(self.http.delete(url)).await
+    }
+
+    /// Every device of the room by its name.
+    pub async fn room(&self, room_name: &str) -> ClientResult<BTreeMap<String, Device>> {
+        #[derive(Deserialize)]
+        struct RoomView {
+            devices: HashMap<String, HashMap<String, String>>,
+        }
+        let url = self.url(&room_location(room_name));
+        let room: RoomView = json(self.http.get(url)).await?;
+        room.devices
+            .into_iter()
+            .map(|(name, dict)| Ok((name, device_from_dict(dict)?)))
+            .collect()
+    }
+
+    pub async fn devices(&self, room_name: &str) -> ClientResult<Vec<String>> {
+        let url = self.url(&format!("{}/devices", room_location(room_name)));
+        json(self.http.get(url)).await
+    }
+
+    /// Creates the device, failing with `device-exists` if the name is taken.
+    pub async fn add_device(
+        &self,
+        room_name: &str,
+        device_name: &str,
+        device: Device,
+    ) -> ClientResult<()> {
+        let url = self.url(&device_location(room_name, device_name));
+        empty(self.http.put(url).header(IF_NONE_MATCH, "*").json(&device)).await
+    }
+
+    /// Like [`HomeClient::add_device`], but from fields, so kinds registered
+    /// only on the server can be created too.
+    pub async fn create_device(
+        &self,
+        room_name: &str,
+        device_name: &str,
+        payload: &DevicePayload,
+    ) -> ClientResult<()> {
+        let url = self.url(&device_location(room_name, device_name));
+        empty(
+            self.http
+                .put(url)
+                .header(IF_NONE_MATCH, "*")
+                .json(&payload_json(payload)),
+        )
+        .await
+    }
+
+    /// Creates the device or replaces the one with the same name.
+    pub async fn replace_device(
+        &self,
+        room_name: &str,
+        device_name: &str,
+        device: Device,
+    ) -> ClientResult<()> {
+        let url = self.url(&device_location(room_name, device_name));
+        empty(self.http.put(url).json(&device)).await
+    }
+
+    /// Changes the fields present in `payload` and returns the updated device.
+    pub async fn update(
+        &self,
+        room_name: &str,
+        device_name: &str,
+        payload: &DevicePayload,
+    ) -> ClientResult<Device> {
+        let url = self.url(&device_location(room_name, device_name));
+        let dict = json(self.http.patch(url).json(&payload_json(payload))).await?;
+        Ok(device_from_dict(dict)?)
+    }
+
+    pub async fn get_device(&self, room_name: &str, device_name: &str) -> ClientResult<Device> {
+        let url = self.url(&device_location(room_name, device_name));
+        Ok(device_from_dict(json(self.http.get(url)).await?)?)
+    }
+
+    pub async fn remove_device(&self, room_name: &str, device_name: &str) -> ClientResult<()> {
+        let url = self.url(&device_location(room_name, device_name));
+        empty(self.http.delete(url)).await
+    }
+
+    pub async fn device_types(&self) -> ClientResult<Vec<KindSchema>> {
+        let url = self.url(&format!("{}/device-types", api_v1::PREFIX));
+        json(self.http.get(url)).await
+    }
+
+    /// A registry of the kinds the server supports, for building payloads
+    /// with [`DevicePayload::from_query`] that the server will accept.
+    pub async fn registry(&self) -> ClientResult<DeviceRegistry> {
+        let mut registry = DeviceRegistry::default();
+        for schema in self.device_types().await? {
+            // Built-in kinds are known already, and the server never lists a kind twice.
+            let _ = registry.register(RemoteKind(schema));
+        }
+        Ok(registry)
+    }
+
+    pub async fn report(&self) -> ClientResult<String> {
+        Ok(checked(self.http.get(self.url("/report")))
+            .await?
+            .text()
+            .await?)
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+}
+
+/// A kind known only from the server's description of it.
+struct RemoteKind(KindSchema);
+
+impl DeviceKind for RemoteKind {
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        self.0.fields.clone()
+    }
+}
+
+/// Sends the request and turns any error status into a [`ClientError`].
+async fn checked(request: RequestBuilder) -> ClientResult<Response> {
+    let resp = request.send().await?;
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let is_problem = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|value| value == problem::CONTENT_TYPE);
+    let body = resp.text().await?;
+    match serde_json::from_str(&body) {
+        Ok(problem) if is_problem => Err(ClientError::Problem(Box::new(problem))),
+        _ => Err(ClientError::Unexpected {
+            status: status.as_u16(),
+            body,
+        }),
+    }
+}
+
+async fn json<T: DeserializeOwned>(request: RequestBuilder) -> ClientResult<T> {
+    Ok(checked(request).await?.json().await?)
+}
+
+async fn empty(request: RequestBuilder) -> ClientResult<()> {
+    checked(request).await.map(drop)
+}
+
+/// Registered kinds are not serializable by themselves; they are sent as flat properties.
+fn payload_json(payload: &DevicePayload) -> Value {
+    match payload {
+        DevicePayload::Custom(custom) => {
+            let mut object = serde_json::Map::new();
+            object.insert("device".into(), custom.kind.name().into());
+            for (name, value) in &custom.properties {
+                object.insert(
+                    name.clone(),
+                    serde_json::to_value(value).unwrap_or_default(),
+                );
+            }
+            Value::Object(object)
+        }
+        _ => serde_json::to_value(payload).unwrap_or_default(),
+    }
+}
+
+/// Rebuilds a device from the string dict every route answers with.
+/// Devices of registered kinds come back as [`Generic`] devices of that kind,
+/// without a schema.
+fn device_from_dict(mut dict: HashMap<String, String>) -> Result<Device, HomeError> {
+    let device = dict
+        .remove("device")
+        .ok_or_else(|| HomeError::invalid_value("device", "missing field"))?;
+    match device.as_str() {
+        "socket" => Ok(Socket::new(
+            dict_number(&dict, "voltage")?,
+            dict_number(&dict, "current")?,
+            dict.get("state").is_some_and(|state| state == "on"),
+        )
+        .into()),
+        "power_strip" => {
+            let count = dict_number(&dict, "channels")? as usize;
+            let mut strip = PowerStrip::new(
+                dict_number(&dict, "voltage")?,
+                dict_number(&dict, "max_current")?,
+                count,
+            )?;
+            for number in 1..=count {
+                let current = PowerStrip::channel_current_field(number);
+                strip.set_current(number, dict_number(&dict, &current)?)?;
+                let on = dict
+                    .get(&PowerStrip::channel_field(number))
+                    .is_some_and(|state| state == "on");
+                strip.switch(number, on)?;
+            }
+            Ok(strip.into())
+        }
+        "thermometer" => Ok(Thermometer::new(dict_number(&dict, "temperature")?).into()),
+        "climate" => {
+            let mut sensor = ClimateSensor::new(
+                dict_number(&dict, "temperature")?,
+                dict_number(&dict, "humidity")?,
+                dict_number(&dict, "co2")?,
+            )?;
+            if dict.contains_key("voc") {
+                sensor.set_voc(dict_number(&dict, "voc")?)?;
+            }
+            Ok(sensor.into())
+        }
+        "contact" => Ok(Contact::new(dict_state(&dict, "open")?).into()),
+        "motion" => {
+            let mut motion = Motion::new(dict_state(&dict, "detected")?);
+            if dict.contains_key("reset_after") {
+                motion.set_reset_after(dict_number(&dict, "reset_after")?)?;
+            }
+            Ok(motion.into())
+        }
+        "leak" => Ok(Leak::new(dict_state(&dict, "wet")?).into()),
+        "light" => {
+            let mut light = Light::new(
+                dict.get("state").is_some_and(|state| state == "on"),
+                dict_number(&dict, "brightness")?,
+            )?;
+            if dict.contains_key("color_temperature") {
+                light.set_color_temperature(dict_number(&dict, "color_temperature")?)?;
+            }
+            if let Some(color) = dict.get("color") {
+                light.set_color(
+                    color
+                        .parse()
+                        .map_err(|e: String| HomeError::invalid_value("color", &e))?,
+                );
+            }
+            light.set_transition(dict_number(&dict, "transition")?)?;
+            Ok(light.into())
+        }
+        "thermostat" => {
+            let mut thermostat = Thermostat::new(
+                dict_number(&dict, "setpoint")?,
+                dict_path(&dict, "sensor")?,
+                dict_path(&dict, "actuator")?,
+            )?;
+            thermostat.set_hysteresis(dict_number(&dict, "hysteresis")?)?;
+            if let Some(mode) = dict.get("mode") {
+                thermostat.set_mode(
+                    mode.parse()
+                        .map_err(|e: String| HomeError::invalid_value("mode", &e))?,
+                );
+            }
+            Ok(thermostat.into())
+        }
+        "unknown" => Ok(Device::Unknown),
+        _ => {
+            let kind = match dict.remove("kind") {
+                Some(kind) if device == "generic" => kind,
+                _ => device,
+            };
+            let properties: BTreeMap<_, _> = dict
+                .iter()
+                .map(|(name, value)| (name.clone(), PropertyValue::infer(value)))
+                .collect();
+            Ok(Generic::new(&kind, properties, None)?.into())
+        }
+    }
+}
+
+fn dict_number(dict: &HashMap<String, String>, field: &str) -> Result<f64, HomeError> {
+    dict.get(field)
+        .ok_or_else(|| HomeError::invalid_value(field, "missing field"))?
+        .parse()
+        .map_err(|_| HomeError::invalid_value(field, "expected a number"))
+}
+
+/// Whether the `state` of a binary sensor is `active`, e.g. `open`.
+fn dict_state(dict: &HashMap<String, String>, active: &str) -> Result<bool, HomeError> {
+    dict.get("state")
+        .map(|state| state == active)
+        .ok_or_else(|| HomeError::invalid_value("state", "missing field"))
+}
+
+fn dict_path(dict: &HashMap<String, String>, field: &str) -> Result<DevicePath, HomeError> {
+    dict.get(field)
+        .ok_or_else(|| HomeError::invalid_value(field, "missing field"))?
+        .parse()
+        .map_err(|e: String| HomeError::invalid_value(field, &e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_kind::DeviceRegistry;
+    use crate::home::Home;
+    use crate::payload::SocketPayload;
+    use crate::storage::Storage;
+    use crate::{run, ServerOptions};
+    use std::net::TcpListener;
+
+    fn spawn_server() -> HomeClient {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = run(
+            listener,
+            Home::restore(),
+            Storage::memory(),
+            ServerOptions::default(),
+        )
+        .unwrap();
+        tokio::spawn(server);
+        HomeClient::new(&format!("http://127.0.0.1:{port}/"))
+    }
+
+    #[actix_web::test]
+    async fn test_client() {
+        let client = spawn_server();
+        assert_eq!(vec!["R"], client.rooms().await.unwrap());
+        client.add_room("new room").await.unwrap();
+        client
+            .add_device("new room", "T", Thermometer::new(36.6).into())
+            .await
+            .unwrap();
+        let err = client
+            .add_device("new room", "T", Thermometer::new(1.).into())
+            .await
+            .unwrap_err();
+        assert_eq!(Some("device-exists"), err.code());
+        assert_eq!(
+            Device::Thermometer(Thermometer::new(36.6)),
+            client.get_device("new room", "T").await.unwrap()
+        );
+        assert_eq!(vec!["T"], client.devices("new room").await.unwrap());
+
+        let payload = DevicePayload::Socket(SocketPayload {
+            on: Some(true),
+            ..Default::default()
+        });
+        assert_eq!(
+            Device::Socket(Socket::new(220., 0., true)),
+            client.update("R", "S", &payload).await.unwrap()
+        );
+        let err = client.update("R", "T", &payload).await.unwrap_err();
+        assert_eq!(Some("device-type-mismatch"), err.code());
+        assert!(client.report().await.unwrap().contains("new room"));
+        assert_eq!(
+            DeviceRegistry::default().schemas(),
+            client.device_types().await.unwrap()
+        );
+
+        let room = client.room("R").await.unwrap();
+        assert_eq!(Some(&Device::new_thermometer()), room.get("T"));
+        let registry = client.registry().await.unwrap();
+        let data = HashMap::from([("device", "socket"), ("state", "off"), ("voltage", "-1")]);
+        assert!(DevicePayload::from_query(&data, &registry).is_err());
+        let data = HashMap::from([
+            ("device", "socket"),
+            ("state", "off"),
+            ("voltage", "230"),
+            ("current", "0"),
+        ]);
+        let payload = DevicePayload::from_query(&data, &registry).unwrap();
+        client.create_device("R", "S2", &payload).await.unwrap();
+        assert_eq!(
+            Some("device-exists"),
+            client
+                .create_device("R", "S2", &payload)
+                .await
+                .unwrap_err()
+                .code()
+        );
+
+        client.remove_device("new room", "T").await.unwrap();
+        client.remove_room("new room").await.unwrap();
+        let err = client.get_device("new room", "T").await.unwrap_err();
+        assert_eq!(Some("device-not-found"), err.code());
+    }
+
+    #[test]
+    fn test_device_from_dict() {
+        let dict = HashMap::from([
+            ("device".to_string(), "dimmer".to_string()),
+            ("level".to_string(), "40".to_string()),
+        ]);
+        let Device::Generic(generic) = device_from_dict(dict).unwrap() else {
+            panic!("expected a generic device");
+        };
+        assert_eq!("dimmer", generic.get_kind());
+        assert_eq!(Some(&PropertyValue::Number(40.)), generic.get("level"));
+    }
+}
@@ -0,0 +1,71 @@
+//! Where the scheduler gets the current time from.
+//!
+//! The server runs on [`SystemClock`]; tests inject a [`ManualClock`] and move it
+//! forward by hand to simulate time passing.
+
+use jiff::{SignedDuration, Zoned};
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// The current time in the time zone schedules are written in.
+    fn now(&self) -> Zoned;
+}
+
+/// The system clock in the system time zone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Zoned {
+        Zoned::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<Zoned>,
+}
+
+impl ManualClock {
+    pub fn new(now: Zoned) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn set(&self, now: Zoned) {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner) = now;
+    }
+
+    pub fn advance(&self, duration: SignedDuration) {
+        let mut now = self.now.lock().unwrap_or_else(PoisonError::into_inner);
+        *now = now
+            .checked_add(duration)
+            .expect("manual clock moved out of range");
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Zoned {
+        self.now
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new("2024-03-01T07:00[UTC]".parse().unwrap());
+        clock.advance(SignedDuration::from_mins(90));
+        assert_eq!("2024-03-01T08:30:00+00:00[UTC]", clock.now().to_string());
+        clock.set("2024-03-02T00:00[UTC]".parse().unwrap());
+        assert_eq!(2, clock.now().day());
+    }
+}
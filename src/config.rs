@@ -0,0 +1,300 @@
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub const DEFAULT_PORT: u16 = 4083;
+pub const DEFAULT_STATE_FILE: &str = "home.json";
+pub const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:4083";
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Cannot read config file '{path}': {source}.")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("Invalid config file '{path}': {source}")]
+    Parse {
+        path: PathBuf,
+        source: Box<toml::de::Error>,
+    },
+    #[error("Invalid value for '{key}': {reason}.")]
+    Invalid { key: &'static str, reason: String },
+}
+
+type ConfigResult<T> = Result<T, ConfigError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+            LogLevel::Trace => log::LevelFilter::Trace,
+        }
+    }
+}
+
+/// Command line of the `http_home` server.
+/// Every option may also be given in the config file; the command line wins.
+#[derive(Debug, Default, Parser)]
+#[command(name = "http_home", version, about = "Smart home HTTP server")]
+pub struct Cli {
+    /// TOML config file with the same keys as the options below
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on [default: 127.0.0.1]
+    #[arg(short, long, value_name = "ADDR")]
+    pub bind: Option<IpAddr>,
+    /// Port to listen on [default: 4083]
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    /// JSON file the home is loaded from and saved to [default: home.json]
+    #[arg(short, long, value_name = "FILE")]
+    pub state_file: Option<PathBuf>,
+    /// Log verbosity [default: info]
+    #[arg(short, long, value_enum)]
+    pub log_level: Option<LogLevel>,
+    /// Refuse every request that would change the home; `--read-only=false`
+    /// turns it off again when the config file turns it on [default: false]
+    #[arg(
+        long,
+        value_name = "BOOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    pub read_only: Option<bool>,
+}
+
+/// Contents of the config file, e.g.
+///
+/// ```toml
+/// bind = "0.0.0.0"
+/// port = 8080
+/// state-file = "/var/lib/http_home/home.json"
+/// log-level = "debug"
+/// read-only = false
+/// ```
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileConfig {
+    pub bind: Option<IpAddr>,
+    pub port: Option<u16>,
+    pub state_file: Option<PathBuf>,
+    pub log_level: Option<LogLevel>,
+    pub read_only: Option<bool>,
+}
+
+impl FileConfig {
+    pub fn read(path: &Path) -> ConfigResult<Self> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.into(),
+            source,
+        })?;
+        toml::from_str(&text).map_err(|source| ConfigError::Parse {
+            path: path.into(),
+            source: Box::new(source),
+        })
+    }
+}
+
+/// Effective settings of the server after merging defaults, file and command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub bind: IpAddr,
+    pub port: u16,
+    pub state_file: PathBuf,
+    pub log_level: LogLevel,
+    pub read_only: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: DEFAULT_PORT,
+            state_file: PathBuf::from(DEFAULT_STATE_FILE),
+            log_level: LogLevel::default(),
+            read_only: false,
+        }
+    }
+}
+
+impl Config {
+    /// Parses the command line, reading the config file if one is given.
+    /// Exits the process on malformed arguments, like any clap program.
+    pub fn from_args<I, T>(args: I) -> ConfigResult<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let cli = Cli::parse_from(args);
+        let file = match &cli.config {
+            Some(path) => FileConfig::read(path)?,
+            None => FileConfig::default(),
+        };
+        Self::merge(file, cli)
+    }
+
+    pub fn merge(file: FileConfig, cli: Cli) -> ConfigResult<Self> {
+        let default = Self::default();
+        let config = Self {
+            bind: cli.bind.or(file.bind).unwrap_or(default.bind),
+            port: cli.port.or(file.port).unwrap_or(default.port),
+            state_file: cli
+                .state_file
+                .or(file.state_file)
+                .unwrap_or(default.state_file),
+            log_level: cli
+                .log_level
+                .or(file.log_level)
+                .unwrap_or(default.log_level),
+            read_only: cli
+                .read_only
+                .or(file.read_only)
+                .unwrap_or(default.read_only),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind, self.port)
+    }
+
+    fn validate(&self) -> ConfigResult<()> {
+        if self.state_file.as_os_str().is_empty() {
+            return Err(ConfigError::Invalid {
+                key: "state-file",
+                reason: "path is empty".into(),
+            });
+        }
+        if self.state_file.is_dir() {
+            return Err(ConfigError::Invalid {
+                key: "state-file",
+                reason: format!("'{}' is a directory", self.state_file.display()),
+            });
+        }
+        match self.state_file.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() && !dir.is_dir() => {
+                Err(ConfigError::Invalid {
+                    key: "state-file",
+                    reason: format!("directory '{}' does not exist", dir.display()),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Command line shared by the example clients.
+#[derive(Debug, Parser)]
+pub struct ClientArgs {
+    /// Base URL of a running http_home server
+    #[arg(short, long, env = "HTTP_HOME_URL", default_value = DEFAULT_SERVER_URL)]
+    pub url: String,
+}
+
+impl ClientArgs {
+    /// Joins `path` to the server URL.
+    pub fn url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults() {
+        let config = Config::from_args(["http_home"]).unwrap();
+        assert_eq!(Config::default(), config);
+        assert_eq!("127.0.0.1:4083", config.socket_addr().to_string());
+    }
+
+    #[test]
+    fn test_cli_overrides_file() {
+        let file: FileConfig =
+            toml::from_str("port = 8080\nbind = \"0.0.0.0\"\nlog-level = \"debug\"").unwrap();
+        let cli = Cli::parse_from(["http_home", "--port", "9090", "--read-only"]);
+        let config = Config::merge(file, cli).unwrap();
+        assert_eq!(9090, config.port);
+        assert_eq!("0.0.0.0".parse::<IpAddr>().unwrap(), config.bind);
+        assert_eq!(LogLevel::Debug, config.log_level);
+        assert!(config.read_only);
+    }
+
+    #[test]
+    fn test_config_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("http_home.toml");
+        let state_file = dir.path().join("state.json");
+        fs::write(
+            &path,
+            format!("state-file = {:?}\nread-only = true\n", state_file),
+        )
+        .unwrap();
+        let config =
+            Config::from_args(["http_home".as_ref(), "-c".as_ref(), path.as_os_str()]).unwrap();
+        assert_eq!(state_file, config.state_file);
+        assert!(config.read_only);
+        assert_eq!(DEFAULT_PORT, config.port);
+
+        let config = Config::from_args([
+            "http_home".as_ref(),
+            "-c".as_ref(),
+            path.as_os_str(),
+            "--read-only=false".as_ref(),
+        ])
+        .unwrap();
+        assert!(!config.read_only);
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(toml::from_str::<FileConfig>("port = 70000").is_err());
+        assert!(toml::from_str::<FileConfig>("colour = \"red\"").is_err());
+        assert!(Cli::try_parse_from(["http_home", "--bind", "localhost:80"]).is_err());
+        assert!(Cli::try_parse_from(["http_home", "--read-only=maybe"]).is_err());
+        let cli = Cli::parse_from(["http_home", "--state-file", "no/such/dir/home.json"]);
+        assert!(matches!(
+            Config::merge(FileConfig::default(), cli),
+            Err(ConfigError::Invalid {
+                key: "state-file",
+                ..
+            })
+        ));
+        assert!(matches!(
+            FileConfig::read(Path::new("no/such/config.toml")),
+            Err(ConfigError::Read { .. })
+        ));
+    }
+
+    #[test]
+    fn test_client_args() {
+        let args = ClientArgs::parse_from(["example", "--url", "http://home:80/"]);
+        assert_eq!("http://home:80/report", args.url("/report"));
+    }
+}
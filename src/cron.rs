@@ -0,0 +1,334 @@
+//! Cron expressions: `minute hour day-of-month month day-of-week`.
+//!
+//! Every field takes `*`, numbers, ranges `a-b`, steps `*/n` or `a-b/n` and lists of
+//! those separated by commas; months and days of week also take names (`JAN`, `MON-FRI`).
+//! Day of week runs from 0 (Sunday) to 7 (Sunday again). As in classic cron, when both
+//! day fields are restricted a day matching either of them matches.
+//! `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are shorthands.
+//!
+//! ```text
+//! 0 7 * * MON-FRI    weekdays at 07:00
+//! */15 * * * *       every quarter of an hour
+//! ```
+
+use jiff::civil::{time, Date, DateTime};
+use jiff::ToSpan;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// How many years ahead to look for the next occurrence, enough for February 29.
+const SEARCH_YEARS: usize = 8;
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("Invalid cron expression '{expression}': {reason}.")]
+pub struct CronError {
+    expression: String,
+    reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and day of week fields are both restricted.
+    either_day: bool,
+}
+
+struct Field {
+    name: &'static str,
+    min: u8,
+    max: u8,
+    names: &'static [&'static str],
+}
+
+const FIELDS: [Field; 5] = [
+    Field {
+        name: "minute",
+        min: 0,
+        max: 59,
+        names: &[],
+    },
+    Field {
+        name: "hour",
+        min: 0,
+        max: 23,
+        names: &[],
+    },
+    Field {
+        name: "day of month",
+        min: 1,
+        max: 31,
+        names: &[],
+    },
+    Field {
+        name: "month",
+        min: 1,
+        max: 12,
+        names: &MONTHS,
+    },
+    Field {
+        name: "day of week",
+        min: 0,
+        max: 7,
+        names: &WEEKDAYS,
+    },
+];
+
+impl Cron {
+    /// The first matching minute strictly after `after`.
+    pub fn next_after(&self, after: DateTime) -> Option<DateTime> {
+        let start = after
+            .with()
+            .second(0)
+            .subsec_nanosecond(0)
+            .build()
+            .ok()?
+            .checked_add(1.minute())
+            .ok()?;
+        let mut date = start.date();
+        for _ in 0..SEARCH_YEARS * 366 {
+            if self.day_matches(date) {
+                let first = if date == start.date() {
+                    start.time()
+                } else {
+                    time(0, 0, 0, 0)
+                };
+                for hour in first.hour()..24 {
+                    if !has(self.hours, hour) {
+                        continue;
+                    }
+                    let first_minute = if hour == first.hour() {
+                        first.minute()
+                    } else {
+                        0
+                    };
+                    if let Some(minute) = (first_minute..60).find(|&m| has(self.minutes, m)) {
+                        return Some(date.to_datetime(time(hour, minute, 0, 0)));
+                    }
+                }
+            }
+            date = date.tomorrow().ok()?;
+        }
+        None
+    }
+
+    fn day_matches(&self, date: Date) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().to_sunday_zero_offset());
+        has(self.months, date.month())
+            && if self.either_day {
+                day || weekday
+            } else {
+                day && weekday
+            }
+    }
+}
+
+fn has(bits: u64, value: i8) -> bool {
+    bits & (1 << value) != 0
+}
+
+impl FromStr for Cron {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let error = |reason: String| CronError {
+            expression: expression.into(),
+            reason,
+        };
+        let fields = match expression.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<_> = fields.split_whitespace().collect();
+        if fields.len() != FIELDS.len() {
+            return Err(error(format!("expected 5 fields, found {}", fields.len())));
+        }
+        let mut bits = [0; 5];
+        for ((text, field), bits) in fields.iter().zip(&FIELDS).zip(&mut bits) {
+            *bits = field
+                .parse(text)
+                .map_err(|reason| error(format!("{} field '{text}' {reason}", field.name)))?;
+        }
+        let [minutes, hours, days, months, mut weekdays] = bits;
+        if has(weekdays, 7) {
+            weekdays |= 1;
+        }
+        let cron = Cron {
+            expression: expression.trim().into(),
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            either_day: !fields[2].starts_with('*') && !fields[4].starts_with('*'),
+        };
+        Ok(cron)
+    }
+}
+
+impl Field {
+    fn parse(&self, text: &str) -> Result<u64, String> {
+        let mut bits = 0;
+        for part in text.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => {
+                    let step: u8 = step
+                        .parse()
+                        .map_err(|_| format!("has a bad step '{step}'"))?;
+                    if step == 0 {
+                        return Err("has a zero step".into());
+                    }
+                    (range, step)
+                }
+                None => (part, 1),
+            };
+            let (first, last) = match range {
+                "*" => (self.min, self.max),
+                _ => match range.split_once('-') {
+                    Some((first, last)) => (self.value(first)?, self.value(last)?),
+                    None if step > 1 => (self.value(range)?, self.max),
+                    None => {
+                        let value = self.value(range)?;
+                        (value, value)
+                    }
+                },
+            };
+            if first > last {
+                return Err(format!("has a backwards range '{range}'"));
+            }
+            for value in (first..=last).step_by(step.into()) {
+                bits |= 1 << value;
+            }
+        }
+        Ok(bits)
+    }
+
+    fn value(&self, text: &str) -> Result<u8, String> {
+        let value = match self
+            .names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(text))
+        {
+            Some(index) => index as u8 + self.min,
+            None => text
+                .parse()
+                .map_err(|_| format!("has a bad value '{text}'"))?,
+        };
+        if !(self.min..=self.max).contains(&value) {
+            return Err(format!("has '{value}' outside {}-{}", self.min, self.max));
+        }
+        Ok(value)
+    }
+}
+
+impl TryFrom<String> for Cron {
+    type Error = CronError;
+
+    fn try_from(expression: String) -> Result<Self, Self::Error> {
+        expression.parse()
+    }
+}
+
+impl From<Cron> for String {
+    fn from(cron: Cron) -> Self {
+        cron.expression
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn next(cron: &str, after: &str) -> String {
+        let cron: Cron = cron.parse().unwrap();
+        cron.next_after(after.parse().unwrap())
+            .map(|next| next.to_string())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_next_after() {
+        // 2024-03-01 is a Friday.
+        let weekdays = "0 7 * * MON-FRI";
+        assert_eq!("2024-03-01T07:00:00", next(weekdays, "2024-03-01T06:59:30"));
+        assert_eq!("2024-03-04T07:00:00", next(weekdays, "2024-03-01T07:00"));
+        assert_eq!(
+            "2024-03-01T10:15:00",
+            next("*/15 * * * *", "2024-03-01T10:00")
+        );
+        assert_eq!(
+            "2024-03-01T23:59:00",
+            next("59 23 * * *", "2024-03-01T12:00")
+        );
+        assert_eq!("2024-03-03T00:00:00", next("@weekly", "2024-03-01T12:00"));
+        assert_eq!(
+            "2028-02-29T00:00:00",
+            next("0 0 29 2 *", "2024-03-01T00:00")
+        );
+        // Either the 13th or a Friday.
+        assert_eq!(
+            "2024-03-08T00:00:00",
+            next("0 0 13 * 5", "2024-03-01T00:00")
+        );
+        assert_eq!(
+            "2024-03-13T00:00:00",
+            next("0 0 13 * 5", "2024-03-08T00:00")
+        );
+        assert_eq!("2024-03-03T00:00:00", next("0 0 * * 7", "2024-03-01T00:00"));
+        assert_eq!(
+            "2024-06-01T00:00:00",
+            next("0 0 1 jun *", "2024-03-01T00:00")
+        );
+        assert_eq!("", next("0 0 31 2 *", "2024-03-01T00:00"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for expression in [
+            "",
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "* * * * FUNDAY",
+        ] {
+            assert!(expression.parse::<Cron>().is_err(), "{expression}");
+        }
+        let error = "0 25 * * *".parse::<Cron>().unwrap_err();
+        assert_eq!(
+            "Invalid cron expression '0 25 * * *': hour field '25' has '25' outside 0-23.",
+            error.to_string()
+        );
+    }
+
+    #[test]
+    fn test_serde() {
+        let cron: Cron = serde_json::from_str("\"0 7 * * MON-FRI\"").unwrap();
+        assert_eq!("\"0 7 * * MON-FRI\"", serde_json::to_string(&cron).unwrap());
+        assert!(serde_json::from_str::<Cron>("\"every day\"").is_err());
+    }
+}
@@ -0,0 +1,914 @@
+//! Pluggable device types.
+//!
+//! Every kind of device the server understands is a [`DeviceKind`] in a [`DeviceRegistry`].
+//! Socket, power strip, thermometer, climate, contact, motion, leak, light, thermostat
+//! and generic devices are registered by default; another crate can
+//! register its own kinds and pass the registry to [`crate::run`] through
+//! [`crate::ServerOptions`], and they work with every HTTP endpoint and the report.
+//!
+//! A kind declares its fields with [`FieldSpec`]s. Incoming properties are checked
+//! against them before `create` or `apply` is called, so those only ever see
+//! declared, writable properties of the declared types.
+
+use crate::error::{HomeError, HomeResult};
+use crate::payload::{
+    self, ChannelPayload, ClimatePayload, ContactPayload, DevicePayload, GenericPayload,
+    LeakPayload, LightPayload, MotionPayload, PowerStripPayload, RequestFields, SocketPayload,
+    ThermometerPayload, ThermostatPayload,
+};
+use crate::smart_device::{
+    ClimateSensor, Comfort, Device, DeviceDict, Generic, Light, Motion, PowerStrip, PropertySchema,
+    PropertyValue, Thermostat, ThermostatMode, FALSE_WORDS, TRUE_WORDS,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+pub type Properties = BTreeMap<String, PropertyValue>;
+
+/// Names of the kinds built into [`Device`]; they cannot be registered again.
+pub const BUILTIN_KINDS: [&str; 10] = [
+    "socket",
+    "power_strip",
+    "thermometer",
+    "climate",
+    "contact",
+    "motion",
+    "leak",
+    "light",
+    "thermostat",
+    "generic",
+];
+
+#[derive(Debug, Error, PartialEq)]
+pub enum RegistryError {
+    #[error("Device kind '{0}' is already registered.")]
+    DuplicateKind(String),
+    #[error("Device kind '{0}' is built in.")]
+    BuiltinKind(String),
+    #[error("Invalid device kind name '{0}'.")]
+    InvalidName(String),
+}
+
+/// Description of one field of a device kind.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct FieldSpec {
+    pub name: String,
+    #[serde(flatten)]
+    pub value_type: PropertySchema,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// Must be given when the device is created.
+    #[serde(default)]
+    pub required: bool,
+    /// Computed by the device; clients may read but never set it.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Other names accepted for the field, e.g. `state` of the legacy socket query.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    /// Text accepted in place of a bool; filled in by [`KindSchema::of`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub accepts: Vec<String>,
+}
+
+impl FieldSpec {
+    pub fn new(name: &str, value_type: PropertySchema) -> Self {
+        Self {
+            name: name.into(),
+            value_type,
+            unit: None,
+            required: false,
+            read_only: false,
+            aliases: Vec::new(),
+            accepts: Vec::new(),
+        }
+    }
+
+    pub fn number(name: &str) -> Self {
+        Self::new(
+            name,
+            PropertySchema::Number {
+                min: None,
+                max: None,
+            },
+        )
+    }
+
+    pub fn boolean(name: &str) -> Self {
+        Self::new(name, PropertySchema::Bool)
+    }
+
+    pub fn text(name: &str) -> Self {
+        Self::new(name, PropertySchema::String)
+    }
+
+    pub fn one_of(name: &str, values: &[&str]) -> Self {
+        Self::new(
+            name,
+            PropertySchema::Enum {
+                values: values.iter().map(|v| v.to_string()).collect(),
+            },
+        )
+    }
+
+    /// Limits a number field; either bound may be omitted.
+    pub fn range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        if let PropertySchema::Number { .. } = self.value_type {
+            self.value_type = PropertySchema::Number { min, max };
+        }
+        self
+    }
+
+    pub fn unit(mut self, unit: &str) -> Self {
+        self.unit = Some(unit.into());
+        self
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.read_only = true;
+        self
+    }
+
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.aliases.push(alias.into());
+        self
+    }
+
+    /// Whether `name` is the name of the field or one of its aliases.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+}
+
+/// Everything a client needs to know to create and update devices of one kind.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct KindSchema {
+    pub name: String,
+    pub fields: Vec<FieldSpec>,
+}
+
+impl KindSchema {
+    pub fn of(kind: &dyn DeviceKind) -> Self {
+        let fields = kind
+            .fields()
+            .into_iter()
+            .map(|mut spec| {
+                if spec.value_type == PropertySchema::Bool && spec.accepts.is_empty() {
+                    spec.accepts = TRUE_WORDS
+                        .iter()
+                        .chain(&FALSE_WORDS)
+                        .map(|w| w.to_string())
+                        .collect();
+                }
+                spec
+            })
+            .collect();
+        Self {
+            name: kind.name().into(),
+            fields,
+        }
+    }
+}
+
+/// A type of device the server can create, update and describe.
+///
+/// Only `name` and `fields` are mandatory: by default a kind keeps its state
+/// as a [`Generic`] device whose `kind` is the kind's name.
+pub trait DeviceKind: Send + Sync {
+    /// Value of the `device` key in requests and dicts, in lower case.
+    fn name(&self) -> &str;
+
+    fn fields(&self) -> Vec<FieldSpec>;
+
+    /// Builds a device from checked properties; every required field is present.
+    fn create(&self, properties: Properties) -> HomeResult<Device> {
+        Ok(Generic::new(self.name(), properties, None)?.into())
+    }
+
+    /// Builds the payload of a request naming this kind. Kinds without a payload type
+    /// of their own keep the default and get a [`DevicePayload::Custom`] of the fields
+    /// checked against [`DeviceKind::fields`], which calls `create` and `apply`.
+    fn payload(&self, fields: &RequestFields) -> HomeResult<Option<DevicePayload>> {
+        let _ = fields;
+        Ok(None)
+    }
+
+    /// Changes `device` with checked properties, leaving it untouched on error.
+    fn apply(&self, device: &mut Device, properties: Properties) -> HomeResult<()> {
+        match device {
+            Device::Generic(generic) if generic.get_kind() == self.name() => {
+                let mut updated = generic.clone();
+                for (name, value) in properties {
+                    updated.set(&name, value)?;
+                }
+                *generic = updated;
+                Ok(())
+            }
+            other => Err(type_mismatch(self.name(), other)),
+        }
+    }
+
+    fn dict(&self, device: &Device) -> HashMap<String, String> {
+        let mut dict = device.device_dict();
+        if let Device::Generic(_) = device {
+            dict.remove("kind");
+            dict.insert("device".into(), self.name().into());
+        }
+        dict
+    }
+}
+
+/// The set of device kinds known to a server.
+#[derive(Clone)]
+pub struct DeviceRegistry {
+    kinds: BTreeMap<String, Arc<dyn DeviceKind>>,
+}
+
+impl DeviceRegistry {
+    /// A registry without even the built-in kinds.
+    pub fn empty() -> Self {
+        Self {
+            kinds: BTreeMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, kind: impl DeviceKind + 'static) -> Result<(), RegistryError> {
+        let name = kind.name().to_string();
+        if name.is_empty() || name != name.to_lowercase() {
+            return Err(RegistryError::InvalidName(name));
+        }
+        if BUILTIN_KINDS.contains(&name.as_str()) {
+            return Err(RegistryError::BuiltinKind(name));
+        }
+        if self.kinds.contains_key(&name) {
+            return Err(RegistryError::DuplicateKind(name));
+        }
+        self.kinds.insert(name, Arc::new(kind));
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn DeviceKind>> {
+        self.kinds.get(&name.to_lowercase())
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &Arc<dyn DeviceKind>> {
+        self.kinds.values()
+    }
+
+    pub fn schemas(&self) -> Vec<KindSchema> {
+        self.kinds()
+            .map(|kind| KindSchema::of(kind.as_ref()))
+            .collect()
+    }
+
+    /// The kind that handles `device`: a generic device whose `kind`
+    /// is a registered kind belongs to that kind.
+    pub fn kind_of(&self, device: &Device) -> Option<&Arc<dyn DeviceKind>> {
+        match device {
+            Device::Generic(generic) if !BUILTIN_KINDS.contains(&generic.get_kind()) => self
+                .kinds
+                .get(generic.get_kind())
+                .or_else(|| self.kinds.get("generic")),
+            _ => self.kinds.get(device.type_name()),
+        }
+    }
+
+    /// Name of the kind that handles `device`, e.g. `socket` or a registered kind.
+    pub fn type_name<'a>(&'a self, device: &'a Device) -> &'a str {
+        match self.kind_of(device) {
+            Some(kind) => kind.name(),
+            None => device.type_name(),
+        }
+    }
+
+    pub fn device_dict(&self, device: &Device) -> HashMap<String, String> {
+        match self.kind_of(device) {
+            Some(kind) => kind.dict(device),
+            None => device.device_dict(),
+        }
+    }
+
+    /// The name of the field `property` refers to in a device type,
+    /// resolving aliases such as `state` for `on`.
+    pub fn field_name(&self, device_type: &str, property: &str) -> String {
+        self.get(device_type)
+            .and_then(|kind| {
+                kind.fields()
+                    .into_iter()
+                    .find(|field| field.is_named(property))
+            })
+            .map_or_else(|| property.to_string(), |field| field.name)
+    }
+}
+
+impl Default for DeviceRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.kinds.insert("socket".into(), Arc::new(SocketKind));
+        registry
+            .kinds
+            .insert("power_strip".into(), Arc::new(PowerStripKind));
+        registry
+            .kinds
+            .insert("thermometer".into(), Arc::new(ThermometerKind));
+        registry
+            .kinds
+            .insert("climate".into(), Arc::new(ClimateKind));
+        registry
+            .kinds
+            .insert("contact".into(), Arc::new(ContactKind));
+        registry.kinds.insert("motion".into(), Arc::new(MotionKind));
+        registry.kinds.insert("leak".into(), Arc::new(LeakKind));
+        registry.kinds.insert("light".into(), Arc::new(LightKind));
+        registry
+            .kinds
+            .insert("thermostat".into(), Arc::new(ThermostatKind));
+        registry
+            .kinds
+            .insert("generic".into(), Arc::new(GenericKind));
+        registry
+    }
+}
+
+impl fmt::Debug for DeviceRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.kinds.keys()).finish()
+    }
+}
+
+/// Checks raw properties against the fields of `kind`, converting them to the declared types.
+pub fn check_properties(
+    kind: &dyn DeviceKind,
+    raw: impl IntoIterator<Item = (String, PropertyValue)>,
+) -> HomeResult<Properties> {
+    let fields = kind.fields();
+    let mut properties = Properties::new();
+    for (name, value) in raw {
+        let spec = fields
+            .iter()
+            .find(|spec| spec.is_named(&name))
+            .ok_or_else(|| HomeError::invalid_value(&name, "unknown field"))?;
+        if spec.read_only {
+            return Err(HomeError::invalid_value(&name, "read-only field"));
+        }
+        let value = spec.value_type.check(&name, value)?;
+        properties.insert(spec.name.clone(), value);
+    }
+    Ok(properties)
+}
+
+/// Fails on the first required field of `kind` missing from `properties`.
+pub fn check_required(kind: &dyn DeviceKind, properties: &Properties) -> HomeResult<()> {
+    match kind
+        .fields()
+        .into_iter()
+        .find(|spec| spec.required && !properties.contains_key(&spec.name))
+    {
+        Some(spec) => Err(HomeError::invalid_value(&spec.name, "missing field")),
+        None => Ok(()),
+    }
+}
+
+fn type_mismatch(expected: &str, device: &Device) -> HomeError {
+    HomeError::TypeMismatch {
+        room: String::new(),
+        device: String::new(),
+        expected: expected.into(),
+        actual: device.type_name().into(),
+    }
+}
+
+fn number(properties: &Properties, name: &str) -> Option<f64> {
+    match properties.get(name) {
+        Some(PropertyValue::Number(n)) => Some(*n),
+        _ => None,
+    }
+}
+
+fn boolean(properties: &Properties, name: &str) -> Option<bool> {
+    match properties.get(name) {
+        Some(PropertyValue::Bool(b)) => Some(*b),
+        _ => None,
+    }
+}
+
+fn text(properties: &Properties, name: &str) -> Option<String> {
+    match properties.get(name) {
+        Some(PropertyValue::Text(s)) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+/// The `payload`, `create` and `apply` of a built-in kind, which go through its
+/// [`DevicePayload`] variant. `$build` reads the variant's payload from checked
+/// properties; with `try` it may fail, and a JSON body is checked as flat properties
+/// instead of deserialized into the payload type.
+macro_rules! builtin_payload {
+    ($variant:ident, $build:ident) => {
+        fn payload(&self, fields: &RequestFields) -> HomeResult<Option<DevicePayload>> {
+            Ok(Some(DevicePayload::$variant(fields.build(self, $build)?)))
+        }
+
+        fn create(&self, properties: Properties) -> HomeResult<Device> {
+            DevicePayload::$variant($build(&properties)).into_device()
+        }
+
+        fn apply(&self, device: &mut Device, properties: Properties) -> HomeResult<()> {
+            DevicePayload::$variant($build(&properties)).apply_to(device)
+        }
+    };
+    ($variant:ident, try $build:ident) => {
+        fn payload(&self, fields: &RequestFields) -> HomeResult<Option<DevicePayload>> {
+            let properties = fields.properties(self)?;
+            Ok(Some(DevicePayload::$variant($build(&properties)?)))
+        }
+
+        fn create(&self, properties: Properties) -> HomeResult<Device> {
+            DevicePayload::$variant($build(&properties)?).into_device()
+        }
+
+        fn apply(&self, device: &mut Device, properties: Properties) -> HomeResult<()> {
+            DevicePayload::$variant($build(&properties)?).apply_to(device)
+        }
+    };
+}
+
+pub struct SocketKind;
+
+impl DeviceKind for SocketKind {
+    fn name(&self) -> &str {
+        "socket"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        vec![
+            FieldSpec::boolean("on").alias("state").required(),
+            FieldSpec::number("voltage")
+                .range(Some(0.), None)
+                .unit("V")
+                .required(),
+            FieldSpec::number("current")
+                .range(Some(0.), None)
+                .unit("A")
+                .required(),
+            FieldSpec::number("power").unit("W").read_only(),
+        ]
+    }
+
+    builtin_payload!(Socket, socket_payload);
+}
+
+pub(crate) fn socket_payload(properties: &Properties) -> SocketPayload {
+    SocketPayload {
+        on: boolean(properties, "on"),
+        voltage: number(properties, "voltage"),
+        current: number(properties, "current"),
+    }
+}
+
+/// Outlets are numbered from 1; `channel_N` switches outlet N and `channel_N_current`
+/// is what it draws. Fields are declared for as many outlets as a strip can have.
+pub struct PowerStripKind;
+
+impl DeviceKind for PowerStripKind {
+    fn name(&self) -> &str {
+        "power_strip"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        let mut fields = vec![
+            FieldSpec::number("voltage")
+                .range(Some(0.), Some(PowerStrip::MAX_VOLTAGE))
+                .unit("V")
+                .required(),
+            FieldSpec::number("max_current")
+                .range(Some(0.), Some(PowerStrip::MAX_CURRENT))
+                .unit("A")
+                .required(),
+            FieldSpec::number("channels")
+                .range(Some(1.), Some(PowerStrip::MAX_CHANNELS as f64))
+                .required(),
+            FieldSpec::number("current").unit("A").read_only(),
+            FieldSpec::number("power").unit("W").read_only(),
+        ];
+        for number in 1..=PowerStrip::MAX_CHANNELS {
+            fields.push(FieldSpec::boolean(&PowerStrip::channel_field(number)));
+            fields.push(
+                FieldSpec::number(&PowerStrip::channel_current_field(number))
+                    .range(Some(0.), None)
+                    .unit("A"),
+            );
+        }
+        fields
+    }
+
+    builtin_payload!(PowerStrip, try power_strip_payload);
+}
+
+/// Fails when the number of channels is not a whole number.
+pub(crate) fn power_strip_payload(properties: &Properties) -> HomeResult<PowerStripPayload> {
+    let channels = number(properties, "channels")
+        .map(|count| {
+            if count.fract() == 0. {
+                Ok(count as usize)
+            } else {
+                Err(HomeError::invalid_value(
+                    "channels",
+                    "must be a whole number",
+                ))
+            }
+        })
+        .transpose()?;
+    let mut outlets = BTreeMap::<usize, ChannelPayload>::new();
+    for (name, value) in properties {
+        let Some((number, is_current)) = PowerStrip::parse_channel_field(name) else {
+            continue;
+        };
+        let outlet = outlets.entry(number).or_default();
+        match value {
+            PropertyValue::Number(current) if is_current => outlet.current = Some(*current),
+            PropertyValue::Bool(on) if !is_current => outlet.on = Some(*on),
+            _ => {}
+        }
+    }
+    Ok(PowerStripPayload {
+        voltage: number(properties, "voltage"),
+        max_current: number(properties, "max_current"),
+        channels,
+        outlets,
+    })
+}
+
+pub struct ThermometerKind;
+
+impl DeviceKind for ThermometerKind {
+    fn name(&self) -> &str {
+        "thermometer"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        vec![FieldSpec::number("temperature")
+            .range(Some(-273.15), None)
+            .unit("°C")
+            .required()]
+    }
+
+    builtin_payload!(Thermometer, thermometer_payload);
+}
+
+pub(crate) fn thermometer_payload(properties: &Properties) -> ThermometerPayload {
+    ThermometerPayload {
+        temperature: number(properties, "temperature"),
+    }
+}
+
+pub struct ClimateKind;
+
+impl DeviceKind for ClimateKind {
+    fn name(&self) -> &str {
+        "climate"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        let (min, max) = ClimateSensor::TEMPERATURE;
+        vec![
+            FieldSpec::number("temperature")
+                .range(Some(min), Some(max))
+                .unit("°C")
+                .required(),
+            FieldSpec::number("humidity")
+                .range(Some(0.), Some(ClimateSensor::MAX_HUMIDITY))
+                .unit("%")
+                .required(),
+            FieldSpec::number("co2")
+                .range(Some(0.), Some(ClimateSensor::MAX_CO2))
+                .unit("ppm")
+                .required(),
+            FieldSpec::number("voc").range(Some(0.), Some(ClimateSensor::MAX_VOC)),
+            FieldSpec::number("dew_point").unit("°C").read_only(),
+            FieldSpec::one_of("comfort", &Comfort::NAMES).read_only(),
+        ]
+    }
+
+    builtin_payload!(Climate, climate_payload);
+}
+
+pub(crate) fn climate_payload(properties: &Properties) -> ClimatePayload {
+    ClimatePayload {
+        temperature: number(properties, "temperature"),
+        humidity: number(properties, "humidity"),
+        co2: number(properties, "co2"),
+        voc: number(properties, "voc"),
+    }
+}
+
+/// When a binary sensor last changed and was last triggered, as RFC 3339 timestamps.
+fn binary_times() -> [FieldSpec; 2] {
+    [
+        FieldSpec::text("changed").read_only(),
+        FieldSpec::text("last_triggered").read_only(),
+    ]
+}
+
+pub struct ContactKind;
+
+impl DeviceKind for ContactKind {
+    fn name(&self) -> &str {
+        "contact"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        let mut fields = vec![FieldSpec::boolean("open").required()];
+        fields.extend(binary_times());
+        fields
+    }
+
+    builtin_payload!(Contact, contact_payload);
+}
+
+pub(crate) fn contact_payload(properties: &Properties) -> ContactPayload {
+    ContactPayload {
+        open: boolean(properties, "open"),
+    }
+}
+
+pub struct MotionKind;
+
+impl DeviceKind for MotionKind {
+    fn name(&self) -> &str {
+        "motion"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        let mut fields = vec![
+            FieldSpec::boolean("detected").required(),
+            FieldSpec::number("reset_after")
+                .range(Some(0.), Some(Motion::MAX_RESET_AFTER))
+                .unit("s"),
+        ];
+        fields.extend(binary_times());
+        fields
+    }
+
+    builtin_payload!(Motion, motion_payload);
+}
+
+pub(crate) fn motion_payload(properties: &Properties) -> MotionPayload {
+    MotionPayload {
+        detected: boolean(properties, "detected"),
+        reset_after: number(properties, "reset_after"),
+    }
+}
+
+pub struct LeakKind;
+
+impl DeviceKind for LeakKind {
+    fn name(&self) -> &str {
+        "leak"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        let mut fields = vec![FieldSpec::boolean("wet").required()];
+        fields.extend(binary_times());
+        fields
+    }
+
+    builtin_payload!(Leak, leak_payload);
+}
+
+pub(crate) fn leak_payload(properties: &Properties) -> LeakPayload {
+    LeakPayload {
+        wet: boolean(properties, "wet"),
+    }
+}
+
+pub struct LightKind;
+
+impl DeviceKind for LightKind {
+    fn name(&self) -> &str {
+        "light"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        let (min_kelvin, max_kelvin) = Light::COLOR_TEMPERATURE;
+        vec![
+            FieldSpec::boolean("on").alias("state").required(),
+            FieldSpec::number("brightness")
+                .range(Some(0.), Some(Light::MAX_BRIGHTNESS))
+                .unit("%"),
+            FieldSpec::number("color_temperature")
+                .range(Some(min_kelvin), Some(max_kelvin))
+                .unit("K"),
+            FieldSpec::text("color"),
+            FieldSpec::number("transition")
+                .range(Some(0.), Some(Light::MAX_TRANSITION))
+                .unit("s"),
+        ]
+    }
+
+    builtin_payload!(Light, light_payload);
+}
+
+pub(crate) fn light_payload(properties: &Properties) -> LightPayload {
+    LightPayload {
+        on: boolean(properties, "on"),
+        brightness: number(properties, "brightness"),
+        color_temperature: number(properties, "color_temperature"),
+        color: text(properties, "color"),
+        transition: number(properties, "transition"),
+    }
+}
+
+pub struct ThermostatKind;
+
+impl DeviceKind for ThermostatKind {
+    fn name(&self) -> &str {
+        "thermostat"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        let (min, max) = Thermostat::SETPOINT;
+        vec![
+            FieldSpec::number("setpoint")
+                .range(Some(min), Some(max))
+                .unit("°C")
+                .required(),
+            FieldSpec::number("hysteresis")
+                .range(Some(0.), Some(Thermostat::MAX_HYSTERESIS))
+                .unit("°C"),
+            FieldSpec::one_of("mode", &ThermostatMode::NAMES),
+            FieldSpec::text("sensor").required(),
+            FieldSpec::text("actuator").required(),
+            FieldSpec::one_of("demand", &["idle", "heating", "cooling"]).read_only(),
+        ]
+    }
+
+    builtin_payload!(Thermostat, thermostat_payload);
+}
+
+pub(crate) fn thermostat_payload(properties: &Properties) -> ThermostatPayload {
+    ThermostatPayload {
+        setpoint: number(properties, "setpoint"),
+        hysteresis: number(properties, "hysteresis"),
+        mode: text(properties, "mode").and_then(|mode| mode.parse().ok()),
+        sensor: text(properties, "sensor"),
+        actuator: text(properties, "actuator"),
+    }
+}
+
+/// Free-form devices; their properties are described by their own optional schema.
+pub struct GenericKind;
+
+impl DeviceKind for GenericKind {
+    fn name(&self) -> &str {
+        "generic"
+    }
+
+    fn fields(&self) -> Vec<FieldSpec> {
+        vec![FieldSpec::text("kind").required()]
+    }
+
+    /// Properties are nested in a JSON body and free-form in a query string.
+    fn payload(&self, fields: &RequestFields) -> HomeResult<Option<DevicePayload>> {
+        let payload = match fields {
+            RequestFields::Json(value) => payload::fields_from_json((*value).clone())?,
+            RequestFields::Query(data) => GenericPayload {
+                kind: data.get("kind").map(|kind| kind.to_string()),
+                properties: data
+                    .iter()
+                    .filter(|(name, _)| !matches!(**name, "device" | "kind"))
+                    .map(|(name, value)| (name.to_string(), PropertyValue::infer(value)))
+                    .collect(),
+                schema: None,
+            },
+        };
+        Ok(Some(DevicePayload::Generic(payload)))
+    }
+
+    /// A generic device names its own `kind`.
+    fn dict(&self, device: &Device) -> HashMap<String, String> {
+        device.device_dict()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A kind another crate could define without touching this one.
+    struct Dimmer;
+
+    impl DeviceKind for Dimmer {
+        fn name(&self) -> &str {
+            "dimmer"
+        }
+
+        fn fields(&self) -> Vec<FieldSpec> {
+            vec![
+                FieldSpec::number("level")
+                    .range(Some(0.), Some(100.))
+                    .unit("%")
+                    .required(),
+                FieldSpec::boolean("lit").read_only(),
+            ]
+        }
+
+        fn create(&self, mut properties: Properties) -> HomeResult<Device> {
+            let lit = number(&properties, "level").unwrap_or_default() > 0.;
+            properties.insert("lit".into(), PropertyValue::Bool(lit));
+            Ok(Generic::new(self.name(), properties, None)?.into())
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = DeviceRegistry::default();
+        assert_eq!(Ok(()), registry.register(Dimmer));
+        assert_eq!(
+            Err(RegistryError::DuplicateKind("dimmer".into())),
+            registry.register(Dimmer)
+        );
+        assert_eq!(
+            Err(RegistryError::BuiltinKind("socket".into())),
+            registry.register(SocketKind)
+        );
+        assert_eq!(
+            Err(RegistryError::BuiltinKind("socket".into())),
+            DeviceRegistry::empty().register(SocketKind)
+        );
+        assert_eq!(
+            vec![
+                "climate",
+                "contact",
+                "dimmer",
+                "generic",
+                "leak",
+                "light",
+                "motion",
+                "power_strip",
+                "socket",
+                "thermometer",
+                "thermostat"
+            ],
+            registry.kinds().map(|kind| kind.name()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_custom_kind() {
+        let mut registry = DeviceRegistry::default();
+        registry.register(Dimmer).unwrap();
+        let dimmer = registry.get("Dimmer").unwrap();
+
+        let raw = [("level".to_string(), PropertyValue::Text("40".into()))];
+        let properties = check_properties(dimmer.as_ref(), raw).unwrap();
+        check_required(dimmer.as_ref(), &properties).unwrap();
+        let mut device = dimmer.create(properties).unwrap();
+        let dict = registry.device_dict(&device);
+        assert_eq!("dimmer", dict["device"]);
+        assert_eq!("40", dict["level"]);
+        assert_eq!("true", dict["lit"]);
+        assert!(!dict.contains_key("kind"));
+
+        let raw = [("lit".to_string(), PropertyValue::Bool(false))];
+        assert!(check_properties(dimmer.as_ref(), raw).is_err());
+        let raw = [("level".to_string(), PropertyValue::Number(150.))];
+        assert!(check_properties(dimmer.as_ref(), raw).is_err());
+        assert!(check_required(dimmer.as_ref(), &Properties::new()).is_err());
+
+        let raw = [("level".to_string(), PropertyValue::Number(10.))];
+        let properties = check_properties(dimmer.as_ref(), raw).unwrap();
+        dimmer.apply(&mut device, properties.clone()).unwrap();
+        assert_eq!("10", registry.device_dict(&device)["level"]);
+        assert!(dimmer.apply(&mut Device::new_socket(), properties).is_err());
+    }
+
+    #[test]
+    fn test_builtin_kinds() {
+        let registry = DeviceRegistry::default();
+        let socket = registry.get("socket").unwrap();
+        let raw = [
+            ("on".to_string(), PropertyValue::Text("on".into())),
+            ("voltage".to_string(), PropertyValue::Number(230.)),
+        ];
+        let properties = check_properties(socket.as_ref(), raw).unwrap();
+        assert!(check_required(socket.as_ref(), &properties).is_err());
+        let mut device = Device::new_socket();
+        socket.apply(&mut device, properties).unwrap();
+        assert_eq!("on", registry.device_dict(&device)["state"]);
+        assert_eq!("230", registry.device_dict(&device)["voltage"]);
+        assert_eq!(
+            "thermometer",
+            registry.kind_of(&Device::new_thermometer()).unwrap().name()
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! Energy accounting: how many kWh the sockets used and what they cost.
+//!
+//! Every socket has a meter that integrates its power while it is on, and so does every
+//! outlet of a power strip, metered as `{device}/channels/{number}`. Power only changes
+//! together with the home, so meters are brought up to date whenever a change is saved
+//! and readings add what was drawn since at the current power.
+//!
+//! The cost follows the tariff in force at the time: a flat price per kWh, or rates by
+//! time of day in the time zone of the server clock, each from its `from` time until the
+//! next one:
+//!
+//! ```json
+//! {"type": "time_of_day", "rates": [
+//!   {"from": "07:00", "price": 0.3},
+//!   {"from": "23:00", "price": 0.1}
+//! ]}
+//! ```
+//!
+//! Energy used before a tariff is set costs nothing. What a removed socket used stays in
+//! the totals of its room and of the home, so they never go down.
+
+use jiff::civil::Time;
+use jiff::{SignedDuration, Timestamp, Zoned};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Tariff {
+    /// The same price per kWh at any time.
+    Flat { price: f64 },
+    /// Prices per kWh by time of day, wrapping around midnight.
+    TimeOfDay { rates: Vec<Rate> },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    /// Time of day the price starts to apply, e.g. `07:00`.
+    #[schema(value_type = String, example = "07:00")]
+    pub from: Time,
+    pub price: f64,
+}
+
+impl Tariff {
+    pub fn validate(&self) -> Result<(), String> {
+        let prices: Vec<_> = match self {
+            Self::Flat { price } => vec![*price],
+            Self::TimeOfDay { rates } => {
+                if rates.is_empty() {
+                    return Err("a time of day tariff needs at least one rate".into());
+                }
+                let times: BTreeSet<_> = rates.iter().map(|rate| rate.from).collect();
+                if times.len() != rates.len() {
+                    return Err("two rates start at the same time".into());
+                }
+                rates.iter().map(|rate| rate.price).collect()
+            }
+        };
+        match prices
+            .iter()
+            .find(|price| !price.is_finite() || **price < 0.)
+        {
+            Some(price) => Err(format!("price {price} is not a non-negative number")),
+            None => Ok(()),
+        }
+    }
+
+    /// What `watts` drawn from `start` until `end` cost.
+    pub fn cost(&self, watts: f64, start: &Zoned, end: &Zoned) -> f64 {
+        let rates = match self {
+            Self::Flat { price } => return kwh(watts, start.duration_until(end)) * price,
+            Self::TimeOfDay { rates } => {
+                let mut rates = rates.clone();
+                rates.sort_by_key(|rate| rate.from);
+                rates
+            }
+        };
+        let Some(last) = rates.last() else {
+            return 0.;
+        };
+        let mut cost = 0.;
+        let mut from = start.clone();
+        while from < *end {
+            let time = from.time();
+            let price = rates
+                .iter()
+                .rev()
+                .find(|rate| rate.from <= time)
+                .unwrap_or(last)
+                .price;
+            let next = match rates.iter().find(|rate| rate.from > time) {
+                Some(rate) => Ok(from.date().to_datetime(rate.from)),
+                None => from
+                    .date()
+                    .tomorrow()
+                    .map(|date| date.to_datetime(rates[0].from)),
+            }
+            .and_then(|next| next.to_zoned(from.time_zone().clone()));
+            let until = match next {
+                Ok(next) if next > from && next < *end => next,
+                _ => end.clone(),
+            };
+            cost += kwh(watts, from.duration_until(&until)) * price;
+            from = until;
+        }
+        cost
+    }
+}
+
+fn kwh(watts: f64, duration: SignedDuration) -> f64 {
+    watts * duration.as_secs_f64() / 3_600_000.
+}
+
+/// Tariff and socket meters of a home, saved with it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Energy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tariff: Option<Tariff>,
+    /// Meters by room and socket name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    meters: BTreeMap<String, BTreeMap<String, Meter>>,
+    /// Energy used by sockets that were removed, by room.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    retired: BTreeMap<String, Retired>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+struct Retired {
+    kwh: f64,
+    cost: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Meter {
+    kwh: f64,
+    cost: f64,
+    /// Power drawn since the last update, zero while the socket is off.
+    watts: f64,
+    since: Timestamp,
+}
+
+/// Energy used and its cost, absent when there is no tariff.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Usage {
+    pub kwh: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SocketUsage {
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// Power drawn now in watts.
+    pub power: f64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct RoomUsage {
+    #[serde(flatten)]
+    pub usage: Usage,
+    pub sockets: BTreeMap<String, SocketUsage>,
+}
+
+/// Energy used by the whole home, by room and by socket.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EnergyUsage {
+    #[serde(flatten)]
+    pub usage: Usage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tariff: Option<Tariff>,
+    pub rooms: BTreeMap<String, RoomUsage>,
+}
+
+impl Usage {
+    fn add(&mut self, other: Usage) {
+        self.kwh += other.kwh;
+        self.cost = match (self.cost, other.cost) {
+            (Some(cost), Some(other)) => Some(cost + other),
+            (cost, other) => cost.or(other),
+        };
+    }
+}
+
+impl Energy {
+    pub fn is_empty(&self) -> bool {
+        self.tariff.is_none() && self.meters.is_empty() && self.retired.is_empty()
+    }
+
+    pub fn tariff(&self) -> Option<&Tariff> {
+        self.tariff.as_ref()
+    }
+
+    /// Changes the tariff from now on; meters must be up to date.
+    pub fn set_tariff(&mut self, tariff: Option<Tariff>) -> Option<Tariff> {
+        std::mem::replace(&mut self.tariff, tariff)
+    }
+
+    /// Brings the meters up to `now` and starts metering `sockets` at their current power,
+    /// given by room, name and watts. Meters of sockets that are gone are dropped
+    /// and what they read is added to the retired total of their room.
+    pub fn update<'a, D: AsRef<str>>(
+        &mut self,
+        sockets: impl IntoIterator<Item = (&'a str, D, f64)>,
+        now: &Zoned,
+    ) {
+        let mut meters: BTreeMap<String, BTreeMap<String, Meter>> = BTreeMap::new();
+        for (room, device, watts) in sockets {
+            let device = device.as_ref();
+            let meter = match self
+                .meters
+                .get_mut(room)
+                .and_then(|room| room.remove(device))
+            {
+                Some(meter) => {
+                    let (kwh, cost) = meter.reading(self.tariff.as_ref(), now);
+                    Meter {
+                        kwh,
+                        cost,
+                        watts,
+                        since: now.timestamp(),
+                    }
+                }
+                None => Meter {
+                    kwh: 0.,
+                    cost: 0.,
+                    watts,
+                    since: now.timestamp(),
+                },
+            };
+            meters
+                .entry(room.into())
+                .or_default()
+                .insert(device.into(), meter);
+        }
+        for (room, gone) in std::mem::replace(&mut self.meters, meters) {
+            for meter in gone.values() {
+                let (kwh, cost) = meter.reading(self.tariff.as_ref(), now);
+                let retired = self.retired.entry(room.clone()).or_default();
+                retired.kwh += kwh;
+                retired.cost += cost;
+            }
+        }
+    }
+
+    /// What every socket used until `now`.
+    pub fn usage(&self, now: &Zoned) -> EnergyUsage {
+        let priced = self.tariff.is_some();
+        let mut usage = EnergyUsage {
+            tariff: self.tariff.clone(),
+            ..Default::default()
+        };
+        let room_names: BTreeSet<_> = self.meters.keys().chain(self.retired.keys()).collect();
+        for room_name in room_names {
+            let mut room = RoomUsage::default();
+            if let Some(retired) = self.retired.get(room_name) {
+                room.usage.add(Usage {
+                    kwh: retired.kwh,
+                    cost: priced.then_some(retired.cost),
+                });
+            }
+            for (device_name, meter) in self.meters.get(room_name).into_iter().flatten() {
+                let (kwh, cost) = meter.reading(self.tariff.as_ref(), now);
+                let socket = Usage {
+                    kwh,
+                    cost: priced.then_some(cost),
+                };
+                room.usage.add(socket);
+                room.sockets.insert(
+                    device_name.clone(),
+                    SocketUsage {
+                        usage: socket,
+                        power: meter.watts,
+                    },
+                );
+            }
+            usage.usage.add(room.usage);
+            usage.rooms.insert(room_name.clone(), room);
+        }
+        usage
+    }
+}
+
+impl Meter {
+    /// Energy and cost counted until `now`.
+    fn reading(&self, tariff: Option<&Tariff>, now: &Zoned) -> (f64, f64) {
+        let since = self.since.to_zoned(now.time_zone().clone());
+        if since >= *now {
+            return (self.kwh, self.cost);
+        }
+        let cost = tariff.map_or(0., |tariff| tariff.cost(self.watts, &since, now));
+        (
+            self.kwh + kwh(self.watts, since.duration_until(now)),
+            self.cost + cost,
+        )
+    }
+}
+
+impl std::fmt::Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.3} kWh", self.kwh)?;
+        if let Some(cost) = self.cost {
+            write!(f, " costing {cost:.2}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> Zoned {
+        format!("2024-03-01T{time}[UTC]").parse().unwrap()
+    }
+
+    fn tariff(json: serde_json::Value) -> Tariff {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn close(expected: f64, actual: f64) {
+        assert!((expected - actual).abs() < 1e-9, "{expected} != {actual}");
+    }
+
+    #[test]
+    fn test_tariff_cost() {
+        let flat = tariff(serde_json::json!({"type": "flat", "price": 0.2}));
+        close(0.4, flat.cost(1000., &at("07:00"), &at("09:00")));
+
+        let night = tariff(serde_json::json!({"type": "time_of_day", "rates": [
+            {"from": "23:00", "price": 0.1}, {"from": "07:00", "price": 0.3}
+        ]}));
+        // 22:00-23:00 at 0.3, 23:00-07:00 at 0.1, 07:00-08:00 at 0.3.
+        let evening = at("22:00");
+        let morning: Zoned = "2024-03-02T08:00[UTC]".parse().unwrap();
+        close(0.3 + 0.8 + 0.3, night.cost(1000., &evening, &morning));
+        close(0.05, night.cost(1000., &at("03:00"), &at("03:30")));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(tariff(serde_json::json!({"type": "flat", "price": 0.2}))
+            .validate()
+            .is_ok());
+        for broken in [
+            serde_json::json!({"type": "flat", "price": -1}),
+            serde_json::json!({"type": "time_of_day", "rates": []}),
+            serde_json::json!({"type": "time_of_day", "rates": [
+                {"from": "07:00", "price": 0.3}, {"from": "07:00", "price": 0.1}
+            ]}),
+        ] {
+            assert!(tariff(broken).validate().is_err());
+        }
+    }
+
+    #[test]
+    fn test_metering() {
+        let mut energy = Energy::default();
+        energy.set_tariff(Some(Tariff::Flat { price: 0.5 }));
+        energy.update([("R", "S", 2000.), ("R", "S2", 0.)], &at("07:00"));
+        let usage = energy.usage(&at("07:30"));
+        close(1., usage.usage.kwh);
+        close(0.5, usage.rooms["R"].usage.cost.unwrap());
+        assert_eq!(2000., usage.rooms["R"].sockets["S"].power);
+
+        // Switched off at 08:00, and S2 removed.
+        energy.update([("R", "S", 0.)], &at("08:00"));
+        let usage = energy.usage(&at("12:00"));
+        close(2., usage.usage.kwh);
+        close(1., usage.usage.cost.unwrap());
+        assert_eq!(
+            vec!["S"],
+            usage.rooms["R"].sockets.keys().collect::<Vec<_>>()
+        );
+
+        // S removed too: what it used stays in the room and the home.
+        energy.update([] as [(&str, &str, f64); 0], &at("13:00"));
+        let usage = energy.usage(&at("14:00"));
+        assert!(usage.rooms["R"].sockets.is_empty());
+        close(2., usage.rooms["R"].usage.kwh);
+        close(2., usage.usage.kwh);
+        close(1., usage.usage.cost.unwrap());
+    }
+
+    #[test]
+    fn test_serde() {
+        let mut energy = Energy::default();
+        assert!(energy.is_empty());
+        energy.update([("R", "S", 100.)], &at("07:00"));
+        let json = serde_json::to_string(&energy).unwrap();
+        assert_eq!(energy, serde_json::from_str(&json).unwrap());
+    }
+}
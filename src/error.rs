@@ -0,0 +1,109 @@
+use thiserror::Error;
+
+/// Why a change to the `Home` was refused.
+///
+/// A payload does not know which device it is applied to, so the type mismatches
+/// it returns name no device until `Home` fills it in with [`HomeError::in_room`]
+/// and [`HomeError::at_device`].
+#[derive(Debug, Clone, PartialEq, Error)]
+#[non_exhaustive]
+pub enum HomeError {
+    #[error("Room not found '{room}'.")]
+    RoomNotFound { room: String },
+    #[error("Room '{room}' already exists.")]
+    RoomExists { room: String },
+    #[error("Device not found {room}/{device}.")]
+    DeviceNotFound { room: String, device: String },
+    #[error("Device {room}/{device} already exists.")]
+    DeviceExists { room: String, device: String },
+    #[error("Device {room}/{device} is a {actual}, not a {expected}.")]
+    TypeMismatch {
+        room: String,
+        device: String,
+        expected: String,
+        actual: String,
+    },
+    #[error("Device {room}/{device} is bound to thermostat {thermostat}.")]
+    DeviceInUse {
+        room: String,
+        device: String,
+        thermostat: String,
+    },
+    #[error("Invalid value for '{field}': {reason}.")]
+    InvalidValue { field: String, reason: String },
+    #[error("Invalid JSON body: {reason}.")]
+    BadJson { reason: String },
+    #[error("Scene not found '{scene}'.")]
+    SceneNotFound { scene: String },
+}
+
+pub type HomeResult<T> = Result<T, HomeError>;
+
+impl HomeError {
+    pub fn room_not_found(room_name: &str) -> Self {
+        Self::RoomNotFound {
+            room: room_name.into(),
+        }
+    }
+
+    pub fn device_not_found(room_name: &str, device_name: &str) -> Self {
+        Self::DeviceNotFound {
+            room: room_name.into(),
+            device: device_name.into(),
+        }
+    }
+
+    pub fn bad_json(reason: impl ToString) -> Self {
+        Self::BadJson {
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn invalid_value(field: &str, reason: &str) -> Self {
+        Self::InvalidValue {
+            field: field.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Names the room a device-level error happened in.
+    pub fn in_room(mut self, room_name: &str) -> Self {
+        match &mut self {
+            Self::DeviceNotFound { room, .. }
+            | Self::DeviceExists { room, .. }
+            | Self::TypeMismatch { room, .. } => *room = room_name.into(),
+            _ => {}
+        }
+        self
+    }
+
+    /// Names the device a type mismatch happened on.
+    pub fn at_device(mut self, device_name: &str) -> Self {
+        if let Self::TypeMismatch { device, .. } = &mut self {
+            *device = device_name.into();
+        }
+        self
+    }
+
+    pub fn room(&self) -> Option<&str> {
+        match self {
+            Self::RoomNotFound { room }
+            | Self::RoomExists { room }
+            | Self::DeviceNotFound { room, .. }
+            | Self::DeviceExists { room, .. }
+            | Self::DeviceInUse { room, .. }
+            | Self::TypeMismatch { room, .. } => Some(room),
+            _ => None,
+        }
+    }
+
+    pub fn device(&self) -> Option<&str> {
+        match self {
+            Self::DeviceNotFound { device, .. }
+            | Self::DeviceExists { device, .. }
+            | Self::DeviceInUse { device, .. }
+            | Self::TypeMismatch { device, .. } => Some(device),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,828 @@
+//! Change notifications.
+//!
+//! Every mutating route, and the scheduler, publishes [`ChangeEvent`]s on the [`EventBus`]
+//! while it still holds the write lock on the home, so events come in the order of the
+//! changes, and only then writes the state file. A change whose save fails stays in
+//! effect and its events are not taken back: the request is answered with a 500
+//! `storage-error` problem saying the change was applied but not saved.
+//!
+//! `GET /api/v1/events` streams the events to WebSocket clients as JSON text messages:
+//!
+//! ```json
+//! {"seq": 42, "time": "2024-03-01T07:00:00Z", "event": "property_changed", "room": "R",
+//!  "device": "S", "device_type": "socket", "property": "state", "old": "off", "new": "on"}
+//! ```
+//!
+//! `GET /api/v1/events/stream` sends the same events as Server-Sent Events for clients
+//! that cannot speak WebSocket. Events are numbered with a monotonic `seq`, and the
+//! bus keeps the latest ones so that an SSE client reconnecting with `Last-Event-ID`
+//! receives what it missed. Numbering starts again on every start of the server, so
+//! the SSE id also names the start, e.g. `1709276400000-42`; a client coming back with
+//! an id of another start is sent a `reset` event instead.
+//!
+//! `?room=`, `?device=` and `?type=` narrow both streams down.
+
+use crate::clock::{Clock, SystemClock};
+use crate::device_kind::DeviceRegistry;
+use crate::error::HomeResult;
+use crate::home::Home;
+use crate::payload::DevicePayload;
+use crate::smart_device::Device;
+use crate::smart_room::Room;
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_ws::Message;
+use futures_util::{stream, StreamExt};
+use jiff::{Timestamp, Zoned};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{self, Instant};
+use utoipa::{IntoParams, ToSchema};
+
+/// How many events a slow subscriber may fall behind before it misses some.
+const CAPACITY: usize = 1024;
+/// How many past events a resuming subscriber can catch up on.
+const HISTORY: usize = 1024;
+/// How often an idle event stream sends a comment to keep proxies from closing it.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+/// How long an SSE client waits before reconnecting.
+const RETRY: Duration = Duration::from_secs(3);
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ChangeEvent {
+    RoomAdded {
+        room: String,
+    },
+    RoomRemoved {
+        room: String,
+    },
+    DeviceAdded {
+        room: String,
+        device: String,
+        device_type: String,
+    },
+    DeviceRemoved {
+        room: String,
+        device: String,
+        device_type: String,
+    },
+    /// `old` or `new` is absent when the property appeared or disappeared.
+    PropertyChanged {
+        room: String,
+        device: String,
+        device_type: String,
+        property: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// A contact opened or a leak detector got wet (`active`), or stopped.
+    Alert {
+        room: String,
+        device: String,
+        device_type: String,
+        /// `open` or `leak`.
+        alert: String,
+        active: bool,
+    },
+    /// Published by the `emit` action of an automation rule or a schedule.
+    Notification {
+        /// Name of the rule or schedule.
+        source: String,
+        message: String,
+    },
+}
+
+impl ChangeEvent {
+    pub fn room(&self) -> Option<&str> {
+        match self {
+            Self::RoomAdded { room }
+            | Self::RoomRemoved { room }
+            | Self::DeviceAdded { room, .. }
+            | Self::DeviceRemoved { room, .. }
+            | Self::PropertyChanged { room, .. }
+            | Self::Alert { room, .. } => Some(room),
+            Self::Notification { .. } => None,
+        }
+    }
+
+    pub fn device(&self) -> Option<&str> {
+        match self {
+            Self::RoomAdded { .. } | Self::RoomRemoved { .. } | Self::Notification { .. } => None,
+            Self::DeviceAdded { device, .. }
+            | Self::DeviceRemoved { device, .. }
+            | Self::PropertyChanged { device, .. }
+            | Self::Alert { device, .. } => Some(device),
+        }
+    }
+
+    pub fn device_type(&self) -> Option<&str> {
+        match self {
+            Self::RoomAdded { .. } | Self::RoomRemoved { .. } | Self::Notification { .. } => None,
+            Self::DeviceAdded { device_type, .. }
+            | Self::DeviceRemoved { device_type, .. }
+            | Self::PropertyChanged { device_type, .. }
+            | Self::Alert { device_type, .. } => Some(device_type),
+        }
+    }
+}
+
+/// A change event numbered in publication order, starting from 1.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SequencedEvent {
+    pub seq: u64,
+    /// When the event was published.
+    #[schema(value_type = String, format = DateTime)]
+    pub time: Timestamp,
+    #[serde(flatten)]
+    pub event: ChangeEvent,
+}
+
+/// Fans change events out to every subscriber and remembers the latest ones
+/// so that a reconnecting subscriber can catch up.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<SequencedEvent>,
+    history: Arc<Mutex<History>>,
+    sinks: Arc<Mutex<Vec<Arc<dyn EventSink>>>>,
+    clock: Arc<dyn Clock>,
+    epoch: u64,
+}
+
+/// Sees every event while it is published, in order. Unlike a subscriber,
+/// a sink cannot fall behind and miss events, so it must not block.
+pub trait EventSink: Send + Sync + fmt::Debug {
+    fn record(&self, event: &SequencedEvent);
+}
+
+/// Where an SSE client left off: the start of the bus, as milliseconds since
+/// the Unix epoch, and the sequence number of the last event received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    pub epoch: u64,
+    pub seq: u64,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.epoch, self.seq)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (epoch, seq) = text
+            .trim()
+            .split_once('-')
+            .ok_or_else(|| format!("'{text}' is not an event id like 1709276400000-42"))?;
+        let number = |part: &str| {
+            part.parse()
+                .map_err(|_| format!("'{text}' is not an event id like 1709276400000-42"))
+        };
+        Ok(Self {
+            epoch: number(epoch)?,
+            seq: number(seq)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct History {
+    next_seq: u64,
+    events: VecDeque<SequencedEvent>,
+    capacity: usize,
+}
+
+/// What a subscriber resuming after some sequence number gets.
+#[derive(Debug)]
+pub struct Resumed {
+    /// Events after the cursor that are already published, oldest first.
+    pub backlog: Vec<SequencedEvent>,
+    /// How many events after the cursor fell out of the history.
+    pub missed: u64,
+    /// The cursor is from another start of the server, or from the future: what was
+    /// missed is unknown and the subscriber should read the state of the home again.
+    pub reset: bool,
+    pub receiver: broadcast::Receiver<SequencedEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::with_history(HISTORY)
+    }
+
+    /// A bus stamping events with the time of `clock` instead of the system clock.
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self::build(HISTORY, clock)
+    }
+
+    /// A bus remembering the last `capacity` events.
+    pub fn with_history(capacity: usize) -> Self {
+        Self::build(capacity, Arc::new(SystemClock))
+    }
+
+    fn build(capacity: usize, clock: Arc<dyn Clock>) -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        let history = History {
+            next_seq: 1,
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+        };
+        let epoch = u64::try_from(clock.now().timestamp().as_millisecond()).unwrap_or_default();
+        Self {
+            sender,
+            history: Arc::new(Mutex::new(history)),
+            sinks: Arc::default(),
+            clock,
+            epoch,
+        }
+    }
+
+    /// Hands every event published from now on to `sink`.
+    pub fn attach(&self, sink: Arc<dyn EventSink>) {
+        self.sinks
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sink);
+    }
+
+    /// When the bus started numbering events, as milliseconds since the Unix epoch.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// The cursor of a client that received `event` from this bus.
+    pub fn cursor(&self, event: &SequencedEvent) -> Cursor {
+        Cursor {
+            epoch: self.epoch,
+            seq: event.seq,
+        }
+    }
+
+    pub fn publish(&self, events: impl IntoIterator<Item = ChangeEvent>) {
+        let mut history = self.history();
+        let sinks = self.sinks.lock().unwrap_or_else(PoisonError::into_inner);
+        let time = self.clock.now().timestamp();
+        for event in events {
+            let event = SequencedEvent {
+                seq: history.next_seq,
+                time,
+                event,
+            };
+            history.next_seq += 1;
+            if history.events.len() == history.capacity {
+                history.events.pop_front();
+            }
+            if history.capacity > 0 {
+                history.events.push_back(event.clone());
+            }
+            for sink in sinks.iter() {
+                sink.record(&event);
+            }
+            // Nobody listening is not an error.
+            let _ = self.sender.send(event);
+        }
+    }
+
+    /// The time published events are stamped with.
+    pub fn now(&self) -> Zoned {
+        self.clock.now()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SequencedEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Subscribes to the events published after `cursor`,
+    /// replaying the remembered ones without gaps or duplicates.
+    pub fn resume(&self, cursor: Cursor) -> Resumed {
+        let history = self.history();
+        // Subscribing under the lock: nothing is published between the backlog and the receiver.
+        let receiver = self.sender.subscribe();
+        if cursor.epoch != self.epoch || cursor.seq >= history.next_seq {
+            return Resumed {
+                backlog: Vec::new(),
+                missed: 0,
+                reset: true,
+                receiver,
+            };
+        }
+        let backlog: Vec<_> = history
+            .events
+            .iter()
+            .filter(|event| event.seq > cursor.seq)
+            .cloned()
+            .collect();
+        let first_kept = backlog.first().map_or(history.next_seq, |event| event.seq);
+        Resumed {
+            backlog,
+            missed: first_kept.saturating_sub(cursor.seq + 1),
+            reset: false,
+            receiver,
+        }
+    }
+
+    fn history(&self) -> MutexGuard<'_, History> {
+        self.history.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps only the events about the given room, device or device type.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventFilter {
+    /// Only events in this room; notifications are skipped.
+    pub room: Option<String>,
+    /// Only events about devices with this name; room events are skipped.
+    pub device: Option<String>,
+    /// Only events about devices of this type; room events are skipped.
+    #[serde(rename = "type")]
+    #[param(rename = "type")]
+    pub device_type: Option<String>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &ChangeEvent) -> bool {
+        self.room
+            .as_deref()
+            .is_none_or(|room| Some(room) == event.room())
+            && self
+                .device
+                .as_deref()
+                .is_none_or(|device| Some(device) == event.device())
+            && self
+                .device_type
+                .as_deref()
+                .is_none_or(|device_type| Some(device_type) == event.device_type())
+    }
+}
+
+pub fn device_added(
+    registry: &DeviceRegistry,
+    room: &str,
+    name: &str,
+    device: &Device,
+) -> ChangeEvent {
+    ChangeEvent::DeviceAdded {
+        room: room.into(),
+        device: name.into(),
+        device_type: registry.type_name(device).into(),
+    }
+}
+
+pub fn device_removed(
+    registry: &DeviceRegistry,
+    room: &str,
+    name: &str,
+    device: &Device,
+) -> ChangeEvent {
+    ChangeEvent::DeviceRemoved {
+        room: room.into(),
+        device: name.into(),
+        device_type: registry.type_name(device).into(),
+    }
+}
+
+/// A removed room takes its devices with it.
+pub fn room_removed(registry: &DeviceRegistry, room_name: &str, room: &Room) -> Vec<ChangeEvent> {
+    let mut events: Vec<_> = room
+        .devices()
+        .map(|(name, device)| device_removed(registry, room_name, name, device))
+        .collect();
+    events.push(ChangeEvent::RoomRemoved {
+        room: room_name.into(),
+    });
+    events
+}
+
+/// Updates the device and describes what changed.
+pub fn update_device(
+    home: &mut Home,
+    registry: &DeviceRegistry,
+    room_name: &str,
+    device_name: &str,
+    payload: &DevicePayload,
+) -> HomeResult<Vec<ChangeEvent>> {
+    let old = home.get_device_by_path(room_name, device_name).cloned();
+    let new = home.update_device(room_name, device_name, payload)?;
+    Ok(old
+        .map(|old| device_changed(registry, room_name, device_name, &old, new))
+        .unwrap_or_default())
+}
+
+/// What changed between two states of a device, as seen in its dict.
+/// A device replaced by one of another type is removed and added again.
+pub fn device_changed(
+    registry: &DeviceRegistry,
+    room: &str,
+    name: &str,
+    old: &Device,
+    new: &Device,
+) -> Vec<ChangeEvent> {
+    let device_type = registry.type_name(new);
+    if registry.type_name(old) != device_type {
+        return vec![
+            device_removed(registry, room, name, old),
+            device_added(registry, room, name, new),
+        ];
+    }
+    let (old, new) = (registry.device_dict(old), registry.device_dict(new));
+    property_changes(&old, &new)
+        .map(|(property, old, new)| ChangeEvent::PropertyChanged {
+            room: room.into(),
+            device: name.into(),
+            device_type: device_type.into(),
+            property: property.into(),
+            old: old.cloned(),
+            new: new.cloned(),
+        })
+        .collect()
+}
+
+fn property_changes<'a>(
+    old: &'a HashMap<String, String>,
+    new: &'a HashMap<String, String>,
+) -> impl Iterator<Item = (&'a String, Option<&'a String>, Option<&'a String>)> {
+    let properties: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    properties
+        .into_iter()
+        .filter(|property| *property != "device")
+        .map(|property| (property, old.get(property), new.get(property)))
+        .filter(|(_, old, new)| old != new)
+}
+
+/// Upgrades to a WebSocket streaming the events that match the query filter.
+#[utoipa::path(
+    get, path = "/events", tag = "events",
+    params(EventFilter),
+    responses(
+        (status = 101, description = "Switching to WebSocket; every text message is a change event", body = SequencedEvent),
+        (status = 400, description = "Not a WebSocket handshake"),
+    )
+)]
+pub async fn websocket(
+    req: HttpRequest,
+    body: web::Payload,
+    filter: web::Query<EventFilter>,
+    bus: web::Data<EventBus>,
+) -> actix_web::Result<HttpResponse> {
+    let (response, mut session, mut messages) = actix_ws::handle(&req, body)?;
+    let filter = filter.into_inner();
+    let mut events = bus.subscribe();
+    actix_web::rt::spawn(async move {
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let text = match event {
+                        Ok(event) if filter.matches(&event.event) => {
+                            serde_json::to_string(&event).unwrap_or_default()
+                        }
+                        Ok(_) => continue,
+                        Err(RecvError::Lagged(missed)) => {
+                            serde_json::json!({"event": "lagged", "missed": missed}).to_string()
+                        }
+                        Err(RecvError::Closed) => break,
+                    };
+                    if session.text(text).await.is_err() {
+                        return;
+                    }
+                }
+                message = messages.recv() => match message {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+/// Streams the events that match the query filter as Server-Sent Events.
+///
+/// Every event carries the start of the server and its sequence number as the SSE id.
+/// A client reconnecting with `Last-Event-ID` first gets the remembered events it missed;
+/// if some are already forgotten it is told how many with a `lagged` event, and if the
+/// id is from another start of the server it gets a `reset` event.
+#[utoipa::path(
+    get, path = "/events/stream", tag = "events",
+    params(
+        EventFilter,
+        ("Last-Event-ID" = Option<String>, Header, description = "SSE id of the last event received, e.g. `1709276400000-42`"),
+    ),
+    responses(
+        (status = 200, description = "`text/event-stream` of change events", body = SequencedEvent, content_type = "text/event-stream"),
+    )
+)]
+pub async fn event_stream(
+    req: HttpRequest,
+    filter: web::Query<EventFilter>,
+    bus: web::Data<EventBus>,
+) -> HttpResponse {
+    let filter = filter.into_inner();
+    let last_event_id = req
+        .headers()
+        .get(LAST_EVENT_ID)
+        .and_then(|value| value.to_str().ok());
+    let Resumed {
+        backlog,
+        missed,
+        reset,
+        receiver,
+    } = match last_event_id {
+        Some(id) => match id.parse() {
+            Ok(cursor) => bus.resume(cursor),
+            // Not an id this server sends, so where the client left off is unknown.
+            Err(_) => Resumed {
+                backlog: Vec::new(),
+                missed: 0,
+                reset: true,
+                receiver: bus.subscribe(),
+            },
+        },
+        None => Resumed {
+            backlog: Vec::new(),
+            missed: 0,
+            reset: false,
+            receiver: bus.subscribe(),
+        },
+    };
+    // Also gets the headers out before the first event.
+    let mut frames = vec![web::Bytes::from(format!(
+        "retry: {}\n\n",
+        RETRY.as_millis()
+    ))];
+    if reset {
+        frames.push(web::Bytes::from_static(
+            b"event: reset\ndata: {\"event\":\"reset\"}\n\n",
+        ));
+    }
+    if missed > 0 {
+        frames.push(lagged_frame(missed));
+    }
+    let epoch = bus.epoch();
+    frames.extend(
+        backlog
+            .iter()
+            .filter(|event| filter.matches(&event.event))
+            .map(|event| event_frame(epoch, event)),
+    );
+
+    let keep_alive = time::interval_at(Instant::now() + KEEP_ALIVE, KEEP_ALIVE);
+    let live = stream::unfold(
+        (receiver, filter, keep_alive),
+        move |(mut receiver, filter, mut keep_alive)| async move {
+            let frame = loop {
+                tokio::select! {
+                    event = receiver.recv() => match event {
+                        Ok(event) if filter.matches(&event.event) => {
+                            break event_frame(epoch, &event)
+                        }
+                        Ok(_) => {}
+                        Err(RecvError::Lagged(missed)) => break lagged_frame(missed),
+                        Err(RecvError::Closed) => return None,
+                    },
+                    _ = keep_alive.tick() => break web::Bytes::from_static(b": keep-alive\n\n"),
+                }
+            };
+            Some((Ok::<_, Infallible>(frame), (receiver, filter, keep_alive)))
+        },
+    );
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(header::CacheControl(vec![header::CacheDirective::NoCache]))
+        .streaming(stream::iter(frames.into_iter().map(Ok)).chain(live))
+}
+
+fn event_frame(epoch: u64, event: &SequencedEvent) -> web::Bytes {
+    let data = serde_json::to_value(event).unwrap_or_default();
+    let name = data["event"].as_str().unwrap_or_default();
+    let id = Cursor {
+        epoch,
+        seq: event.seq,
+    };
+    format!("id: {id}\nevent: {name}\ndata: {data}\n\n").into()
+}
+
+fn lagged_frame(missed: u64) -> web::Bytes {
+    let data = serde_json::json!({"event": "lagged", "missed": missed});
+    format!("event: lagged\ndata: {data}\n\n").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::smart_device::{Socket, Thermometer};
+
+    #[test]
+    fn test_device_changed() {
+        let registry = DeviceRegistry::default();
+        let old = Socket::new(220., 0., false).into();
+        let new = Socket::new(220., 1., true).into();
+        let events = device_changed(&registry, "R", "S", &old, &new);
+        assert_eq!(
+            vec!["current", "power", "state"],
+            events
+                .iter()
+                .map(|event| match event {
+                    ChangeEvent::PropertyChanged { property, .. } => property.as_str(),
+                    other => panic!("unexpected event {other:?}"),
+                })
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            ChangeEvent::PropertyChanged {
+                room: "R".into(),
+                device: "S".into(),
+                device_type: "socket".into(),
+                property: "state".into(),
+                old: Some("off".into()),
+                new: Some("on".into()),
+            },
+            events[2]
+        );
+        let thermometer = Thermometer::new(20.).into();
+        let events = device_changed(&registry, "R", "S", &old, &thermometer);
+        assert!(matches!(
+            events[..],
+            [
+                ChangeEvent::DeviceRemoved { .. },
+                ChangeEvent::DeviceAdded { .. }
+            ]
+        ));
+        assert!(device_changed(&registry, "R", "S", &old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_filter() {
+        let event = ChangeEvent::DeviceAdded {
+            room: "R".into(),
+            device: "S".into(),
+            device_type: "socket".into(),
+        };
+        let room_event = ChangeEvent::RoomAdded { room: "R".into() };
+        let filter =
+            |room: Option<&str>, device: Option<&str>, device_type: Option<&str>| EventFilter {
+                room: room.map(String::from),
+                device: device.map(String::from),
+                device_type: device_type.map(String::from),
+            };
+        assert!(EventFilter::default().matches(&event));
+        assert!(filter(Some("R"), None, None).matches(&room_event));
+        assert!(filter(Some("R"), Some("S"), Some("socket")).matches(&event));
+        assert!(!filter(Some("K"), None, None).matches(&event));
+        assert!(!filter(None, None, Some("thermometer")).matches(&event));
+        assert!(!filter(None, Some("S"), None).matches(&room_event));
+    }
+
+    fn room_added(room: &str) -> ChangeEvent {
+        ChangeEvent::RoomAdded { room: room.into() }
+    }
+
+    #[actix_web::test]
+    async fn test_bus() {
+        let clock = ManualClock::new("2024-03-01T07:00[UTC]".parse().unwrap());
+        let bus = EventBus::with_clock(Arc::new(clock));
+        bus.publish([room_added("lost")]);
+        let mut events = bus.subscribe();
+        bus.publish([room_added("R")]);
+        assert_eq!(
+            SequencedEvent {
+                seq: 2,
+                time: "2024-03-01T07:00Z".parse().unwrap(),
+                event: room_added("R")
+            },
+            events.recv().await.unwrap()
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_resume() {
+        let bus = EventBus::with_history(2);
+        let at = |seq| Cursor {
+            epoch: bus.epoch(),
+            seq,
+        };
+        bus.publish(["A", "B", "C"].map(room_added));
+        let resumed = bus.resume(at(1));
+        assert_eq!(
+            vec![2, 3],
+            resumed.backlog.iter().map(|e| e.seq).collect::<Vec<_>>()
+        );
+        assert_eq!(0, resumed.missed);
+        assert!(!resumed.reset);
+        let mut resumed = bus.resume(at(0));
+        assert_eq!(1, resumed.missed);
+        assert_eq!(room_added("B"), resumed.backlog[0].event);
+        bus.publish([room_added("D")]);
+        assert_eq!(4, resumed.receiver.recv().await.unwrap().seq);
+
+        let resumed = bus.resume(at(4));
+        assert!(resumed.backlog.is_empty());
+        assert_eq!(0, resumed.missed);
+        assert!(!resumed.reset);
+        // A cursor from the future or from another start of the server.
+        assert!(bus.resume(at(100)).reset);
+        let other_start = Cursor {
+            epoch: bus.epoch() + 1,
+            seq: 1,
+        };
+        let resumed = bus.resume(other_start);
+        assert!(resumed.reset);
+        assert!(resumed.backlog.is_empty());
+    }
+
+    #[test]
+    fn test_cursor() {
+        let cursor = Cursor {
+            epoch: 1709276400000,
+            seq: 42,
+        };
+        assert_eq!("1709276400000-42", cursor.to_string());
+        assert_eq!(Ok(cursor), " 1709276400000-42 ".parse());
+        assert!("42".parse::<Cursor>().is_err());
+        assert!("x-42".parse::<Cursor>().is_err());
+    }
+
+    async fn next_frame(body: &mut actix_web::body::BoxBody) -> String {
+        use actix_web::body::MessageBody;
+        let frame = std::future::poll_fn(|cx| std::pin::Pin::new(&mut *body).poll_next(cx));
+        let bytes = frame.await.unwrap().unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_event_stream() {
+        use actix_web::{test, App};
+        let clock = ManualClock::new("2024-03-01T07:00[UTC]".parse().unwrap());
+        let bus = EventBus::with_clock(Arc::new(clock));
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(bus.clone()))
+                .route("/events/stream", web::get().to(event_stream)),
+        )
+        .await;
+        bus.publish(["A", "B"].map(room_added));
+
+        let req = test::TestRequest::get()
+            .uri("/events/stream?room=B")
+            .insert_header((LAST_EVENT_ID, "1709276400000-0"));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(
+            "text/event-stream",
+            resp.headers().get(header::CONTENT_TYPE).unwrap()
+        );
+        let mut body = resp.into_body();
+        assert_eq!("retry: 3000\n\n", next_frame(&mut body).await);
+        assert_eq!(
+            "id: 1709276400000-2\nevent: room_added\ndata: {\"event\":\"room_added\",\"room\":\"B\",\"seq\":2,\"time\":\"2024-03-01T07:00:00Z\"}\n\n",
+            next_frame(&mut body).await
+        );
+        bus.publish(["A", "B"].map(room_added));
+        assert!(next_frame(&mut body)
+            .await
+            .starts_with("id: 1709276400000-4\n"));
+
+        let req = test::TestRequest::get().uri("/events/stream");
+        let resp = test::call_service(&app, req.to_request()).await;
+        let mut body = resp.into_body();
+        next_frame(&mut body).await;
+        bus.publish([room_added("C")]);
+        assert!(next_frame(&mut body)
+            .await
+            .starts_with("id: 1709276400000-5\n"));
+
+        // An id without the start of the server.
+        let req = test::TestRequest::get()
+            .uri("/events/stream")
+            .insert_header((LAST_EVENT_ID, "3"));
+        let resp = test::call_service(&app, req.to_request()).await;
+        let mut body = resp.into_body();
+        next_frame(&mut body).await;
+        assert_eq!(
+            "event: reset\ndata: {\"event\":\"reset\"}\n\n",
+            next_frame(&mut body).await
+        );
+    }
+}
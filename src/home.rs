@@ -1,11 +1,31 @@
-use crate::smart_device::{Device, DeviceDict};
+use crate::device_kind::DeviceRegistry;
+use crate::energy::{Energy, Tariff};
+use crate::error::{HomeError, HomeResult};
+use crate::payload::DevicePayload;
+use crate::rules::Rule;
+use crate::scene::Scene;
+use crate::scheduler::Schedule;
+use crate::smart_device::Device;
 use crate::smart_room::Room;
-use std::collections::{hash_map::Entry, HashMap};
+use crate::storage::{self, StorageError};
+use jiff::Zoned;
+use serde::{Deserialize, Serialize};
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+use std::path::Path;
 
 #[allow(dead_code, unused)]
+#[derive(Serialize, Deserialize)]
 pub struct Home {
     name: String,
     rooms: HashMap<String, Room>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    rules: BTreeMap<String, Rule>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    schedules: BTreeMap<String, Schedule>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    scenes: BTreeMap<String, Scene>,
+    #[serde(default, skip_serializing_if = "Energy::is_empty")]
+    energy: Energy,
 }
 
 #[allow(dead_code, unused)]
@@ -14,6 +34,10 @@ impl Home {
         Home {
             name: String::from(name),
             rooms: HashMap::new(),
+            rules: BTreeMap::new(),
+            schedules: BTreeMap::new(),
+            scenes: BTreeMap::new(),
+            energy: Energy::default(),
         }
     }
 
@@ -29,15 +53,21 @@ impl Home {
         self.rooms.values()
     }
 
-    pub fn add_room(&mut self, unique_name: &str) -> Option<&Room> {
+    pub fn add_room(&mut self, unique_name: &str) -> HomeResult<&Room> {
         match self.rooms.entry(unique_name.into()) {
-            Entry::Occupied(_) => None,
-            Entry::Vacant(entry) => Some(entry.insert(Default::default())),
+            Entry::Occupied(_) => Err(HomeError::RoomExists {
+                room: unique_name.into(),
+            }),
+            Entry::Vacant(entry) => Ok(entry.insert(Default::default())),
         }
     }
 
-    pub fn remove_room(&mut self, room_name: &str) -> Option<Room> {
-        self.rooms.remove(room_name)
+    /// Removes the room, unless a thermostat in another room is bound to a device in it.
+    pub fn remove_room(&mut self, room_name: &str) -> HomeResult<Room> {
+        self.check_bound(room_name, None, None)?;
+        self.rooms
+            .remove(room_name)
+            .ok_or_else(|| HomeError::room_not_found(room_name))
     }
 
     pub fn get_room_by_name(&self, room_name: &str) -> Option<&Room> {
@@ -55,16 +85,133 @@ impl Home {
         room_name: &str,
         unique_name: &str,
         device: Device,
-    ) -> Option<&Device> {
-        self.rooms
-            .get_mut(room_name)
-            .and_then(|room| room.add_device(unique_name, device))
+    ) -> HomeResult<&Device> {
+        self.check_bindings(&device)?;
+        self.room_mut(room_name)?
+            .add_device(unique_name, device)
+            .map_err(|e| e.in_room(room_name))
+    }
+
+    /// Inserts the device or replaces the one with the same name, returning the old one.
+    /// A device a thermostat is bound to may only be replaced with one of the same type.
+    pub fn replace_device(
+        &mut self,
+        room_name: &str,
+        device_name: &str,
+        device: Device,
+    ) -> HomeResult<Option<Device>> {
+        self.check_bindings(&device)?;
+        self.check_bound(room_name, Some(device_name), Some(&device))?;
+        Ok(self
+            .room_mut(room_name)?
+            .replace_device(device_name, device))
+    }
+
+    /// Removes the device, unless a thermostat is bound to it.
+    pub fn remove_device(&mut self, room_name: &str, device_name: &str) -> HomeResult<Device> {
+        self.check_bound(room_name, Some(device_name), None)?;
+        self.room_mut(room_name)?
+            .remove_device(device_name)
+            .map_err(|e| e.in_room(room_name))
+    }
+
+    /// Changes the properties given in `payload`, leaving the device untouched on error.
+    pub fn update_device(
+        &mut self,
+        room_name: &str,
+        device_name: &str,
+        payload: &DevicePayload,
+    ) -> HomeResult<&Device> {
+        let device = self
+            .room_mut(room_name)?
+            .get_device_by_name_mut(device_name)
+            .ok_or_else(|| HomeError::device_not_found(room_name, device_name))?;
+        let mut updated = device.clone();
+        payload
+            .apply_to(&mut updated)
+            .and_then(|()| self.check_bindings(&updated))
+            .map_err(|e| e.in_room(room_name).at_device(device_name))?;
+        let device = self
+            .room_mut(room_name)?
+            .get_device_by_name_mut(device_name)
+            .expect("device was just found");
+        *device = updated;
+        Ok(device)
+    }
+
+    /// Fails unless the devices a thermostat reads and switches are in the home
+    /// and of the right type.
+    fn check_bindings(&self, device: &Device) -> HomeResult<()> {
+        let Device::Thermostat(thermostat) = device else {
+            return Ok(());
+        };
+        for (field, path, expected) in [
+            ("sensor", thermostat.get_sensor(), "thermometer"),
+            ("actuator", thermostat.get_actuator(), "socket"),
+        ] {
+            match self.get_device_by_path(&path.room, &path.device) {
+                None => {
+                    return Err(HomeError::invalid_value(
+                        field,
+                        &format!("no device at '{path}'"),
+                    ))
+                }
+                Some(bound) if bound.type_name() != expected => {
+                    return Err(HomeError::invalid_value(
+                        field,
+                        &format!("'{path}' is a {}, not a {expected}", bound.type_name()),
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
     }
 
-    pub fn remove_device(&mut self, room_name: &str, device_name: &str) -> Option<Device> {
+    /// Fails if a thermostat is bound to the device `device_name` in `room_name`,
+    /// or to any device in the room if no device is given, unless `replacement` is
+    /// of the type the thermostat expects there. Thermostats that are themselves
+    /// being removed or replaced are not asked.
+    fn check_bound(
+        &self,
+        room_name: &str,
+        device_name: Option<&str>,
+        replacement: Option<&Device>,
+    ) -> HomeResult<()> {
+        let changed = |room: &str, device: &str| {
+            room == room_name && device_name.is_none_or(|name| name == device)
+        };
+        for (thermostat_room, room) in &self.rooms {
+            for (thermostat_name, device) in room.devices() {
+                let Device::Thermostat(thermostat) = device else {
+                    continue;
+                };
+                if changed(thermostat_room, thermostat_name) {
+                    continue;
+                }
+                for (path, expected) in [
+                    (thermostat.get_sensor(), "thermometer"),
+                    (thermostat.get_actuator(), "socket"),
+                ] {
+                    if changed(&path.room, &path.device)
+                        && replacement.is_none_or(|device| device.type_name() != expected)
+                    {
+                        return Err(HomeError::DeviceInUse {
+                            room: path.room.clone(),
+                            device: path.device.clone(),
+                            thermostat: format!("{thermostat_room}/{thermostat_name}"),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn room_mut(&mut self, room_name: &str) -> HomeResult<&mut Room> {
         self.rooms
             .get_mut(room_name)
-            .and_then(|room| room.remove_device(device_name))
+            .ok_or_else(|| HomeError::room_not_found(room_name))
     }
 
     pub fn get_device_by_path(&self, room_name: &str, device_name: &str) -> Option<&Device> {
@@ -83,23 +230,145 @@ impl Home {
             .and_then(|room| room.get_device_by_name_mut(device_name))
     }
 
+    pub fn rules(&self) -> &BTreeMap<String, Rule> {
+        &self.rules
+    }
+
+    pub fn get_rule(&self, rule_name: &str) -> Option<&Rule> {
+        self.rules.get(rule_name)
+    }
+
+    /// Adds the rule or replaces the one with the same name, returning the old one.
+    pub fn put_rule(&mut self, rule_name: &str, rule: Rule) -> Option<Rule> {
+        self.rules.insert(rule_name.into(), rule)
+    }
+
+    pub fn remove_rule(&mut self, rule_name: &str) -> Option<Rule> {
+        self.rules.remove(rule_name)
+    }
+
+    pub fn schedules(&self) -> &BTreeMap<String, Schedule> {
+        &self.schedules
+    }
+
+    pub fn get_schedule(&self, schedule_name: &str) -> Option<&Schedule> {
+        self.schedules.get(schedule_name)
+    }
+
+    pub fn get_schedule_mut(&mut self, schedule_name: &str) -> Option<&mut Schedule> {
+        self.schedules.get_mut(schedule_name)
+    }
+
+    /// Adds the schedule or replaces the one with the same name, returning the old one.
+    pub fn put_schedule(&mut self, schedule_name: &str, schedule: Schedule) -> Option<Schedule> {
+        self.schedules.insert(schedule_name.into(), schedule)
+    }
+
+    pub fn remove_schedule(&mut self, schedule_name: &str) -> Option<Schedule> {
+        self.schedules.remove(schedule_name)
+    }
+
+    pub fn scenes(&self) -> &BTreeMap<String, Scene> {
+        &self.scenes
+    }
+
+    pub fn get_scene(&self, scene_name: &str) -> Option<&Scene> {
+        self.scenes.get(scene_name)
+    }
+
+    /// Adds the scene or replaces the one with the same name, returning the old one.
+    pub fn put_scene(&mut self, scene_name: &str, scene: Scene) -> Option<Scene> {
+        self.scenes.insert(scene_name.into(), scene)
+    }
+
+    pub fn remove_scene(&mut self, scene_name: &str) -> Option<Scene> {
+        self.scenes.remove(scene_name)
+    }
+
+    pub fn energy(&self) -> &Energy {
+        &self.energy
+    }
+
+    /// Brings the socket and power strip outlet meters up to `now`;
+    /// called whenever a change to the devices is saved.
+    pub fn meter_energy(&mut self, now: &Zoned) {
+        let sockets = self.rooms.iter().flat_map(|(room_name, room)| {
+            room.devices().flat_map(move |(name, device)| {
+                let meters: Vec<_> = match device {
+                    Device::Socket(socket) => {
+                        let watts = if socket.is_on() {
+                            socket.get_current_power()
+                        } else {
+                            0.
+                        };
+                        vec![(name.clone(), watts)]
+                    }
+                    Device::PowerStrip(strip) => strip
+                        .channels()
+                        .map(|(number, channel)| {
+                            (
+                                format!("{name}/channels/{number}"),
+                                strip.channel_power(channel),
+                            )
+                        })
+                        .collect(),
+                    _ => Vec::new(),
+                };
+                meters
+                    .into_iter()
+                    .map(move |(meter, watts)| (room_name.as_str(), meter, watts))
+            })
+        });
+        self.energy.update(sockets, now);
+    }
+
+    /// Prices the energy used from `now` on with `tariff`, returning the previous tariff.
+    pub fn set_tariff(&mut self, tariff: Option<Tariff>, now: &Zoned) -> Option<Tariff> {
+        self.meter_energy(now);
+        self.energy.set_tariff(tariff)
+    }
+
     pub fn report(&self) -> String {
+        self.report_with(&DeviceRegistry::default(), &Zoned::now())
+    }
+
+    /// Describes every device through the kind `registry` knows it as,
+    /// and the energy used until `now`.
+    pub fn report_with(&self, registry: &DeviceRegistry, now: &Zoned) -> String {
+        let usage = self.energy.usage(now);
         let mut lines = vec![format!("General report about {}:", self.name)];
         for room_name in self.room_names_list() {
             lines.push(format!("\tIn room '{}'", room_name));
             let room = self.get_room_by_name(room_name).unwrap();
             for device in room.device_list() {
-                lines.push(format!("\t\t{:?}", device.device_dict()));
+                lines.push(format!("\t\t{:?}", registry.device_dict(device)));
+            }
+            if let Some(room_usage) = usage.rooms.get(room_name) {
+                for (socket_name, socket) in &room_usage.sockets {
+                    lines.push(format!("\t\tSocket '{socket_name}' used {}", socket.usage));
+                }
+                lines.push(format!("\tRoom '{room_name}' used {}", room_usage.usage));
             }
         }
+        lines.push(format!("Home used {}", usage.usage));
         lines.join("\n")
     }
 
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        storage::read_home(path.as_ref())
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StorageError> {
+        storage::write_home(path.as_ref(), self)
+    }
+
     pub fn restore() -> Self {
         let mut h = Self::new("For home_server");
-        h.add_room("R");
-        h.add_device("R", "S", Device::new_socket());
-        h.add_device("R", "T", Device::new_thermometer());
+        h.add_room("R").expect("new home has no rooms");
+        h.add_device("R", "S", Device::new_socket())
+            .expect("room 'R' was just added");
+        h.add_device("R", "T", Device::new_thermometer())
+            .expect("room 'R' was just added");
         h
     }
 }
@@ -107,6 +376,7 @@ impl Home {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::smart_device::Thermometer;
     #[test]
     fn test_home() {
         let home = Home::new("Home");
@@ -118,16 +388,16 @@ mod tests {
     #[test]
     fn test_add_rooms() {
         let mut home = Home::new("Home with rooms");
-        assert!(home.add_room("R1").is_some());
-        assert!(home.add_room("R2").is_some());
-        assert!(home.add_room("R1").is_none());
+        assert!(home.add_room("R1").is_ok());
+        assert!(home.add_room("R2").is_ok());
+        assert!(home.add_room("R1").is_err());
     }
 
     #[test]
     fn test_room_list() {
         let mut home = Home::new("Home with rooms");
-        home.add_room("R1");
-        home.add_room("R2");
+        home.add_room("R1").unwrap();
+        home.add_room("R2").unwrap();
         let rooms: Vec<&Room> = home.room_list().collect();
         assert!(rooms.contains(&home.get_room_by_name("R1").unwrap()));
         assert!(rooms.contains(&home.get_room_by_name("R2").unwrap()));
@@ -139,13 +409,13 @@ mod tests {
     #[test]
     fn test_remove_room() {
         let mut home = Home::new("Home to delete");
-        assert!(home.add_room("R1").is_some());
-        assert!(home.add_room("R2").is_some());
-        assert!(home.remove_room("R1").is_some());
-        assert!(home.remove_room("R2").is_some());
-        assert!(home.remove_room("R3").is_none());
-        assert!(home.remove_room("R2").is_none());
-        assert!(home.remove_room("R1").is_none());
+        assert!(home.add_room("R1").is_ok());
+        assert!(home.add_room("R2").is_ok());
+        assert!(home.remove_room("R1").is_ok());
+        assert!(home.remove_room("R2").is_ok());
+        assert!(home.remove_room("R3").is_err());
+        assert!(home.remove_room("R2").is_err());
+        assert!(home.remove_room("R1").is_err());
         assert!(home.room_names_list().next().is_none());
         assert!(home.room_list().next().is_none());
     }
@@ -153,21 +423,21 @@ mod tests {
     #[test]
     fn test_add_device() {
         let mut home = Home::new("Home for devices");
-        assert!(home.add_room("R1").is_some());
-        assert!(home.add_room("R2").is_some());
-        assert!(home.add_device("R1", "S1", Device::new_socket()).is_some());
-        assert!(home.add_device("R1", "S2", Device::new_socket()).is_some());
+        assert!(home.add_room("R1").is_ok());
+        assert!(home.add_room("R2").is_ok());
+        assert!(home.add_device("R1", "S1", Device::new_socket()).is_ok());
+        assert!(home.add_device("R1", "S2", Device::new_socket()).is_ok());
         assert!(home
             .add_device("R1", "T", Device::new_thermometer())
-            .is_some());
+            .is_ok());
         assert!(home
             .add_device("R1", "S1", Device::new_thermometer())
-            .is_none());
-        assert!(home.add_device("R2", "S1", Device::new_socket()).is_some());
+            .is_err());
+        assert!(home.add_device("R2", "S1", Device::new_socket()).is_ok());
         assert!(home
             .add_device("R2", "T1", Device::new_thermometer())
-            .is_some());
-        assert!(home.add_device("R2", "T1", Device::new_socket()).is_none());
+            .is_ok());
+        assert!(home.add_device("R2", "T1", Device::new_socket()).is_err());
         assert_eq!(
             &Device::new_socket(),
             home.get_device_by_path("R1", "S1").unwrap()
@@ -192,30 +462,117 @@ mod tests {
     #[test]
     fn test_remove_device() {
         let mut home = Home::new("Home for devices");
-        assert!(home.add_room("R1").is_some());
-        assert!(home.add_room("R2").is_some());
-        assert!(home.add_device("R1", "S1", Device::new_socket()).is_some());
-        assert!(home.add_device("R1", "S2", Device::new_socket()).is_some());
+        assert!(home.add_room("R1").is_ok());
+        assert!(home.add_room("R2").is_ok());
+        assert!(home.add_device("R1", "S1", Device::new_socket()).is_ok());
+        assert!(home.add_device("R1", "S2", Device::new_socket()).is_ok());
         assert!(home
             .add_device("R1", "T", Device::new_thermometer())
-            .is_some());
+            .is_ok());
         assert!(home
             .add_device("R1", "S1", Device::new_thermometer())
-            .is_none());
+            .is_err());
         assert_eq!(3, home.device_names_list("R1").unwrap().len());
-        assert!(home.add_device("R2", "S1", Device::new_socket()).is_some());
+        assert!(home.add_device("R2", "S1", Device::new_socket()).is_ok());
         assert!(home
             .add_device("R2", "T1", Device::new_thermometer())
-            .is_some());
+            .is_ok());
         assert_eq!(2, home.device_names_list("R2").unwrap().len());
-        assert!(home.remove_device("R1", "No device").is_none());
-        assert!(home.remove_device("R1", "S1").is_some());
-        assert!(home.remove_device("R1", "S2").is_some());
-        assert!(home.remove_device("R1", "T1").is_none());
-        assert!(home.remove_device("R1", "T").is_some());
+        assert!(home.remove_device("R1", "No device").is_err());
+        assert!(home.remove_device("R1", "S1").is_ok());
+        assert!(home.remove_device("R1", "S2").is_ok());
+        assert!(home.remove_device("R1", "T1").is_err());
+        assert!(home.remove_device("R1", "T").is_ok());
         assert!(home.device_names_list("R1").unwrap().is_empty());
-        assert!(home.remove_device("R2", "S1").is_some());
-        assert!(home.remove_device("R2", "T1").is_some());
+        assert!(home.remove_device("R2", "S1").is_ok());
+        assert!(home.remove_device("R2", "T1").is_ok());
         assert!(home.device_names_list("R2").unwrap().is_empty());
     }
+
+    #[test]
+    fn test_errors() {
+        let mut home = Home::restore();
+        assert_eq!(
+            Err(HomeError::room_not_found("X")),
+            home.add_device("X", "S", Device::new_socket()).map(|_| ())
+        );
+        assert_eq!(
+            Err(HomeError::DeviceExists {
+                room: "R".into(),
+                device: "S".into()
+            }),
+            home.add_device("R", "S", Device::new_socket()).map(|_| ())
+        );
+        assert_eq!(
+            Err(HomeError::device_not_found("R", "X")),
+            home.remove_device("R", "X").map(|_| ())
+        );
+        assert_eq!(
+            Err(HomeError::room_not_found("X")),
+            home.remove_device("X", "S").map(|_| ())
+        );
+        assert_eq!(
+            Err(HomeError::RoomExists { room: "R".into() }),
+            home.add_room("R").map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_update_device() {
+        let mut home = Home::restore();
+        let payload = DevicePayload::from_json(
+            br#"{"device": "thermometer", "temperature": 25}"#,
+            &DeviceRegistry::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            &Device::Thermometer(Thermometer::new(25.)),
+            home.update_device("R", "T", &payload).unwrap()
+        );
+        assert_eq!(
+            Err(HomeError::TypeMismatch {
+                room: "R".into(),
+                device: "S".into(),
+                expected: "thermometer".into(),
+                actual: "socket".into()
+            }),
+            home.update_device("R", "S", &payload).map(|_| ())
+        );
+        assert_eq!(
+            Err(HomeError::device_not_found("R", "X")),
+            home.update_device("R", "X", &payload).map(|_| ())
+        );
+        assert_eq!(
+            Err(HomeError::room_not_found("X")),
+            home.update_device("X", "T", &payload).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_energy_report() {
+        use crate::smart_device::Socket;
+        let registry = DeviceRegistry::default();
+        let mut home = Home::restore();
+        let morning: Zoned = "2024-03-01T07:00[UTC]".parse().unwrap();
+        home.set_tariff(Some(Tariff::Flat { price: 0.5 }), &morning);
+        home.replace_device("R", "S", Socket::new(200., 5., true).into())
+            .unwrap();
+        home.meter_energy(&morning);
+        let noon: Zoned = "2024-03-01T12:00[UTC]".parse().unwrap();
+        let report = home.report_with(&registry, &noon);
+        assert!(report.contains("\t\tSocket 'S' used 5.000 kWh costing 2.50"));
+        assert!(report.contains("\tRoom 'R' used 5.000 kWh costing 2.50"));
+        assert!(report.ends_with("\nHome used 5.000 kWh costing 2.50"));
+    }
+
+    #[test]
+    fn test_climate_report() {
+        use crate::smart_device::ClimateSensor;
+        let mut home = Home::restore();
+        let sensor = ClimateSensor::new(16., 40., 500.).unwrap();
+        home.add_device("R", "C", sensor.into()).unwrap();
+        let report = home.report();
+        assert!(report.contains(r#""comfort": "cold""#), "{report}");
+        assert!(report.contains(r#""dew_point": "2.4""#), "{report}");
+    }
 }
@@ -1,48 +1,191 @@
 use actix_web::dev::Server;
-use actix_web::{web, App, HttpServer};
+use actix_web::http::header;
+use actix_web::{guard, middleware, web, App, HttpServer};
 use std::net::TcpListener;
-use tokio::sync::RwLock;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+pub mod api_v1;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod clock;
+pub mod config;
+pub mod cron;
+pub mod device_kind;
+pub mod energy;
+pub mod error;
+pub mod events;
 pub mod home;
+pub mod metrics;
+pub mod openapi;
+pub mod payload;
+pub mod problem;
+pub mod rules;
+pub mod scene;
+pub mod scheduler;
+pub mod sensors;
 pub mod smart_device;
 pub mod smart_room;
+pub mod storage;
+pub mod telemetry;
+pub mod thermostat;
 pub mod web_routes;
 
-type SmartHome = RwLock<home::Home>;
+/// The home shared by every worker, timing how long callers wait for its lock.
+pub struct SmartHome {
+    home: RwLock<home::Home>,
+    lock_wait: metrics::LockWait,
+}
+
+impl SmartHome {
+    pub fn new(home: home::Home) -> Self {
+        Self {
+            home: RwLock::new(home),
+            lock_wait: Default::default(),
+        }
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, home::Home> {
+        let start = Instant::now();
+        let home = self.home.read().await;
+        self.lock_wait.observe_read(start.elapsed());
+        home
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, home::Home> {
+        let start = Instant::now();
+        let home = self.home.write().await;
+        self.lock_wait.observe_write(start.elapsed());
+        home
+    }
+
+    pub fn lock_wait(&self) -> &metrics::LockWait {
+        &self.lock_wait
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// Answer every request except GET and HEAD with 403 Forbidden.
+    pub read_only: bool,
+    /// Device kinds accepted by the API; the built-in ones by default.
+    pub registry: device_kind::DeviceRegistry,
+    /// Time source of the scheduler; the system clock by default.
+    pub clock: Arc<dyn clock::Clock>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            read_only: false,
+            registry: Default::default(),
+            clock: Arc::new(clock::SystemClock),
+        }
+    }
+}
 
-pub fn run(listener: TcpListener, home: home::Home) -> std::io::Result<Server> {
+pub fn run(
+    listener: TcpListener,
+    home: home::Home,
+    storage: storage::Storage,
+    options: ServerOptions,
+) -> std::io::Result<Server> {
     let smart_home = web::Data::new(SmartHome::new(home));
+    let storage = web::Data::new(storage);
+    let registry = web::Data::new(options.registry.clone());
+    let bus = web::Data::new(events::EventBus::with_clock(Arc::clone(&options.clock)));
+    let telemetry = web::Data::new(telemetry::Telemetry::new(&bus));
+    let clock = web::Data::from(Arc::clone(&options.clock));
+    let metrics = web::Data::new(metrics::Metrics::default());
+    if !options.read_only {
+        let scheduler = scheduler::Scheduler::new(Arc::clone(&options.clock));
+        tokio::spawn(scheduler::run(
+            scheduler,
+            web::Data::clone(&smart_home),
+            web::Data::clone(&storage),
+            web::Data::clone(&registry),
+            web::Data::clone(&bus),
+        ));
+    }
     let server = HttpServer::new(move || {
+        let read_only = options.read_only;
         App::new()
+            .wrap(problem::errors())
+            .wrap(middleware::Logger::default())
+            .wrap(middleware::from_fn(metrics::track))
+            .configure(|cfg| {
+                if read_only {
+                    cfg.route(
+                        "/{tail:.*}",
+                        web::route()
+                            .guard(guard::Not(guard::Any(guard::Get()).or(guard::Head())))
+                            .to(web_routes::read_only),
+                    );
+                }
+            })
             .route("/", web::get().to(web_routes::greet))
             .route("/health_check", web::get().to(web_routes::health_check))
-            .route("/room_list", web::get().to(web_routes::room_list))
-            .route(
-                "/device_list/{room_name}",
-                web::get().to(web_routes::device_list),
-            )
-            .route("add_room/{room_name}", web::post().to(web_routes::add_room))
-            .route(
-                "add_device/{room_name}/{device_name}",
-                web::post().to(web_routes::add_device),
-            )
-            .route(
-                "/remove_device/{room_name}/{device_name}",
-                web::post().to(web_routes::remove_device),
-            )
-            .route(
-                "remove_room/{room_name}",
-                web::post().to(web_routes::remove_room),
-            )
-            .route(
-                "/update/{room_name}/{device_name}",
-                web::post().to(web_routes::update),
-            )
             .route("/report", web::get().to(web_routes::report))
-            .route("/{room_name}/{device_name}", web::get().to(web_routes::get_device))
+            .route("/metrics", web::get().to(metrics::metrics))
+            .route("/openapi.json", web::get().to(openapi::openapi_json))
+            .route("/docs", web::get().to(openapi::docs))
+            .service(openapi::swagger_ui())
+            .service(web::scope(api_v1::PREFIX).configure(api_v1::routes))
+            .service(
+                web::scope("")
+                    .wrap(
+                        middleware::DefaultHeaders::new()
+                            .add(("Deprecation", "true"))
+                            .add((
+                                header::LINK,
+                                format!("<{}/rooms>; rel=\"successor-version\"", api_v1::PREFIX),
+                            )),
+                    )
+                    .configure(legacy_routes),
+            )
+            .default_service(web::route().to(problem::not_found))
             .app_data(web::Data::clone(&smart_home))
+            .app_data(web::Data::clone(&storage))
+            .app_data(web::Data::clone(&registry))
+            .app_data(web::Data::clone(&bus))
+            .app_data(web::Data::clone(&telemetry))
+            .app_data(web::Data::clone(&clock))
+            .app_data(web::Data::clone(&metrics))
+            .app_data(web_routes::json_config())
+            .app_data(web_routes::query_config())
     })
     .listen(listener)?
     .run();
     Ok(server)
 }
+
+/// Verb-in-path routes kept for old clients, superseded by [`api_v1`].
+fn legacy_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/room_list", web::get().to(web_routes::room_list))
+        .route(
+            "/device_list/{room_name}",
+            web::get().to(web_routes::device_list),
+        )
+        .route("add_room/{room_name}", web::post().to(web_routes::add_room))
+        .route(
+            "add_device/{room_name}/{device_name}",
+            web::post().to(web_routes::add_device),
+        )
+        .route(
+            "/remove_device/{room_name}/{device_name}",
+            web::post().to(web_routes::remove_device),
+        )
+        .route(
+            "remove_room/{room_name}",
+            web::post().to(web_routes::remove_room),
+        )
+        .route(
+            "/update/{room_name}/{device_name}",
+            web::post().to(web_routes::update),
+        )
+        .route(
+            "/{room_name}/{device_name}",
+            web::get().to(web_routes::get_device),
+        );
+}
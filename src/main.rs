@@ -1,10 +1,44 @@
+use std::io;
 use std::net::TcpListener;
 
+use http_home::config::Config;
 use http_home::home;
-use http_home::run;
+use http_home::storage::Storage;
+use http_home::{run, ServerOptions};
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:4083")?;
-    run(listener, home::Home::restore())?.await
+    let config = match Config::from_args(std::env::args_os()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("http_home: {e}");
+            std::process::exit(2);
+        }
+    };
+    env_logger::Builder::new()
+        .filter_level(config.log_level.into())
+        .init();
+
+    let storage = Storage::file(&config.state_file);
+    let home = storage
+        .load()
+        .map_err(|e| {
+            io::Error::other(format!(
+                "cannot load state file '{}': {e}",
+                config.state_file.display()
+            ))
+        })?
+        .unwrap_or_else(home::Home::restore);
+    let listener = TcpListener::bind(config.socket_addr())?;
+    log::info!(
+        "Listening on {}, state file '{}'{}",
+        config.socket_addr(),
+        config.state_file.display(),
+        if config.read_only { ", read-only" } else { "" }
+    );
+    let options = ServerOptions {
+        read_only: config.read_only,
+        ..Default::default()
+    };
+    run(listener, home, storage, options)?.await
 }
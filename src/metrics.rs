@@ -0,0 +1,475 @@
+//! Prometheus metrics in the text exposition format, served at `/metrics`.
+//!
+//! Device values are read from the home at scrape time and labelled by room and device:
+//!
+//! ```text
+//! home_thermometer_temperature_celsius{room="R",device="T"} 20
+//! home_socket_power_watts{room="R",device="S"} 990
+//! ```
+//!
+//! Server metrics are collected as requests come in: counts and latencies per route
+//! pattern, so that device names do not multiply the series, and how long requests
+//! waited for the lock of the shared home.
+
+use crate::home::Home;
+use crate::smart_device::Device;
+use crate::SmartHome;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::ContentType;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the histogram buckets, in seconds.
+const BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.,
+];
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    /// Observations in each bucket of [`BUCKETS`], not cumulative.
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (le, count) in BUCKETS.iter().zip(self.buckets) {
+            cumulative += count;
+            sample(
+                out,
+                &format!("{name}_bucket"),
+                &join(labels, "le", &le.to_string()),
+                cumulative as f64,
+            );
+        }
+        sample(
+            out,
+            &format!("{name}_bucket"),
+            &join(labels, "le", "+Inf"),
+            self.count as f64,
+        );
+        sample(out, &format!("{name}_sum"), labels, self.sum);
+        sample(out, &format!("{name}_count"), labels, self.count as f64);
+    }
+}
+
+/// How long callers waited to lock the home, for reading and for writing.
+#[derive(Debug, Default)]
+pub struct LockWait {
+    read: Mutex<Histogram>,
+    write: Mutex<Histogram>,
+}
+
+impl LockWait {
+    pub fn observe_read(&self, waited: Duration) {
+        lock(&self.read).observe(waited);
+    }
+
+    pub fn observe_write(&self, waited: Duration) {
+        lock(&self.write).observe(waited);
+    }
+}
+
+#[derive(Debug, Default)]
+struct RouteStats {
+    statuses: BTreeMap<u16, u64>,
+    latency: Histogram,
+}
+
+/// Request counts and latencies by method and route pattern.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(String, String), RouteStats>>,
+}
+
+impl Metrics {
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let mut requests = lock(&self.requests);
+        let stats = requests.entry((method.into(), route.into())).or_default();
+        *stats.statuses.entry(status).or_default() += 1;
+        stats.latency.observe(latency);
+    }
+
+    /// Every metric in the text exposition format.
+    pub fn render(&self, home: &Home, lock_wait: &LockWait) -> String {
+        let mut out = String::new();
+        render_home(&mut out, home);
+
+        let requests = lock(&self.requests);
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "HTTP requests answered.",
+        );
+        for ((method, route), stats) in requests.iter() {
+            for (status, count) in &stats.statuses {
+                let labels = labels(&[
+                    ("method", method),
+                    ("route", route),
+                    ("status", &status.to_string()),
+                ]);
+                sample(&mut out, "http_requests_total", &labels, *count as f64);
+            }
+        }
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time taken to answer HTTP requests.",
+        );
+        for ((method, route), stats) in requests.iter() {
+            let labels = labels(&[("method", method), ("route", route)]);
+            stats
+                .latency
+                .render(&mut out, "http_request_duration_seconds", &labels);
+        }
+
+        header(
+            &mut out,
+            "home_lock_wait_seconds",
+            "histogram",
+            "Time spent waiting for the lock of the home.",
+        );
+        for (mode, histogram) in [("read", &lock_wait.read), ("write", &lock_wait.write)] {
+            lock(histogram).render(
+                &mut out,
+                "home_lock_wait_seconds",
+                &labels(&[("mode", mode)]),
+            );
+        }
+        out
+    }
+}
+
+/// A gauge with a sample for every device it applies to.
+struct DeviceGauge {
+    name: &'static str,
+    help: &'static str,
+    value: fn(&Device) -> Option<f64>,
+}
+
+const DEVICE_GAUGES: [DeviceGauge; 15] = [
+    DeviceGauge {
+        name: "home_thermometer_temperature_celsius",
+        help: "Temperature measured by a thermometer.",
+        value: |device| match device {
+            Device::Thermometer(thermometer) => Some(thermometer.get_temperature()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_climate_temperature_celsius",
+        help: "Temperature measured by a climate sensor.",
+        value: |device| match device {
+            Device::Climate(climate) => Some(climate.get_temperature()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_climate_humidity_percent",
+        help: "Relative humidity measured by a climate sensor.",
+        value: |device| match device {
+            Device::Climate(climate) => Some(climate.get_humidity()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_climate_co2_ppm",
+        help: "CO2 concentration measured by a climate sensor.",
+        value: |device| match device {
+            Device::Climate(climate) => Some(climate.get_co2()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_socket_voltage_volts",
+        help: "Voltage of a socket.",
+        value: |device| match device {
+            Device::Socket(socket) => Some(socket.get_voltage()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_socket_current_amperes",
+        help: "Current through a socket.",
+        value: |device| match device {
+            Device::Socket(socket) => Some(socket.get_current()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_socket_power_watts",
+        help: "Power drawn through a socket.",
+        value: |device| match device {
+            Device::Socket(socket) => Some(socket.get_current_power()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_socket_on",
+        help: "Whether a socket is switched on.",
+        value: |device| match device {
+            Device::Socket(socket) => Some(if socket.is_on() { 1. } else { 0. }),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_power_strip_current_amperes",
+        help: "Current drawn through the outlets of a power strip switched on.",
+        value: |device| match device {
+            Device::PowerStrip(strip) => Some(strip.load()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_power_strip_power_watts",
+        help: "Power drawn through a power strip.",
+        value: |device| match device {
+            Device::PowerStrip(strip) => Some(strip.power()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_sensor_active",
+        help: "Whether a contact is open, motion detected or a leak detector wet.",
+        value: |device| {
+            let state = device.binary_state()?;
+            Some(if state.is_active() { 1. } else { 0. })
+        },
+    },
+    DeviceGauge {
+        name: "home_light_on",
+        help: "Whether a light is switched on.",
+        value: |device| match device {
+            Device::Light(light) => Some(if light.is_on() { 1. } else { 0. }),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_light_brightness_percent",
+        help: "Brightness a light is set to.",
+        value: |device| match device {
+            Device::Light(light) => Some(light.get_brightness()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_thermostat_setpoint_celsius",
+        help: "Temperature a thermostat keeps.",
+        value: |device| match device {
+            Device::Thermostat(thermostat) => Some(thermostat.get_setpoint()),
+            _ => None,
+        },
+    },
+    DeviceGauge {
+        name: "home_thermostat_active",
+        help: "Whether a thermostat is heating or cooling.",
+        value: |device| match device {
+            Device::Thermostat(thermostat) => Some(if thermostat.is_active() { 1. } else { 0. }),
+            _ => None,
+        },
+    },
+];
+
+fn render_home(out: &mut String, home: &Home) {
+    let mut rooms: Vec<_> = home
+        .room_names_list()
+        .filter_map(|name| Some((name, home.get_room_by_name(name)?)))
+        .collect();
+    rooms.sort_by_key(|(name, _)| *name);
+    let devices: Vec<_> = rooms
+        .iter()
+        .flat_map(|(room_name, room)| {
+            let mut devices: Vec<_> = room.devices().collect();
+            devices.sort_by_key(|(name, _)| *name);
+            devices.into_iter().map(move |(name, device)| {
+                (labels(&[("room", room_name), ("device", name)]), device)
+            })
+        })
+        .collect();
+
+    header(out, "home_rooms", "gauge", "Rooms in the home.");
+    sample(out, "home_rooms", "", rooms.len() as f64);
+    header(out, "home_devices", "gauge", "Devices in the home.");
+    sample(out, "home_devices", "", devices.len() as f64);
+
+    for gauge in &DEVICE_GAUGES {
+        header(out, gauge.name, "gauge", gauge.help);
+        for (labels, device) in &devices {
+            if let Some(value) = (gauge.value)(device) {
+                sample(out, gauge.name, labels, value);
+            }
+        }
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: f64) {
+    let value = match value {
+        value if value.is_nan() => "NaN".to_string(),
+        f64::INFINITY => "+Inf".to_string(),
+        f64::NEG_INFINITY => "-Inf".to_string(),
+        value => value.to_string(),
+    };
+    if labels.is_empty() {
+        let _ = writeln!(out, "{name} {value}");
+    } else {
+        let _ = writeln!(out, "{name}{{{labels}}} {value}");
+    }
+}
+
+/// `name="value"` pairs separated by commas, without the braces.
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn join(labels: &str, name: &str, value: &str) -> String {
+    let label = self::labels(&[(name, value)]);
+    if labels.is_empty() {
+        label
+    } else {
+        format!("{labels},{label}")
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Middleware counting every request by method, route pattern and status.
+pub async fn track(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let start = Instant::now();
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let res = next.call(req).await;
+    if let Some(metrics) = metrics {
+        let (route, status) = match &res {
+            Ok(res) => (res.request().match_pattern(), res.status()),
+            Err(e) => (None, e.as_response_error().status_code()),
+        };
+        let route = route.as_deref().unwrap_or("unmatched");
+        metrics.observe_request(&method, route, status.as_u16(), start.elapsed());
+    }
+    res
+}
+
+#[utoipa::path(
+    get, path = "/metrics", tag = "server",
+    responses((status = 200, description = "Device values and server metrics for Prometheus", body = String, content_type = "text/plain"))
+)]
+pub async fn metrics(home: web::Data<SmartHome>, metrics: web::Data<Metrics>) -> HttpResponse {
+    let text = metrics.render(&*home.read().await, home.lock_wait());
+    HttpResponse::Ok()
+        .insert_header(ContentType(CONTENT_TYPE.parse().expect("valid mime type")))
+        .body(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_home() {
+        let mut home = Home::restore();
+        home.add_room("a \"quoted\" room").unwrap();
+        let text = Metrics::default().render(&home, &LockWait::default());
+        assert!(text.contains("# TYPE home_rooms gauge\nhome_rooms 2\n"));
+        assert!(text.contains("home_devices 2\n"));
+        assert!(text.contains("home_thermometer_temperature_celsius{room=\"R\",device=\"T\"} 20\n"));
+        assert!(text.contains("home_socket_voltage_volts{room=\"R\",device=\"S\"} 220\n"));
+        assert!(text.contains("home_socket_on{room=\"R\",device=\"S\"} 0\n"));
+        assert_eq!(
+            "room=\"a \\\"quoted\\\" room\"",
+            labels(&[("room", "a \"quoted\" room")])
+        );
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_secs(60));
+        let mut out = String::new();
+        histogram.render(&mut out, "wait_seconds", "mode=\"read\"");
+        assert!(out.contains("wait_seconds_bucket{mode=\"read\",le=\"0.001\"} 0\n"));
+        assert!(out.contains("wait_seconds_bucket{mode=\"read\",le=\"0.005\"} 1\n"));
+        assert!(out.contains("wait_seconds_bucket{mode=\"read\",le=\"0.025\"} 2\n"));
+        assert!(out.contains("wait_seconds_bucket{mode=\"read\",le=\"10\"} 2\n"));
+        assert!(out.contains("wait_seconds_bucket{mode=\"read\",le=\"+Inf\"} 3\n"));
+        assert!(out.contains("wait_seconds_count{mode=\"read\"} 3\n"));
+    }
+
+    #[actix_web::test]
+    async fn test_metrics_endpoint() {
+        use actix_web::{middleware, test, App};
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware::from_fn(track))
+                .app_data(web::Data::new(SmartHome::new(Home::restore())))
+                .app_data(web::Data::new(Metrics::default()))
+                .route("/rooms/{room_name}", web::get().to(HttpResponse::Ok))
+                .route("/metrics", web::get().to(metrics)),
+        )
+        .await;
+        for uri in ["/rooms/R", "/rooms/X", "/nowhere"] {
+            test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        }
+        let req = test::TestRequest::get().uri("/metrics").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(
+            CONTENT_TYPE,
+            resp.headers()
+                .get("content-type")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+        let text = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        assert!(text.contains(
+            "http_requests_total{method=\"GET\",route=\"/rooms/{room_name}\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains(
+            "http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1\n"
+        ));
+        assert!(text.contains(
+            "http_request_duration_seconds_count{method=\"GET\",route=\"/rooms/{room_name}\"} 2\n"
+        ));
+        assert!(text.contains("home_lock_wait_seconds_count{mode=\"read\"} 1\n"));
+    }
+}
@@ -0,0 +1,182 @@
+//! OpenAPI 3 description of every route, served at `/openapi.json`,
+//! and an interactive Swagger UI page at `/docs/`. The Swagger UI assets are
+//! built into the binary, so the page works without access to the internet.
+//!
+//! The document is generated from the `#[utoipa::path]` attributes of the handlers
+//! and the payload types, so it only has to be touched when a route is added.
+
+use crate::{api_v1, metrics, web_routes};
+use actix_web::http::header;
+use actix_web::HttpResponse;
+use utoipa::openapi::{Deprecated, OpenApi as OpenApiDocument};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::{Config, SwaggerUi};
+
+#[derive(Debug, OpenApi)]
+#[openapi(
+    info(title = "http_home", description = "Smart home server."),
+    paths(
+        web_routes::greet,
+        web_routes::health_check,
+        web_routes::report,
+        metrics::metrics,
+        web_routes::room_list,
+        web_routes::device_list,
+        web_routes::add_room,
+        web_routes::add_device,
+        web_routes::remove_device,
+        web_routes::remove_room,
+        web_routes::update,
+        web_routes::get_device,
+        openapi_json,
+        docs,
+    ),
+    nest((path = "/api/v1", api = api_v1::ApiDoc)),
+    modifiers(&DeprecateLegacy)
+)]
+pub struct ApiDoc;
+
+/// Marks the verb-in-path routes as deprecated in favour of `/api/v1`.
+struct DeprecateLegacy;
+
+impl Modify for DeprecateLegacy {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        for item in openapi.paths.paths.values_mut() {
+            for operation in [&mut item.get, &mut item.post].into_iter().flatten() {
+                if operation
+                    .tags
+                    .as_ref()
+                    .is_some_and(|tags| tags.iter().any(|tag| tag == "legacy"))
+                {
+                    operation.deprecated = Some(Deprecated::True);
+                }
+            }
+        }
+    }
+}
+
+/// Path the Swagger UI is served under.
+const DOCS_PATH: &str = "/docs/";
+
+/// Swagger UI reading `/openapi.json`, at `/docs/`.
+pub fn swagger_ui() -> SwaggerUi {
+    SwaggerUi::new(format!("{DOCS_PATH}{{_:.*}}")).config(Config::from("/openapi.json"))
+}
+
+#[utoipa::path(
+    get, path = "/openapi.json", tag = "server",
+    responses((status = 200, description = "This OpenAPI document", content_type = "application/json"))
+)]
+pub async fn openapi_json() -> HttpResponse {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[utoipa::path(
+    get, path = "/docs", tag = "server",
+    responses((status = 308, description = "Redirect to the Swagger UI for this API at `/docs/`", headers(("Location" = String))))
+)]
+pub async fn docs() -> HttpResponse {
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, DOCS_PATH))
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `(method, path)` of every route registered with `.route(...)` or `web::resource(...)`
+    /// in `source`, with `prefix` prepended.
+    fn registered_routes(source: &str, prefix: &str) -> Vec<(String, String)> {
+        let mut routes = Vec::new();
+        let mut resource = None;
+        let mut rest = source;
+        while let Some(start) = rest.find(['.', 'w']) {
+            rest = &rest[start..];
+            if let Some(tail) = rest.strip_prefix("web::resource(") {
+                resource = Some(string_literal(tail));
+            } else if let Some(tail) = rest.strip_prefix(".route(") {
+                let tail = tail.trim_start();
+                let path = if tail.starts_with('"') {
+                    Some(string_literal(tail))
+                } else {
+                    resource.clone()
+                };
+                let method = tail.split("web::").nth(1).and_then(|s| s.split('(').next());
+                if let (Some(path), Some(method)) = (path, method) {
+                    // `web::route()` is the read-only guard catching every path.
+                    if method != "route" {
+                        let path = format!("{prefix}/{}", path.trim_start_matches('/'));
+                        routes.push((method.to_string(), path));
+                    }
+                }
+            }
+            rest = &rest[1..];
+        }
+        routes
+    }
+
+    fn string_literal(s: &str) -> String {
+        s.split('"').nth(1).unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn test_every_route_is_documented() {
+        let mut routes = registered_routes(include_str!("lib.rs"), "");
+        routes.extend(registered_routes(include_str!("api_v1.rs"), api_v1::PREFIX));
+        assert!(routes.len() > 20, "found only {routes:?}");
+
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let undocumented: Vec<_> = routes
+            .iter()
+            .filter(|(method, path)| doc["paths"][path][method].is_null())
+            .collect();
+        assert!(
+            undocumented.is_empty(),
+            "undocumented routes {undocumented:?}"
+        );
+    }
+
+    #[test]
+    fn test_document() {
+        let doc = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+        assert_eq!(true, doc["paths"]["/room_list"]["get"]["deprecated"]);
+        assert!(doc["paths"]["/api/v1/rooms"]["get"]["deprecated"].is_null());
+        for schema in ["DevicePayload", "Problem", "KindSchema", "RoomView"] {
+            assert!(
+                doc["components"]["schemas"][schema].is_object(),
+                "no schema {schema}"
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_docs_are_served_locally() {
+        use actix_web::{test, web, App};
+
+        let app = test::init_service(
+            App::new()
+                .route("/docs", web::get().to(docs))
+                .service(swagger_ui()),
+        )
+        .await;
+        let resp =
+            test::call_service(&app, test::TestRequest::get().uri("/docs").to_request()).await;
+        assert_eq!(
+            actix_web::http::StatusCode::PERMANENT_REDIRECT,
+            resp.status()
+        );
+        assert_eq!(DOCS_PATH, resp.headers().get(header::LOCATION).unwrap());
+
+        let req = test::TestRequest::get().uri(DOCS_PATH).to_request();
+        let page = String::from_utf8(test::call_and_read_body(&app, req).await.to_vec()).unwrap();
+        assert!(page.contains("swagger-ui"));
+        assert!(!page.contains("unpkg.com"));
+        let req = test::TestRequest::get()
+            .uri("/docs/swagger-initializer.js")
+            .to_request();
+        let script = test::call_and_read_body(&app, req).await;
+        assert!(String::from_utf8_lossy(&script).contains("/openapi.json"));
+    }
+}
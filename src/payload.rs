@@ -0,0 +1,1294 @@
+//! Typed device parameters sent by clients to create or update a device.
+//!
+//! The same [`DevicePayload`] is built either from a JSON body
+//! (`{"device": "socket", "on": true, "voltage": 220, "current": 0.5}`)
+//! or, for old clients, from a query string (`device=socket&state=on&voltage=220&current=0.5`).
+//! Kinds registered in a [`DeviceRegistry`] take their properties at the top level
+//! in both forms and are checked against the kind's fields.
+
+use crate::device_kind::{
+    self, ClimateKind, ContactKind, DeviceKind, DeviceRegistry, LeakKind, LightKind, MotionKind,
+    PowerStripKind, Properties, SocketKind, ThermometerKind, ThermostatKind, BUILTIN_KINDS,
+};
+use crate::error::{HomeError, HomeResult};
+use crate::smart_device::{
+    ClimateSensor, Contact, Device, DevicePath, Generic, Leak, Light, Motion, PowerStrip,
+    PropertySchema, PropertyValue, Rgb, Socket, Thermometer, Thermostat, ThermostatMode,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(tag = "device", rename_all = "lowercase")]
+pub enum DevicePayload {
+    Socket(SocketPayload),
+    #[serde(rename = "power_strip")]
+    PowerStrip(PowerStripPayload),
+    Thermometer(ThermometerPayload),
+    Climate(ClimatePayload),
+    Contact(ContactPayload),
+    Motion(MotionPayload),
+    Leak(LeakPayload),
+    Light(LightPayload),
+    Thermostat(ThermostatPayload),
+    Generic(GenericPayload),
+    /// A kind from the registry; never sent by clients in this form.
+    #[serde(skip_serializing)]
+    Custom(CustomPayload),
+}
+
+/// Fields absent from the payload are left unchanged by an update,
+/// but all of them are required to create a socket.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SocketPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voltage: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<f64>,
+}
+
+/// `voltage`, `max_current` and `channels`, the number of outlets, are required to create
+/// a power strip, whose outlets are then all off and draw nothing. Outlet N is switched
+/// with `channel_N` and what it draws set with `channel_N_current`, in amperes.
+/// The number of outlets cannot be changed by an update, and a change leaving the outlets
+/// switched on drawing more than `max_current` is refused.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+#[serde(into = "Properties")]
+pub struct PowerStripPayload {
+    /// Volts.
+    pub voltage: Option<f64>,
+    /// Amperes.
+    pub max_current: Option<f64>,
+    pub channels: Option<usize>,
+    /// Changes to outlets by number, sent as `channel_N` and `channel_N_current`.
+    #[schema(ignore)]
+    pub outlets: BTreeMap<usize, ChannelPayload>,
+}
+
+/// A change to one outlet of a power strip.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ChannelPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<bool>,
+    /// Amperes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ThermometerPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+}
+
+/// All but `voc` are required to create a climate sensor; `dew_point` and `comfort`
+/// are derived from them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ClimatePayload {
+    /// Degrees Celsius.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    /// Relative humidity in percent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f64>,
+    /// Parts per million.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub co2: Option<f64>,
+    /// VOC index.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voc: Option<f64>,
+}
+
+/// When the sensor last changed is recorded by the server, never sent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ContactPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open: Option<bool>,
+}
+
+/// Reporting `detected: true` again while detected restarts the `reset_after` timeout.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MotionPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detected: Option<bool>,
+    /// Seconds after the last trigger a detection clears by itself; 0 never.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset_after: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LeakPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wet: Option<bool>,
+}
+
+/// Only `on` is required to create a light, which is then fully bright and plain white.
+/// `color_temperature` and `color` cannot be set together; setting one clears the other.
+/// `transition` is how many seconds the change fades in over, none when absent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct LightPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub on: Option<bool>,
+    /// Percent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<f64>,
+    /// Kelvin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_temperature: Option<f64>,
+    /// `#rrggbb`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// Seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transition: Option<f64>,
+}
+
+/// `setpoint`, `sensor` and `actuator` are required to create a thermostat, which heats
+/// by default. `sensor` must be the `room/device` path of a thermometer in the home
+/// and `actuator` that of a socket; the home checks them when the thermostat is saved.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ThermostatPayload {
+    /// Degrees Celsius.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub setpoint: Option<f64>,
+    /// Degrees Celsius either side of the setpoint.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hysteresis: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<ThermostatMode>,
+    /// `room/device` of a thermometer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sensor: Option<String>,
+    /// `room/device` of a socket.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actuator: Option<String>,
+}
+
+/// `kind` and `schema` are fixed when the device is created;
+/// an update may only repeat the same `kind` and must not carry a `schema`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GenericPayload {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub properties: BTreeMap<String, PropertyValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<BTreeMap<String, PropertySchema>>,
+}
+
+/// Properties of a registered kind, already checked against its fields.
+#[derive(Clone)]
+pub struct CustomPayload {
+    pub kind: Arc<dyn DeviceKind>,
+    pub properties: Properties,
+}
+
+/// The fields of a request creating or updating a device, without `device`.
+#[derive(Debug, Clone, Copy)]
+pub enum RequestFields<'a> {
+    /// The members of a JSON body, as an object.
+    Json(&'a Value),
+    /// The parameters of a legacy query string.
+    Query(&'a HashMap<&'a str, &'a str>),
+}
+
+impl RequestFields<'_> {
+    /// The fields as flat properties checked against the fields of `kind`.
+    pub fn properties(&self, kind: &dyn DeviceKind) -> HomeResult<Properties> {
+        match self {
+            RequestFields::Json(value) => {
+                device_kind::check_properties(kind, json_properties((*value).clone())?)
+            }
+            RequestFields::Query(data) => query_properties(kind, data),
+        }
+    }
+
+    /// A JSON body deserialized into the payload type of a built-in kind
+    /// once aliases such as `state` are renamed to the fields they stand for,
+    /// or a query string checked against `kind` and read with `from_properties`.
+    pub(crate) fn build<T: for<'de> Deserialize<'de>>(
+        &self,
+        kind: &dyn DeviceKind,
+        from_properties: fn(&Properties) -> T,
+    ) -> HomeResult<T> {
+        match self {
+            RequestFields::Json(value) => fields_from_json(canonical_names(kind, value)),
+            RequestFields::Query(data) => Ok(from_properties(&query_properties(kind, data)?)),
+        }
+    }
+}
+
+impl fmt::Debug for CustomPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CustomPayload")
+            .field("kind", &self.kind.name())
+            .field("properties", &self.properties)
+            .finish()
+    }
+}
+
+impl PartialEq for CustomPayload {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind.name() == other.kind.name() && self.properties == other.properties
+    }
+}
+
+impl SocketPayload {
+    fn properties(&self) -> Properties {
+        let mut properties = Properties::new();
+        if let Some(on) = self.on {
+            properties.insert("on".into(), PropertyValue::Bool(on));
+        }
+        for (name, value) in [("voltage", self.voltage), ("current", self.current)] {
+            if let Some(value) = value {
+                properties.insert(name.into(), PropertyValue::Number(value));
+            }
+        }
+        properties
+    }
+}
+
+impl PowerStripPayload {
+    fn properties(&self) -> Properties {
+        let mut properties: Properties = [
+            ("voltage", self.voltage),
+            ("max_current", self.max_current),
+            ("channels", self.channels.map(|count| count as f64)),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.to_string(), PropertyValue::Number(value?))))
+        .collect();
+        for (number, outlet) in &self.outlets {
+            if let Some(on) = outlet.on {
+                properties.insert(PowerStrip::channel_field(*number), PropertyValue::Bool(on));
+            }
+            if let Some(current) = outlet.current {
+                properties.insert(
+                    PowerStrip::channel_current_field(*number),
+                    PropertyValue::Number(current),
+                );
+            }
+        }
+        properties
+    }
+
+    /// Sets the fields present on `strip`, leaving it untouched on error
+    /// or if the outlets switched on would draw more than it is rated for.
+    fn apply(&self, strip: &mut PowerStrip) -> HomeResult<()> {
+        let mut updated = strip.clone();
+        if let Some(voltage) = self.voltage {
+            updated.set_voltage(voltage)?;
+        }
+        if self
+            .channels
+            .is_some_and(|count| count != strip.channel_count())
+        {
+            return Err(invalid_field("channels", "cannot be changed by an update"));
+        }
+        for (number, outlet) in &self.outlets {
+            if let Some(current) = outlet.current {
+                updated.set_current(*number, current)?;
+            }
+            if let Some(on) = outlet.on {
+                updated.switch(*number, on)?;
+            }
+        }
+        // Rated against the outlets as they are after this update.
+        match self.max_current {
+            Some(max_current) => updated.set_max_current(max_current)?,
+            None => updated.check_load()?,
+        }
+        *strip = updated;
+        Ok(())
+    }
+}
+
+impl From<PowerStripPayload> for Properties {
+    fn from(payload: PowerStripPayload) -> Self {
+        payload.properties()
+    }
+}
+
+impl ThermometerPayload {
+    fn properties(&self) -> Properties {
+        self.temperature
+            .map(|t| ("temperature".to_string(), PropertyValue::Number(t)))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl ClimatePayload {
+    fn properties(&self) -> Properties {
+        [
+            ("temperature", self.temperature),
+            ("humidity", self.humidity),
+            ("co2", self.co2),
+            ("voc", self.voc),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.to_string(), PropertyValue::Number(value?))))
+        .collect()
+    }
+
+    /// Sets the fields present on `sensor`, leaving it untouched on error.
+    fn apply(&self, sensor: &mut ClimateSensor) -> HomeResult<()> {
+        let mut updated = sensor.clone();
+        if let Some(temperature) = self.temperature {
+            updated.set_temperature(temperature)?;
+        }
+        if let Some(humidity) = self.humidity {
+            updated.set_humidity(humidity)?;
+        }
+        if let Some(co2) = self.co2 {
+            updated.set_co2(co2)?;
+        }
+        if let Some(voc) = self.voc {
+            updated.set_voc(voc)?;
+        }
+        *sensor = updated;
+        Ok(())
+    }
+}
+
+impl ContactPayload {
+    fn properties(&self) -> Properties {
+        self.open
+            .map(|open| ("open".to_string(), PropertyValue::Bool(open)))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl MotionPayload {
+    fn properties(&self) -> Properties {
+        let mut properties = Properties::new();
+        if let Some(detected) = self.detected {
+            properties.insert("detected".into(), PropertyValue::Bool(detected));
+        }
+        if let Some(seconds) = self.reset_after {
+            properties.insert("reset_after".into(), PropertyValue::Number(seconds));
+        }
+        properties
+    }
+
+    /// Sets the fields present on `motion`, leaving it untouched on error.
+    fn apply(&self, motion: &mut Motion) -> HomeResult<()> {
+        let mut updated = motion.clone();
+        if let Some(seconds) = self.reset_after {
+            updated.set_reset_after(seconds)?;
+        }
+        if let Some(detected) = self.detected {
+            updated.set_detected(detected);
+        }
+        *motion = updated;
+        Ok(())
+    }
+}
+
+impl LeakPayload {
+    fn properties(&self) -> Properties {
+        self.wet
+            .map(|wet| ("wet".to_string(), PropertyValue::Bool(wet)))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl LightPayload {
+    fn properties(&self) -> Properties {
+        let mut properties = Properties::new();
+        if let Some(on) = self.on {
+            properties.insert("on".into(), PropertyValue::Bool(on));
+        }
+        for (name, value) in [
+            ("brightness", self.brightness),
+            ("color_temperature", self.color_temperature),
+            ("transition", self.transition),
+        ] {
+            if let Some(value) = value {
+                properties.insert(name.into(), PropertyValue::Number(value));
+            }
+        }
+        if let Some(color) = &self.color {
+            properties.insert("color".into(), PropertyValue::Text(color.clone()));
+        }
+        properties
+    }
+
+    fn color(&self) -> HomeResult<Option<Rgb>> {
+        if self.color.is_some() && self.color_temperature.is_some() {
+            return Err(invalid_field(
+                "color",
+                "cannot be set together with color_temperature",
+            ));
+        }
+        self.color
+            .as_deref()
+            .map(|color| {
+                color
+                    .parse()
+                    .map_err(|e: String| invalid_field("color", &e))
+            })
+            .transpose()
+    }
+
+    /// Sets the fields present on `light`, leaving it untouched on error.
+    fn apply(&self, light: &mut Light) -> HomeResult<()> {
+        let mut updated = light.clone();
+        if let Some(on) = self.on {
+            updated.switch(on);
+        }
+        if let Some(brightness) = self.brightness {
+            updated.set_brightness(brightness)?;
+        }
+        if let Some(kelvin) = self.color_temperature {
+            updated.set_color_temperature(kelvin)?;
+        }
+        if let Some(color) = self.color()? {
+            updated.set_color(color);
+        }
+        updated.set_transition(self.transition.unwrap_or_default())?;
+        *light = updated;
+        Ok(())
+    }
+}
+
+impl ThermostatPayload {
+    fn properties(&self) -> Properties {
+        let mut properties = Properties::new();
+        for (name, value) in [("setpoint", self.setpoint), ("hysteresis", self.hysteresis)] {
+            if let Some(value) = value {
+                properties.insert(name.into(), PropertyValue::Number(value));
+            }
+        }
+        if let Some(mode) = self.mode {
+            properties.insert("mode".into(), PropertyValue::Text(mode.to_string()));
+        }
+        for (name, path) in [("sensor", &self.sensor), ("actuator", &self.actuator)] {
+            if let Some(path) = path {
+                properties.insert(name.into(), PropertyValue::Text(path.clone()));
+            }
+        }
+        properties
+    }
+
+    fn paths(&self) -> HomeResult<(Option<DevicePath>, Option<DevicePath>)> {
+        let parse = |field: &str, path: &Option<String>| {
+            path.as_deref()
+                .map(|path| path.parse().map_err(|e: String| invalid_field(field, &e)))
+                .transpose()
+        };
+        Ok((
+            parse("sensor", &self.sensor)?,
+            parse("actuator", &self.actuator)?,
+        ))
+    }
+
+    /// Sets the fields present on `thermostat`, leaving it untouched on error.
+    fn apply(&self, thermostat: &mut Thermostat) -> HomeResult<()> {
+        let mut updated = thermostat.clone();
+        if let Some(setpoint) = self.setpoint {
+            updated.set_setpoint(setpoint)?;
+        }
+        if let Some(hysteresis) = self.hysteresis {
+            updated.set_hysteresis(hysteresis)?;
+        }
+        if let Some(mode) = self.mode {
+            updated.set_mode(mode);
+        }
+        let (sensor, actuator) = self.paths()?;
+        if let Some(sensor) = sensor {
+            updated.set_sensor(sensor);
+        }
+        if let Some(actuator) = actuator {
+            updated.set_actuator(actuator);
+        }
+        *thermostat = updated;
+        Ok(())
+    }
+}
+
+impl DevicePayload {
+    /// Parses a JSON body, reporting the offending field on error.
+    pub fn from_json(body: &[u8], registry: &DeviceRegistry) -> HomeResult<Self> {
+        let mut value: Value = serde_json::from_slice(body).map_err(HomeError::bad_json)?;
+        let device = match value.as_object_mut().map(|object| object.remove("device")) {
+            Some(Some(Value::String(device))) => device,
+            Some(Some(_)) => return Err(invalid_field("device", "must be a string")),
+            Some(None) => return Err(invalid_field("device", "missing field")),
+            None => return Err(HomeError::bad_json("expected an object")),
+        };
+        Self::from_fields(&device, &RequestFields::Json(&value), registry)
+    }
+
+    /// The payload setting `properties` on a device of the given type,
+    /// as a JSON body with those fields would. Aliases such as `state` are accepted.
+    pub fn from_properties(
+        device_type: &str,
+        properties: &Properties,
+        registry: &DeviceRegistry,
+    ) -> HomeResult<Self> {
+        let fields = properties.iter().map(|(name, value)| {
+            let value = serde_json::to_value(value).unwrap_or_default();
+            (registry.field_name(device_type, name), value)
+        });
+        let mut body = serde_json::Map::new();
+        body.insert("device".into(), device_type.into());
+        if device_type == "generic" {
+            body.insert("properties".into(), Value::Object(fields.collect()));
+        } else {
+            body.extend(fields);
+        }
+        Self::from_json(Value::Object(body).to_string().as_bytes(), registry)
+    }
+
+    /// Parses the legacy query string dict, e.g. `device=socket&state=on`.
+    pub fn from_query(data: &HashMap<&str, &str>, registry: &DeviceRegistry) -> HomeResult<Self> {
+        let device = data
+            .get("device")
+            .ok_or_else(|| invalid_field("device", "missing field"))?;
+        Self::from_fields(device, &RequestFields::Query(data), registry)
+    }
+
+    /// Like [`DevicePayload::from_query`], but read-only keys are ignored instead of
+    /// refused, so that an old client may send back the dict it got, `power` and all.
+    pub fn from_legacy_query(
+        data: &HashMap<&str, &str>,
+        registry: &DeviceRegistry,
+    ) -> HomeResult<Self> {
+        let read_only: Vec<_> = data
+            .get("device")
+            .and_then(|device| registry.get(device))
+            .map(|kind| kind.fields())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|spec| spec.read_only)
+            .collect();
+        let data = data
+            .iter()
+            .filter(|(name, _)| !read_only.iter().any(|spec| spec.is_named(name)))
+            .map(|(name, value)| (*name, *value))
+            .collect();
+        Self::from_query(&data, registry)
+    }
+
+    /// Lets the registered kind named `device` build the payload. A generic payload
+    /// whose `kind` is a registered kind is checked against that kind's fields.
+    fn from_fields(
+        device: &str,
+        fields: &RequestFields,
+        registry: &DeviceRegistry,
+    ) -> HomeResult<Self> {
+        let kind = kind_named(registry, device)?;
+        let payload = match kind.payload(fields)? {
+            Some(payload) => payload,
+            None => custom_payload(Arc::clone(&kind), fields.properties(kind.as_ref())?),
+        };
+        match payload {
+            DevicePayload::Generic(GenericPayload {
+                kind: Some(name),
+                properties,
+                schema,
+            }) if !BUILTIN_KINDS.contains(&name.as_str()) && registry.get(&name).is_some() => {
+                if schema.is_some() {
+                    return Err(invalid_field(
+                        "schema",
+                        &format!("'{name}' devices have the fields of their kind"),
+                    ));
+                }
+                let kind = kind_named(registry, &name)?;
+                let properties = device_kind::check_properties(kind.as_ref(), properties)?;
+                Ok(custom_payload(kind, properties))
+            }
+            payload => Ok(payload),
+        }
+    }
+
+    pub fn device_type(&self) -> &str {
+        match self {
+            DevicePayload::Socket(_) => "socket",
+            DevicePayload::PowerStrip(_) => "power_strip",
+            DevicePayload::Thermometer(_) => "thermometer",
+            DevicePayload::Climate(_) => "climate",
+            DevicePayload::Contact(_) => "contact",
+            DevicePayload::Motion(_) => "motion",
+            DevicePayload::Leak(_) => "leak",
+            DevicePayload::Light(_) => "light",
+            DevicePayload::Thermostat(_) => "thermostat",
+            DevicePayload::Generic(_) => "generic",
+            DevicePayload::Custom(custom) => custom.kind.name(),
+        }
+    }
+
+    /// Checks the values against the fields of the payload's kind.
+    pub fn validate(&self) -> HomeResult<()> {
+        match self {
+            DevicePayload::Socket(socket) => {
+                device_kind::check_properties(&SocketKind, socket.properties()).map(drop)
+            }
+            DevicePayload::PowerStrip(strip) => {
+                device_kind::check_properties(&PowerStripKind, strip.properties()).map(drop)
+            }
+            DevicePayload::Thermometer(thermometer) => {
+                device_kind::check_properties(&ThermometerKind, thermometer.properties()).map(drop)
+            }
+            DevicePayload::Climate(climate) => {
+                device_kind::check_properties(&ClimateKind, climate.properties()).map(drop)
+            }
+            DevicePayload::Contact(contact) => {
+                device_kind::check_properties(&ContactKind, contact.properties()).map(drop)
+            }
+            DevicePayload::Motion(motion) => {
+                device_kind::check_properties(&MotionKind, motion.properties()).map(drop)
+            }
+            DevicePayload::Leak(leak) => {
+                device_kind::check_properties(&LeakKind, leak.properties()).map(drop)
+            }
+            DevicePayload::Light(light) => {
+                device_kind::check_properties(&LightKind, light.properties())?;
+                light.color().map(drop)
+            }
+            DevicePayload::Thermostat(thermostat) => {
+                device_kind::check_properties(&ThermostatKind, thermostat.properties())?;
+                thermostat.paths().map(drop)
+            }
+            DevicePayload::Generic(_) | DevicePayload::Custom(_) => Ok(()),
+        }
+    }
+
+    /// Builds a new device; every field of its type is required.
+    pub fn into_device(self) -> HomeResult<Device> {
+        self.validate()?;
+        match self {
+            DevicePayload::Socket(socket) => Ok(Socket::new(
+                required("voltage", socket.voltage)?,
+                required("current", socket.current)?,
+                required("on", socket.on)?,
+            )
+            .into()),
+            DevicePayload::PowerStrip(payload) => {
+                let mut strip = PowerStrip::new(
+                    required("voltage", payload.voltage)?,
+                    required("max_current", payload.max_current)?,
+                    required("channels", payload.channels)?,
+                )?;
+                payload.apply(&mut strip)?;
+                Ok(strip.into())
+            }
+            DevicePayload::Thermometer(thermometer) => {
+                Ok(Thermometer::new(required("temperature", thermometer.temperature)?).into())
+            }
+            DevicePayload::Climate(payload) => {
+                let mut sensor = ClimateSensor::new(
+                    required("temperature", payload.temperature)?,
+                    required("humidity", payload.humidity)?,
+                    required("co2", payload.co2)?,
+                )?;
+                payload.apply(&mut sensor)?;
+                Ok(sensor.into())
+            }
+            DevicePayload::Contact(contact) => {
+                Ok(Contact::new(required("open", contact.open)?).into())
+            }
+            DevicePayload::Motion(payload) => {
+                let mut motion = Motion::new(required("detected", payload.detected)?);
+                payload.apply(&mut motion)?;
+                Ok(motion.into())
+            }
+            DevicePayload::Leak(leak) => Ok(Leak::new(required("wet", leak.wet)?).into()),
+            DevicePayload::Light(payload) => {
+                let mut light = Light::new(required("on", payload.on)?, Light::MAX_BRIGHTNESS)?;
+                payload.apply(&mut light)?;
+                Ok(light.into())
+            }
+            DevicePayload::Thermostat(payload) => {
+                let (sensor, actuator) = payload.paths()?;
+                let mut thermostat = Thermostat::new(
+                    required("setpoint", payload.setpoint)?,
+                    required("sensor", sensor)?,
+                    required("actuator", actuator)?,
+                )?;
+                payload.apply(&mut thermostat)?;
+                Ok(thermostat.into())
+            }
+            DevicePayload::Generic(generic) => Ok(Generic::new(
+                &required("kind", generic.kind)?,
+                generic.properties,
+                generic.schema,
+            )?
+            .into()),
+            DevicePayload::Custom(custom) => {
+                device_kind::check_required(custom.kind.as_ref(), &custom.properties)?;
+                custom.kind.create(custom.properties)
+            }
+        }
+    }
+
+    /// Changes the fields present in the payload, refusing a device of another type.
+    pub fn apply_to(&self, device: &mut Device) -> HomeResult<()> {
+        self.validate()?;
+        match (self, device) {
+            (DevicePayload::Custom(custom), device) => {
+                custom.kind.apply(device, custom.properties.clone())
+            }
+            (DevicePayload::Socket(payload), Device::Socket(socket)) => {
+                if let Some(on) = payload.on {
+                    socket.switch(on);
+                }
+                if let Some(voltage) = payload.voltage {
+                    socket.set_voltage(voltage);
+                }
+                if let Some(current) = payload.current {
+                    socket.set_current(current);
+                }
+                Ok(())
+            }
+            (DevicePayload::PowerStrip(payload), Device::PowerStrip(strip)) => payload.apply(strip),
+            (DevicePayload::Thermometer(payload), Device::Thermometer(thermometer)) => {
+                if let Some(temperature) = payload.temperature {
+                    thermometer.set_temperature(temperature);
+                }
+                Ok(())
+            }
+            (DevicePayload::Climate(payload), Device::Climate(sensor)) => payload.apply(sensor),
+            (DevicePayload::Contact(payload), Device::Contact(contact)) => {
+                if let Some(open) = payload.open {
+                    contact.set_open(open);
+                }
+                Ok(())
+            }
+            (DevicePayload::Motion(payload), Device::Motion(motion)) => payload.apply(motion),
+            (DevicePayload::Leak(payload), Device::Leak(leak)) => {
+                if let Some(wet) = payload.wet {
+                    leak.set_wet(wet);
+                }
+                Ok(())
+            }
+            (DevicePayload::Light(payload), Device::Light(light)) => payload.apply(light),
+            (DevicePayload::Thermostat(payload), Device::Thermostat(thermostat)) => {
+                payload.apply(thermostat)
+            }
+            (DevicePayload::Generic(payload), Device::Generic(generic))
+                if payload.kind.is_none()
+                    || payload.kind.as_deref() == Some(generic.get_kind()) =>
+            {
+                if payload.schema.is_some() {
+                    return Err(invalid_field("schema", "cannot be changed by an update"));
+                }
+                let mut updated = generic.clone();
+                for (name, value) in &payload.properties {
+                    updated.set(name, value.clone())?;
+                }
+                *generic = updated;
+                Ok(())
+            }
+            (
+                DevicePayload::Generic(GenericPayload {
+                    kind: Some(kind), ..
+                }),
+                Device::Generic(generic),
+            ) => Err(HomeError::TypeMismatch {
+                room: String::new(),
+                device: String::new(),
+                expected: format!("generic '{kind}'"),
+                actual: format!("generic '{}'", generic.get_kind()),
+            }),
+            (_, device) => Err(HomeError::TypeMismatch {
+                room: String::new(),
+                device: String::new(),
+                expected: self.device_type().into(),
+                actual: device.type_name().into(),
+            }),
+        }
+    }
+}
+
+pub(crate) fn fields_from_json<T: for<'de> Deserialize<'de>>(value: Value) -> HomeResult<T> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
+        let field = e.path().to_string();
+        let reason = e.inner().to_string();
+        match reason.strip_prefix("unknown field `") {
+            Some(rest) => invalid_field(rest.split('`').next().unwrap_or(&field), "unknown field"),
+            None => invalid_field(&field, &reason),
+        }
+    })
+}
+
+/// The top-level fields of a JSON object as flat properties.
+fn json_properties(value: Value) -> HomeResult<Vec<(String, PropertyValue)>> {
+    let Value::Object(object) = value else {
+        return Err(HomeError::bad_json("expected an object"));
+    };
+    object
+        .into_iter()
+        .map(|(name, value)| {
+            serde_json::from_value(value)
+                .map(|value| (name.clone(), value))
+                .map_err(|_| invalid_field(&name, "expected a bool, number or string"))
+        })
+        .collect()
+}
+
+/// The JSON object with every alias of a field of `kind` renamed to the field.
+fn canonical_names(kind: &dyn DeviceKind, value: &Value) -> Value {
+    let Value::Object(object) = value else {
+        return value.clone();
+    };
+    let fields = kind.fields();
+    let object = object
+        .iter()
+        .map(|(name, value)| {
+            let name = fields
+                .iter()
+                .find(|spec| spec.is_named(name))
+                .map_or(name, |spec| &spec.name);
+            (name.clone(), value.clone())
+        })
+        .collect();
+    Value::Object(object)
+}
+
+fn kind_named(registry: &DeviceRegistry, device: &str) -> HomeResult<Arc<dyn DeviceKind>> {
+    registry
+        .get(device)
+        .cloned()
+        .ok_or_else(|| unknown_device(device))
+}
+
+fn custom_payload(kind: Arc<dyn DeviceKind>, properties: Properties) -> DevicePayload {
+    DevicePayload::Custom(CustomPayload { kind, properties })
+}
+
+fn query_properties(kind: &dyn DeviceKind, data: &HashMap<&str, &str>) -> HomeResult<Properties> {
+    let raw = data
+        .iter()
+        .filter(|(name, _)| **name != "device")
+        .map(|(name, value)| (name.to_string(), PropertyValue::infer(value)));
+    device_kind::check_properties(kind, raw)
+}
+
+fn required<T>(field: &str, value: Option<T>) -> HomeResult<T> {
+    value.ok_or_else(|| invalid_field(field, "missing field"))
+}
+
+fn invalid_field(field: &str, reason: &str) -> HomeError {
+    HomeError::invalid_value(field, reason)
+}
+
+fn unknown_device(device: &str) -> HomeError {
+    invalid_field("device", &format!("unknown device type '{device}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smart_device::DeviceDict;
+
+    fn registry() -> DeviceRegistry {
+        DeviceRegistry::default()
+    }
+
+    fn field_of<T: std::fmt::Debug>(result: HomeResult<T>) -> String {
+        match result {
+            Err(HomeError::InvalidValue { field, .. }) => field,
+            other => panic!("unexpected result {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_from_json() {
+        let payload = DevicePayload::from_json(
+            br#"{"device": "socket", "on": true, "voltage": 220, "current": 0.5}"#,
+            &registry(),
+        )
+        .unwrap();
+        assert_eq!(
+            Device::Socket(Socket::new(220., 0.5, true)),
+            payload.into_device().unwrap()
+        );
+        let payload =
+            DevicePayload::from_json(br#"{"device": "thermometer"}"#, &registry()).unwrap();
+        assert_eq!("temperature", field_of(payload.into_device()));
+    }
+
+    #[test]
+    fn test_fields_not_an_object() {
+        let registry = registry();
+        let power_strip = registry.get("power_strip").unwrap();
+        let value = serde_json::json!([1, 2]);
+        assert!(matches!(
+            RequestFields::Json(&value).properties(power_strip.as_ref()),
+            Err(HomeError::BadJson { .. })
+        ));
+    }
+
+    #[test]
+    fn test_json_errors_name_field() {
+        let json = br#"{"device": "socket", "on": "yes", "voltage": 1, "current": 1}"#;
+        assert_eq!("on", field_of(DevicePayload::from_json(json, &registry())));
+        let json = br#"{"device": "socket", "on": true, "volts": 1}"#;
+        assert_eq!(
+            "volts",
+            field_of(DevicePayload::from_json(json, &registry()))
+        );
+        let json = br#"{"device": "kettle"}"#;
+        assert_eq!(
+            "device",
+            field_of(DevicePayload::from_json(json, &registry()))
+        );
+        let json = br#"{"device": "socket", "current": -1}"#;
+        let payload = DevicePayload::from_json(json, &registry()).unwrap();
+        assert_eq!("current", field_of(payload.validate()));
+        assert!(matches!(
+            DevicePayload::from_json(b"[1, 2]", &registry()),
+            Err(HomeError::BadJson { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_query() {
+        let data = HashMap::from([
+            ("device", "Socket"),
+            ("state", "вкл"),
+            ("voltage", "230"),
+            ("current", "2"),
+        ]);
+        let payload = DevicePayload::from_query(&data, &registry()).unwrap();
+        assert_eq!(
+            Device::Socket(Socket::new(230., 2., true)),
+            payload.into_device().unwrap()
+        );
+        let data = HashMap::from([("device", "thermometer"), ("temperature", "warm")]);
+        assert_eq!(
+            "temperature",
+            field_of(DevicePayload::from_query(&data, &registry()))
+        );
+    }
+
+    #[test]
+    fn test_legacy_query_ignores_read_only() {
+        let mut dict = Device::Socket(Socket::new(230., 2., true)).device_dict();
+        dict.insert("state".into(), "off".into());
+        let data: HashMap<_, _> = dict.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            "power",
+            field_of(DevicePayload::from_query(&data, &registry()))
+        );
+        let payload = DevicePayload::from_legacy_query(&data, &registry()).unwrap();
+        assert_eq!(
+            Device::Socket(Socket::new(230., 2., false)),
+            payload.into_device().unwrap()
+        );
+        let data = HashMap::from([("device", "socket"), ("volts", "1")]);
+        assert_eq!(
+            "volts",
+            field_of(DevicePayload::from_legacy_query(&data, &registry()))
+        );
+    }
+
+    #[test]
+    fn test_json_aliases() {
+        let json = br#"{"device": "socket", "state": true, "voltage": 230, "current": 2}"#;
+        assert_eq!(
+            Device::Socket(Socket::new(230., 2., true)),
+            DevicePayload::from_json(json, &registry())
+                .unwrap()
+                .into_device()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_kinds_come_from_registry() {
+        let empty = DeviceRegistry::empty();
+        let data = HashMap::from([("device", "socket"), ("state", "on")]);
+        assert_eq!("device", field_of(DevicePayload::from_query(&data, &empty)));
+        let json = br#"{"device": "socket", "on": true}"#;
+        assert_eq!("device", field_of(DevicePayload::from_json(json, &empty)));
+    }
+
+    #[test]
+    fn test_apply_to() {
+        let mut device = Device::new_socket();
+        let payload = DevicePayload::Socket(SocketPayload {
+            on: Some(true),
+            ..Default::default()
+        });
+        payload.apply_to(&mut device).unwrap();
+        assert_eq!(Device::Socket(Socket::new(220., 0., true)), device);
+        let payload = DevicePayload::Thermometer(ThermometerPayload {
+            temperature: Some(25.),
+        });
+        assert!(matches!(
+            payload.apply_to(&mut device),
+            Err(HomeError::TypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_light() {
+        let json = br#"{"device": "light", "on": true, "color_temperature": 2700}"#;
+        let mut device = DevicePayload::from_json(json, &registry())
+            .unwrap()
+            .into_device()
+            .unwrap();
+        let Device::Light(light) = &device else {
+            panic!("unexpected device {device:?}");
+        };
+        assert_eq!(100., light.get_brightness());
+        assert_eq!(Some(2700.), light.get_color_temperature());
+
+        let data = HashMap::from([
+            ("device", "light"),
+            ("brightness", "40"),
+            ("color", "ff8800"),
+            ("transition", "1.5"),
+        ]);
+        DevicePayload::from_query(&data, &registry())
+            .unwrap()
+            .apply_to(&mut device)
+            .unwrap();
+        let dict = device.device_dict();
+        assert_eq!("40", dict["brightness"]);
+        assert_eq!("#ff8800", dict["color"]);
+        assert_eq!("1.5", dict["transition"]);
+        assert!(!dict.contains_key("color_temperature"));
+
+        for (json, field) in [
+            (
+                &br#"{"device": "light", "brightness": 120}"#[..],
+                "brightness",
+            ),
+            (br#"{"device": "light", "color": "orange"}"#, "color"),
+            (
+                br##"{"device": "light", "color": "#ff8800", "color_temperature": 3000}"##,
+                "color",
+            ),
+            (br#"{"device": "light", "transition": -1}"#, "transition"),
+        ] {
+            let payload = DevicePayload::from_json(json, &registry()).unwrap();
+            assert_eq!(field, field_of(payload.apply_to(&mut device)));
+        }
+        assert_eq!("40", device.device_dict()["brightness"]);
+        let payload = DevicePayload::from_json(br#"{"device": "light"}"#, &registry()).unwrap();
+        assert_eq!("on", field_of(payload.into_device()));
+    }
+
+    #[test]
+    fn test_climate() {
+        let json = br#"{"device": "climate", "temperature": 23, "humidity": 45, "co2": 800}"#;
+        let mut device = DevicePayload::from_json(json, &registry())
+            .unwrap()
+            .into_device()
+            .unwrap();
+        let data = HashMap::from([("device", "climate"), ("co2", "1200"), ("voc", "90")]);
+        DevicePayload::from_query(&data, &registry())
+            .unwrap()
+            .apply_to(&mut device)
+            .unwrap();
+        let dict = device.device_dict();
+        assert_eq!("1200", dict["co2"]);
+        assert_eq!("stuffy", dict["comfort"]);
+
+        let data = HashMap::from([("device", "climate"), ("humidity", "120")]);
+        assert_eq!(
+            "humidity",
+            field_of(DevicePayload::from_query(&data, &registry()))
+        );
+        let data = HashMap::from([("device", "climate"), ("dew_point", "10")]);
+        assert_eq!(
+            "dew_point",
+            field_of(DevicePayload::from_query(&data, &registry()))
+        );
+        let json = br#"{"device": "climate", "temperature": 23, "humidity": 45}"#;
+        let payload = DevicePayload::from_json(json, &registry()).unwrap();
+        assert_eq!("co2", field_of(payload.into_device()));
+    }
+
+    #[test]
+    fn test_power_strip() {
+        let json = br#"{"device": "power_strip", "voltage": 230, "max_current": 16,
+            "channels": 4, "channel_1": true, "channel_1_current": 10}"#;
+        let mut device = DevicePayload::from_json(json, &registry())
+            .unwrap()
+            .into_device()
+            .unwrap();
+        assert_eq!("10", device.device_dict()["current"]);
+
+        let data = HashMap::from([
+            ("device", "power_strip"),
+            ("channel_2", "on"),
+            ("channel_2_current", "8"),
+        ]);
+        let payload = DevicePayload::from_query(&data, &registry()).unwrap();
+        assert_eq!("max_current", field_of(payload.apply_to(&mut device)));
+        assert_eq!("off", device.device_dict()["channel_2"]);
+        let properties = Properties::from([("channel_1".into(), PropertyValue::Bool(false))]);
+        DevicePayload::from_properties("power_strip", &properties, &registry())
+            .unwrap()
+            .apply_to(&mut device)
+            .unwrap();
+        payload.apply_to(&mut device).unwrap();
+        let dict = device.device_dict();
+        assert_eq!("off", dict["channel_1"]);
+        assert_eq!("1840", dict["power"]);
+
+        let payload = DevicePayload::from_json(&serde_json::to_vec(&payload).unwrap(), &registry());
+        assert_eq!(
+            DevicePayload::from_query(&data, &registry()).unwrap(),
+            payload.unwrap()
+        );
+        let json = br#"{"device": "power_strip", "channels": 6}"#;
+        let payload = DevicePayload::from_json(json, &registry()).unwrap();
+        assert_eq!("channels", field_of(payload.apply_to(&mut device)));
+        let json = br#"{"device": "power_strip", "channel_5": true}"#;
+        let payload = DevicePayload::from_json(json, &registry()).unwrap();
+        assert_eq!("channel_5", field_of(payload.apply_to(&mut device)));
+        let json = br#"{"device": "power_strip", "channel_9": true}"#;
+        assert_eq!(
+            "channel_9",
+            field_of(DevicePayload::from_json(json, &registry()))
+        );
+        let json = br#"{"device": "power_strip", "voltage": 230, "max_current": 16,
+            "channels": 2.5}"#;
+        assert_eq!(
+            "channels",
+            field_of(DevicePayload::from_json(json, &registry()))
+        );
+    }
+
+    #[test]
+    fn test_binary_sensors() {
+        let json = br#"{"device": "motion", "detected": false, "reset_after": 90}"#;
+        let mut device = DevicePayload::from_json(json, &registry())
+            .unwrap()
+            .into_device()
+            .unwrap();
+        let data = HashMap::from([("device", "motion"), ("detected", "true")]);
+        DevicePayload::from_query(&data, &registry())
+            .unwrap()
+            .apply_to(&mut device)
+            .unwrap();
+        let dict = device.device_dict();
+        assert_eq!("detected", dict["state"]);
+        assert_eq!("90", dict["reset_after"]);
+
+        let data = HashMap::from([("device", "contact"), ("open", "on")]);
+        let device = DevicePayload::from_query(&data, &registry())
+            .unwrap()
+            .into_device()
+            .unwrap();
+        assert_eq!("open", device.device_dict()["state"]);
+        let json = br#"{"device": "leak", "wet": true, "changed": "2024-03-01T07:00:00Z"}"#;
+        assert_eq!(
+            "changed",
+            field_of(DevicePayload::from_json(json, &registry()))
+        );
+        let data = HashMap::from([("device", "leak"), ("last_triggered", "now")]);
+        assert_eq!(
+            "last_triggered",
+            field_of(DevicePayload::from_query(&data, &registry()))
+        );
+        let payload = DevicePayload::from_json(br#"{"device": "leak"}"#, &registry()).unwrap();
+        assert_eq!("wet", field_of(payload.into_device()));
+    }
+
+    #[test]
+    fn test_thermostat() {
+        let data = HashMap::from([
+            ("device", "thermostat"),
+            ("setpoint", "21"),
+            ("sensor", "R/T"),
+            ("actuator", "R/S"),
+        ]);
+        let mut device = DevicePayload::from_query(&data, &registry())
+            .unwrap()
+            .into_device()
+            .unwrap();
+        let dict = device.device_dict();
+        assert_eq!("heat", dict["mode"]);
+        assert_eq!("0.5", dict["hysteresis"]);
+
+        let json = br#"{"device": "thermostat", "mode": "cool", "setpoint": 25}"#;
+        DevicePayload::from_json(json, &registry())
+            .unwrap()
+            .apply_to(&mut device)
+            .unwrap();
+        assert_eq!("cool", device.device_dict()["mode"]);
+
+        for json in [
+            &br#"{"device": "thermostat", "mode": "auto"}"#[..],
+            br#"{"device": "thermostat", "demand": "idle"}"#,
+        ] {
+            assert!(DevicePayload::from_json(json, &registry()).is_err());
+        }
+        let json = br#"{"device": "thermostat", "setpoint": 22, "sensor": "T"}"#;
+        let payload = DevicePayload::from_json(json, &registry()).unwrap();
+        assert_eq!("sensor", field_of(payload.apply_to(&mut device)));
+        assert_eq!("25", device.device_dict()["setpoint"]);
+        let data = HashMap::from([("device", "thermostat"), ("demand", "heating")]);
+        assert_eq!(
+            "demand",
+            field_of(DevicePayload::from_query(&data, &registry()))
+        );
+        let json = br#"{"device": "thermostat", "setpoint": 21, "sensor": "R/T"}"#;
+        let payload = DevicePayload::from_json(json, &registry()).unwrap();
+        assert_eq!("actuator", field_of(payload.into_device()));
+    }
+
+    #[test]
+    fn test_generic() {
+        let json = br#"{
+            "device": "generic",
+            "kind": "dimmer",
+            "properties": {"level": 40, "on": true},
+            "schema": {"level": {"type": "number", "min": 0, "max": 100}, "on": {"type": "bool"}}
+        }"#;
+        let mut device = DevicePayload::from_json(json, &registry())
+            .unwrap()
+            .into_device()
+            .unwrap();
+        let data = HashMap::from([("device", "generic"), ("level", "60"), ("on", "off")]);
+        DevicePayload::from_query(&data, &registry())
+            .unwrap()
+            .apply_to(&mut device)
+            .unwrap();
+        let Device::Generic(generic) = &device else {
+            panic!("unexpected device {device:?}");
+        };
+        assert_eq!(Some(&PropertyValue::Number(60.)), generic.get("level"));
+        assert_eq!(Some(&PropertyValue::Bool(false)), generic.get("on"));
+
+        let json = br#"{"device": "generic", "properties": {"level": 70, "speed": 3}}"#;
+        let payload = DevicePayload::from_json(json, &registry()).unwrap();
+        assert_eq!("speed", field_of(payload.apply_to(&mut device)));
+        let Device::Generic(generic) = &device else {
+            unreachable!()
+        };
+        assert_eq!(Some(&PropertyValue::Number(60.)), generic.get("level"));
+
+        let json = br#"{"device": "generic", "kind": "fan", "properties": {}}"#;
+        let payload = DevicePayload::from_json(json, &registry()).unwrap();
+        assert!(matches!(
+            payload.apply_to(&mut device),
+            Err(HomeError::TypeMismatch { .. })
+        ));
+    }
+}
@@ -0,0 +1,226 @@
+//! RFC 7807 problem documents returned by every failing request.
+//!
+//! ```json
+//! {
+//!   "type": "urn:http-home:device-not-found",
+//!   "title": "Device not found",
+//!   "status": 404,
+//!   "detail": "Device not found R/X.",
+//!   "room": "R",
+//!   "device": "X"
+//! }
+//! ```
+
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::middleware::{ErrorHandlerResponse, ErrorHandlers};
+use actix_web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+const TYPE_PREFIX: &str = "urn:http-home:";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Problem {
+    /// Stable identifier of the kind of error, e.g. `urn:http-home:room-not-found`.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, code: &str, title: &str, detail: impl Into<String>) -> Self {
+        Self {
+            problem_type: format!("{TYPE_PREFIX}{code}"),
+            title: title.into(),
+            status: status.as_u16(),
+            detail: detail.into(),
+            room: None,
+            device: None,
+            field: None,
+        }
+    }
+
+    pub fn room(mut self, room_name: &str) -> Self {
+        self.room = Some(room_name.into());
+        self
+    }
+
+    pub fn device(mut self, device_name: &str) -> Self {
+        self.device = Some(device_name.into());
+        self
+    }
+
+    pub fn field(mut self, field: &str) -> Self {
+        self.field = Some(field.into());
+        self
+    }
+
+    /// The short code of `type` without the URN prefix, e.g. `room-not-found`.
+    pub fn code(&self) -> &str {
+        self.problem_type
+            .strip_prefix(TYPE_PREFIX)
+            .unwrap_or(&self.problem_type)
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    pub fn response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .content_type(CONTENT_TYPE)
+            .json(self)
+    }
+}
+
+pub async fn not_found(req: actix_web::HttpRequest) -> HttpResponse {
+    Problem::new(
+        StatusCode::NOT_FOUND,
+        "route-not-found",
+        "Route not found",
+        format!("No route for {} {}.", req.method(), req.path()),
+    )
+    .response()
+}
+
+/// Middleware turning every error response that is not a problem yet into one,
+/// e.g. 405 from a route with another method or 413 from a too large body.
+pub fn errors<B: 'static>() -> ErrorHandlers<B> {
+    ErrorHandlers::new().default_handler(render)
+}
+
+fn render<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let is_problem = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == CONTENT_TYPE);
+    if is_problem {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+    let status = res.status();
+    let detail = match res.response().error() {
+        Some(error) => error.to_string(),
+        None if status == StatusCode::METHOD_NOT_ALLOWED => format!(
+            "Method {} is not allowed on {}.",
+            res.request().method(),
+            res.request().path()
+        ),
+        None => format!("{status}."),
+    };
+    let (code, title) = match status {
+        StatusCode::BAD_REQUEST => ("bad-request", "Bad request"),
+        StatusCode::METHOD_NOT_ALLOWED => ("method-not-allowed", "Method not allowed"),
+        StatusCode::PAYLOAD_TOO_LARGE => ("payload-too-large", "Payload too large"),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => ("unsupported-media-type", "Unsupported media type"),
+        _ if status.is_client_error() => ("client-error", "Client error"),
+        _ => ("server-error", "Server error"),
+    };
+    let problem = Problem::new(status, code, title, detail);
+    let body = serde_json::to_string(&problem)?;
+    let (req, res) = res.into_parts();
+    let (mut res, _) = res.into_parts();
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(CONTENT_TYPE));
+    let res = ServiceResponse::new(req, res.set_body(body))
+        .map_into_boxed_body()
+        .map_into_right_body();
+    Ok(ErrorHandlerResponse::Response(res))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use actix_web::{test, web, App};
+
+    #[actix_web::test]
+    async fn test_problem_response() {
+        let problem = Problem::new(
+            StatusCode::CONFLICT,
+            "room-exists",
+            "Room already exists",
+            "Room 'R' already exists.",
+        )
+        .room("R");
+        assert_eq!("room-exists", problem.code());
+        let resp = problem.response();
+        assert_eq!(StatusCode::CONFLICT, resp.status());
+        assert_eq!(
+            CONTENT_TYPE,
+            resp.headers()
+                .get("content-type")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!("urn:http-home:room-exists", json["type"]);
+        assert_eq!(409, json["status"]);
+        assert_eq!("R", json["room"]);
+        assert!(json.get("device").is_none());
+        assert_eq!(problem, serde_json::from_value(json).unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_error_responses() {
+        #[derive(serde::Deserialize)]
+        struct Query {
+            #[allow(dead_code)]
+            n: u8,
+        }
+        let app = test::init_service(
+            App::new()
+                .wrap(errors())
+                .app_data(crate::web_routes::query_config())
+                .service(web::resource("/get").route(web::get().to(HttpResponse::Ok)))
+                .route("/body", web::post().to(|_: web::Bytes| async { "" }))
+                .route("/query", web::get().to(|_: web::Query<Query>| async { "" }))
+                .route("/problem", web::get().to(not_found)),
+        )
+        .await;
+        let cases = [
+            (
+                test::TestRequest::post().uri("/get"),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "method-not-allowed",
+            ),
+            (
+                test::TestRequest::post()
+                    .uri("/body")
+                    .set_payload(vec![b'x'; 512 * 1024]),
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload-too-large",
+            ),
+            (
+                test::TestRequest::get().uri("/query?n=x"),
+                StatusCode::BAD_REQUEST,
+                "invalid-query",
+            ),
+            (
+                test::TestRequest::get().uri("/problem"),
+                StatusCode::NOT_FOUND,
+                "route-not-found",
+            ),
+        ];
+        for (req, status, code) in cases {
+            let resp = test::call_service(&app, req.to_request()).await;
+            assert_eq!(status, resp.status());
+            assert_eq!(CONTENT_TYPE, resp.headers().get("content-type").unwrap());
+            let problem: Problem = test::read_body_json(resp).await;
+            assert_eq!(code, problem.code());
+            assert_eq!(status.as_u16(), problem.status);
+        }
+    }
+}
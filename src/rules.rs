@@ -0,0 +1,526 @@
+//! Automation rules.
+//!
+//! A rule runs its actions when one of its triggers fires and all of its conditions hold:
+//!
+//! ```json
+//! {
+//!   "triggers": [{"type": "property", "room": "R", "device": "T", "property": "temperature"}],
+//!   "conditions": [{"room": "R", "device": "T", "property": "temperature", "op": "gt", "value": 28}],
+//!   "actions": [{"type": "switch", "room": "R", "device": "S", "on": true}]
+//! }
+//! ```
+//!
+//! Rules are kept in the [`Home`] and saved with it. They are evaluated after every
+//! change of the home; the changes made by their actions can trigger further rules,
+//! up to [`MAX_CASCADE`] rounds deep. Thermostats are [regulated](thermostat::regulate)
+//! in every round, so rules also see what they switch. Time triggers are fired by the
+//! [`Scheduler`](crate::scheduler::Scheduler).
+
+use crate::device_kind::{DeviceRegistry, Properties};
+use crate::error::{HomeError, HomeResult};
+use crate::events::{self, ChangeEvent};
+use crate::home::Home;
+use crate::payload::DevicePayload;
+use crate::smart_device::PropertyValue;
+use crate::thermostat;
+use jiff::civil::{DateTime, Time};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use utoipa::ToSchema;
+
+/// How many rounds of rules triggering rules run before the evaluation gives up.
+pub const MAX_CASCADE: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    pub triggers: Vec<Trigger>,
+    /// All of them must hold for the actions to run.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Trigger {
+    /// A property of the device changed; any of its properties when `property` is absent.
+    Property {
+        room: String,
+        device: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        property: Option<String>,
+    },
+    /// Every day at this local time.
+    Time {
+        #[schema(value_type = String, example = "07:30")]
+        at: Time,
+    },
+}
+
+/// Compares a property of a device, e.g. `temperature > 28` or `on == true`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    pub room: String,
+    pub device: String,
+    pub property: String,
+    pub op: Comparison,
+    pub value: PropertyValue,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    /// Turns a socket on or off.
+    Switch {
+        room: String,
+        device: String,
+        on: bool,
+    },
+    /// Sets a property of a device, as a PATCH with just that field would.
+    Set {
+        room: String,
+        device: String,
+        property: String,
+        value: PropertyValue,
+    },
+    /// Applies a scene; nothing changes if any of its devices cannot be set.
+    Scene { scene: String },
+    /// Publishes a `notification` event.
+    Emit { message: String },
+}
+
+impl Rule {
+    /// Why the rule can never work, if it cannot.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.triggers.is_empty() {
+            return Err("a rule needs at least one trigger".into());
+        }
+        if self.actions.is_empty() {
+            return Err("a rule needs at least one action".into());
+        }
+        for condition in &self.conditions {
+            let ordered = !matches!(condition.op, Comparison::Eq | Comparison::Ne);
+            if ordered && !matches!(condition.value, PropertyValue::Number(_)) {
+                return Err(format!(
+                    "'{}' can only be compared with a number using {:?}",
+                    condition.property, condition.op
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn triggered_by(&self, registry: &DeviceRegistry, event: &ChangeEvent) -> bool {
+        self.enabled
+            && self
+                .triggers
+                .iter()
+                .any(|trigger| trigger.matches(registry, event))
+    }
+
+    fn due(&self, after: DateTime, until: DateTime) -> bool {
+        self.enabled
+            && self.triggers.iter().any(|trigger| match trigger {
+                Trigger::Time { at } => time_passed(*at, after, until),
+                Trigger::Property { .. } => false,
+            })
+    }
+
+    /// Runs the actions if the conditions hold, returning what they changed.
+    fn run(&self, rule_name: &str, home: &mut Home, registry: &DeviceRegistry) -> Vec<ChangeEvent> {
+        if !self
+            .conditions
+            .iter()
+            .all(|condition| condition.holds(home))
+        {
+            return Vec::new();
+        }
+        log::info!("Rule '{rule_name}' fired.");
+        let mut changes = Vec::new();
+        for action in &self.actions {
+            match action.run(rule_name, home, registry) {
+                Ok(events) => changes.extend(events),
+                Err(e) => log::warn!("Rule '{rule_name}' failed: {e}"),
+            }
+        }
+        changes
+    }
+}
+
+impl Trigger {
+    fn matches(&self, registry: &DeviceRegistry, event: &ChangeEvent) -> bool {
+        match (self, event) {
+            (
+                Trigger::Property {
+                    room,
+                    device,
+                    property,
+                },
+                ChangeEvent::PropertyChanged {
+                    room: changed_room,
+                    device: changed_device,
+                    device_type,
+                    property: changed_property,
+                    ..
+                },
+            ) => {
+                room == changed_room
+                    && device == changed_device
+                    && property.as_deref().is_none_or(|property| {
+                        registry.field_name(device_type, property)
+                            == registry.field_name(device_type, changed_property)
+                    })
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Whether the first `at` after `after` is no later than `until`.
+fn time_passed(at: Time, after: DateTime, until: DateTime) -> bool {
+    let mut next = after.date().to_datetime(at);
+    if next <= after {
+        match after.date().tomorrow() {
+            Ok(tomorrow) => next = tomorrow.to_datetime(at),
+            Err(_) => return false,
+        }
+    }
+    next <= until
+}
+
+impl Condition {
+    pub fn holds(&self, home: &Home) -> bool {
+        home.get_device_by_path(&self.room, &self.device)
+            .and_then(|device| device.property(&self.property))
+            .is_some_and(|actual| self.op.compare(&actual, &self.value))
+    }
+}
+
+impl Comparison {
+    pub fn compare(self, actual: &PropertyValue, expected: &PropertyValue) -> bool {
+        let ordering = match (actual, expected) {
+            (PropertyValue::Number(actual), PropertyValue::Number(expected)) => {
+                actual.partial_cmp(expected)
+            }
+            _ if actual == expected => Some(Ordering::Equal),
+            _ => None,
+        };
+        match self {
+            Comparison::Eq => ordering == Some(Ordering::Equal),
+            Comparison::Ne => ordering != Some(Ordering::Equal),
+            Comparison::Lt => ordering == Some(Ordering::Less),
+            Comparison::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+            Comparison::Gt => ordering == Some(Ordering::Greater),
+            Comparison::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+impl Action {
+    /// Runs the action on behalf of the named rule or schedule.
+    pub(crate) fn run(
+        &self,
+        source: &str,
+        home: &mut Home,
+        registry: &DeviceRegistry,
+    ) -> HomeResult<Vec<ChangeEvent>> {
+        match self {
+            Action::Switch { room, device, on } => set_property(
+                home,
+                registry,
+                room,
+                device,
+                "on",
+                &PropertyValue::Bool(*on),
+            ),
+            Action::Set {
+                room,
+                device,
+                property,
+                value,
+            } => set_property(home, registry, room, device, property, value),
+            Action::Scene { scene } => {
+                let scene =
+                    home.get_scene(scene)
+                        .cloned()
+                        .ok_or_else(|| HomeError::SceneNotFound {
+                            scene: scene.clone(),
+                        })?;
+                Ok(scene.apply(home, registry)?.1)
+            }
+            Action::Emit { message } => Ok(vec![ChangeEvent::Notification {
+                source: source.into(),
+                message: message.clone(),
+            }]),
+        }
+    }
+}
+
+fn set_property(
+    home: &mut Home,
+    registry: &DeviceRegistry,
+    room_name: &str,
+    device_name: &str,
+    property: &str,
+    value: &PropertyValue,
+) -> HomeResult<Vec<ChangeEvent>> {
+    let device = home
+        .get_device_by_path(room_name, device_name)
+        .ok_or_else(|| HomeError::device_not_found(room_name, device_name))?;
+    let properties = Properties::from([(property.to_string(), value.clone())]);
+    let payload =
+        DevicePayload::from_properties(registry.type_name(device), &properties, registry)?;
+    events::update_device(home, registry, room_name, device_name, &payload)
+}
+
+/// Runs the rules triggered by `changes`, and the rules triggered by what those changed,
+/// returning `changes` followed by everything the rules and thermostats did.
+pub fn evaluate(
+    home: &mut Home,
+    registry: &DeviceRegistry,
+    mut changes: Vec<ChangeEvent>,
+) -> Vec<ChangeEvent> {
+    changes.extend(thermostat::regulate(home, registry));
+    let mut all = changes.clone();
+    let mut round = changes;
+    for _ in 0..MAX_CASCADE {
+        let fired: Vec<_> = home
+            .rules()
+            .iter()
+            .filter(|(_, rule)| round.iter().any(|event| rule.triggered_by(registry, event)))
+            .map(|(name, rule)| (name.clone(), rule.clone()))
+            .collect();
+        round = fired
+            .iter()
+            .flat_map(|(name, rule)| rule.run(name, home, registry))
+            .collect();
+        round.extend(thermostat::regulate(home, registry));
+        if round.is_empty() {
+            return all;
+        }
+        all.extend(round.iter().cloned());
+    }
+    log::warn!("Rules still trigger each other after {MAX_CASCADE} rounds, stopping.");
+    all
+}
+
+/// Whether any rule has a time trigger that passed after `after` and until `until`.
+pub fn any_scheduled(home: &Home, after: DateTime, until: DateTime) -> bool {
+    home.rules().values().any(|rule| rule.due(after, until))
+}
+
+/// Runs the rules with a time trigger in `(after, until]`, then whatever they trigger.
+pub fn fire_scheduled(
+    home: &mut Home,
+    registry: &DeviceRegistry,
+    after: DateTime,
+    until: DateTime,
+) -> Vec<ChangeEvent> {
+    let due: Vec<_> = home
+        .rules()
+        .iter()
+        .filter(|(_, rule)| rule.due(after, until))
+        .map(|(name, rule)| (name.clone(), rule.clone()))
+        .collect();
+    let changes = due
+        .iter()
+        .flat_map(|(name, rule)| rule.run(name, home, registry))
+        .collect();
+    evaluate(home, registry, changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smart_device::{Device, Socket};
+
+    fn rule(json: serde_json::Value) -> Rule {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn temperature_changed(new: &str) -> ChangeEvent {
+        ChangeEvent::PropertyChanged {
+            room: "R".into(),
+            device: "T".into(),
+            device_type: "thermometer".into(),
+            property: "temperature".into(),
+            old: Some("20".into()),
+            new: Some(new.into()),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = rule(serde_json::json!({
+            "triggers": [{"type": "time", "at": "07:30"}],
+            "actions": [{"type": "emit", "message": "wake up"}]
+        }));
+        assert!(valid.enabled);
+        assert_eq!(Ok(()), valid.validate());
+        let mut invalid = valid.clone();
+        invalid.actions.clear();
+        assert!(invalid.validate().is_err());
+        let mut invalid = valid;
+        invalid.conditions.push(Condition {
+            room: "R".into(),
+            device: "S".into(),
+            property: "on".into(),
+            op: Comparison::Gt,
+            value: PropertyValue::Bool(true),
+        });
+        assert!(invalid.validate().is_err());
+        assert!(serde_json::from_value::<Rule>(serde_json::json!({
+            "triggers": [{"type": "sunset"}], "actions": []
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let number = |n| PropertyValue::Number(n);
+        assert!(Comparison::Gt.compare(&number(30.), &number(28.)));
+        assert!(Comparison::Le.compare(&number(28.), &number(28.)));
+        assert!(!Comparison::Lt.compare(&number(28.), &number(28.)));
+        let on = PropertyValue::Bool(true);
+        assert!(Comparison::Eq.compare(&on, &on));
+        assert!(Comparison::Ne.compare(&on, &number(1.)));
+        assert!(!Comparison::Gt.compare(&on, &number(0.)));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let registry = DeviceRegistry::default();
+        let mut home = Home::restore();
+        home.put_rule(
+            "cool down",
+            rule(serde_json::json!({
+                "triggers": [{"type": "property", "room": "R", "device": "T"}],
+                "conditions": [{"room": "R", "device": "T", "property": "temperature", "op": "gt", "value": 28}],
+                "actions": [{"type": "switch", "room": "R", "device": "S", "on": true}]
+            })),
+        );
+        home.put_rule(
+            "announce",
+            rule(serde_json::json!({
+                "triggers": [{"type": "property", "room": "R", "device": "S", "property": "on"}],
+                "actions": [{"type": "emit", "message": "fan switched"}]
+            })),
+        );
+
+        // 20 °C: the condition does not hold.
+        let changes = evaluate(&mut home, &registry, vec![temperature_changed("21")]);
+        assert_eq!(1, changes.len());
+
+        let thermometer = home.get_device_by_path_mut("R", "T").unwrap();
+        *thermometer = crate::smart_device::Thermometer::new(30.).into();
+        let changes = evaluate(&mut home, &registry, vec![temperature_changed("30")]);
+        assert_eq!(
+            Some(PropertyValue::Bool(true)),
+            home.get_device_by_path("R", "S").unwrap().property("on")
+        );
+        assert!(matches!(
+            &changes[1],
+            ChangeEvent::PropertyChanged { property, .. } if property == "state"
+        ));
+        assert_eq!(
+            &ChangeEvent::Notification {
+                source: "announce".into(),
+                message: "fan switched".into()
+            },
+            changes.last().unwrap()
+        );
+
+        // Already on: nothing changes, nothing is announced.
+        let changes = evaluate(&mut home, &registry, vec![temperature_changed("31")]);
+        assert_eq!(1, changes.len());
+    }
+
+    #[test]
+    fn test_cascade_stops() {
+        let registry = DeviceRegistry::default();
+        let mut home = Home::restore();
+        for (name, on) in [("on", true), ("off", false)] {
+            home.put_rule(
+                name,
+                rule(serde_json::json!({
+                    "triggers": [{"type": "property", "room": "R", "device": "S", "property": "state"}],
+                    "conditions": [{"room": "R", "device": "S", "property": "on", "op": "ne", "value": on}],
+                    "actions": [{"type": "switch", "room": "R", "device": "S", "on": on}]
+                })),
+            );
+        }
+        home.replace_device("R", "S", Socket::new(220., 1., true).into())
+            .unwrap();
+        let changed = ChangeEvent::PropertyChanged {
+            room: "R".into(),
+            device: "S".into(),
+            device_type: "socket".into(),
+            property: "state".into(),
+            old: Some("off".into()),
+            new: Some("on".into()),
+        };
+        let changes = evaluate(&mut home, &registry, vec![changed]);
+        assert_eq!(1 + MAX_CASCADE * 2, changes.len());
+    }
+
+    #[test]
+    fn test_fire_scheduled() {
+        let registry = DeviceRegistry::default();
+        let mut home = Home::restore();
+        home.put_rule(
+            "night",
+            rule(serde_json::json!({
+                "triggers": [{"type": "time", "at": "23:00"}],
+                "actions": [{"type": "set", "room": "R", "device": "S", "property": "state", "value": true}]
+            })),
+        );
+        let at = |s: &str| s.parse::<DateTime>().unwrap();
+        let changes = fire_scheduled(
+            &mut home,
+            &registry,
+            at("2024-01-01T22:00"),
+            at("2024-01-01T22:59:59"),
+        );
+        assert!(changes.is_empty());
+        let changes = fire_scheduled(
+            &mut home,
+            &registry,
+            at("2024-01-01T22:59:59"),
+            at("2024-01-01T23:00"),
+        );
+        assert_eq!(1, changes.len());
+        assert_eq!(
+            &Device::Socket(Socket::new(220., 0., true)),
+            home.get_device_by_path("R", "S").unwrap()
+        );
+        assert!(time_passed(
+            "01:00".parse().unwrap(),
+            at("2024-01-01T23:00"),
+            at("2024-01-02T01:30")
+        ));
+        assert!(!time_passed(
+            "23:00".parse().unwrap(),
+            at("2024-01-01T23:00"),
+            at("2024-01-02T01:30")
+        ));
+    }
+}
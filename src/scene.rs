@@ -0,0 +1,309 @@
+//! Scenes: named target states of devices across rooms, such as "night" or "away".
+//!
+//! ```json
+//! {"devices": {"R": {"S": {"on": false}, "T": {"temperature": 18}}}}
+//! ```
+//!
+//! A scene is captured from the current home, edited, and applied atomically:
+//! every target is checked against its device before any device is touched,
+//! so a scene is applied either completely or not at all.
+
+use crate::device_kind::{DeviceRegistry, Properties};
+use crate::error::{HomeError, HomeResult};
+use crate::events::{self, ChangeEvent};
+use crate::home::Home;
+use crate::payload::DevicePayload;
+use crate::smart_device::{Device, Generic, PropertyValue};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Scene {
+    /// Target properties of each device, by room and device name.
+    #[schema(value_type = BTreeMap<String, BTreeMap<String, BTreeMap<String, PropertyValue>>>)]
+    pub devices: BTreeMap<String, BTreeMap<String, Properties>>,
+}
+
+/// Changes to a scene: the properties given are merged into the targets,
+/// and a device given as `null` is dropped from the scene.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SceneEdit {
+    #[schema(value_type = BTreeMap<String, BTreeMap<String, Option<BTreeMap<String, PropertyValue>>>>)]
+    pub devices: BTreeMap<String, BTreeMap<String, Option<Properties>>>,
+}
+
+/// What applying a scene did to each of its devices.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SceneReport {
+    pub changed: Vec<DeviceChanges>,
+    /// Devices that were already in their target state.
+    pub unchanged: Vec<DevicePath>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DeviceChanges {
+    pub room: String,
+    pub device: String,
+    /// Old and new value of every changed property, as in the device dict.
+    pub properties: BTreeMap<String, PropertyChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PropertyChange {
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct DevicePath {
+    pub room: String,
+    pub device: String,
+}
+
+impl Scene {
+    /// The settable properties of every device, or only of those in `room_name`.
+    pub fn capture(
+        home: &Home,
+        registry: &DeviceRegistry,
+        room_name: Option<&str>,
+    ) -> HomeResult<Self> {
+        let rooms: Vec<_> = match room_name {
+            Some(room_name) => vec![(
+                room_name,
+                home.get_room_by_name(room_name)
+                    .ok_or_else(|| HomeError::room_not_found(room_name))?,
+            )],
+            None => home
+                .room_names_list()
+                .filter_map(|name| Some((name.as_str(), home.get_room_by_name(name)?)))
+                .collect(),
+        };
+        let devices = rooms
+            .into_iter()
+            .map(|(room_name, room)| {
+                let devices = room
+                    .devices()
+                    .map(|(name, device)| (name.clone(), capture_device(registry, device)))
+                    .collect();
+                (room_name.to_string(), devices)
+            })
+            .collect();
+        Ok(Self { devices })
+    }
+
+    pub fn edit(&mut self, edit: SceneEdit) {
+        for (room_name, devices) in edit.devices {
+            let room = self.devices.entry(room_name.clone()).or_default();
+            for (device_name, properties) in devices {
+                match properties {
+                    Some(properties) => room.entry(device_name).or_default().extend(properties),
+                    None => {
+                        room.remove(&device_name);
+                    }
+                }
+            }
+            if room.is_empty() {
+                self.devices.remove(&room_name);
+            }
+        }
+    }
+
+    /// Every target device as it will be once the scene is applied,
+    /// or why the scene cannot be applied to the home as it is.
+    pub fn plan(
+        &self,
+        home: &Home,
+        registry: &DeviceRegistry,
+    ) -> HomeResult<Vec<(&str, &str, Device)>> {
+        let mut planned = Vec::new();
+        for (room_name, devices) in &self.devices {
+            if home.get_room_by_name(room_name).is_none() {
+                return Err(HomeError::room_not_found(room_name));
+            }
+            for (device_name, properties) in devices {
+                let mut device = home
+                    .get_device_by_path(room_name, device_name)
+                    .ok_or_else(|| HomeError::device_not_found(room_name, device_name))?
+                    .clone();
+                let payload = DevicePayload::from_properties(
+                    registry.type_name(&device),
+                    properties,
+                    registry,
+                )?;
+                payload
+                    .apply_to(&mut device)
+                    .map_err(|e| e.in_room(room_name).at_device(device_name))?;
+                planned.push((room_name.as_str(), device_name.as_str(), device));
+            }
+        }
+        Ok(planned)
+    }
+
+    /// Sets every target device, or none of them if any cannot be set.
+    pub fn apply(
+        &self,
+        home: &mut Home,
+        registry: &DeviceRegistry,
+    ) -> HomeResult<(SceneReport, Vec<ChangeEvent>)> {
+        let planned = self.plan(home, registry)?;
+        let mut report = SceneReport::default();
+        let mut changes = Vec::new();
+        for (room_name, device_name, device) in planned {
+            let old = home
+                .replace_device(room_name, device_name, device)?
+                .expect("planned devices exist");
+            let new = home
+                .get_device_by_path(room_name, device_name)
+                .expect("the device was just replaced");
+            let changed = events::device_changed(registry, room_name, device_name, &old, new);
+            if changed.is_empty() {
+                report.unchanged.push(DevicePath {
+                    room: room_name.into(),
+                    device: device_name.into(),
+                });
+                continue;
+            }
+            let properties = changed
+                .iter()
+                .filter_map(|event| match event {
+                    ChangeEvent::PropertyChanged {
+                        property, old, new, ..
+                    } => Some((
+                        property.clone(),
+                        PropertyChange {
+                            old: old.clone(),
+                            new: new.clone(),
+                        },
+                    )),
+                    _ => None,
+                })
+                .collect();
+            report.changed.push(DeviceChanges {
+                room: room_name.into(),
+                device: device_name.into(),
+                properties,
+            });
+            changes.extend(changed);
+        }
+        Ok((report, changes))
+    }
+}
+
+/// The properties of a device a client may set.
+fn capture_device(registry: &DeviceRegistry, device: &Device) -> Properties {
+    let device_type = registry.type_name(device);
+    match device {
+        Device::Generic(generic) if device_type == "generic" => generic_properties(generic),
+        _ => registry
+            .get(device_type)
+            .map(|kind| kind.fields())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|field| !field.read_only)
+            .filter_map(|field| Some((field.name.clone(), device.property(&field.name)?)))
+            .collect(),
+    }
+}
+
+fn generic_properties(generic: &Generic) -> Properties {
+    generic
+        .properties()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smart_device::Socket;
+
+    fn scene(json: serde_json::Value) -> Scene {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_capture() {
+        let registry = DeviceRegistry::default();
+        let home = Home::restore();
+        let captured = Scene::capture(&home, &registry, Some("R")).unwrap();
+        assert_eq!(
+            scene(serde_json::json!({"devices": {"R": {
+                "S": {"on": false, "voltage": 220.0, "current": 0.0},
+                "T": {"temperature": 20.0}
+            }}})),
+            captured
+        );
+        assert!(Scene::capture(&home, &registry, Some("X")).is_err());
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut night = scene(serde_json::json!({"devices": {"R": {
+            "S": {"on": false}, "T": {"temperature": 18}
+        }}}));
+        night.edit(
+            serde_json::from_value(serde_json::json!({"devices": {"R": {
+                "S": {"current": 0}, "T": null
+            }}}))
+            .unwrap(),
+        );
+        assert_eq!(
+            scene(serde_json::json!({"devices": {"R": {"S": {"on": false, "current": 0}}}})),
+            night
+        );
+        night.edit(
+            serde_json::from_value(serde_json::json!({"devices": {"R": {"S": null}}})).unwrap(),
+        );
+        assert!(night.devices.is_empty());
+    }
+
+    #[test]
+    fn test_apply() {
+        let registry = DeviceRegistry::default();
+        let mut home = Home::restore();
+        home.replace_device("R", "S", Socket::new(220., 2., true).into())
+            .unwrap();
+        let night = scene(serde_json::json!({"devices": {"R": {
+            "S": {"state": false, "current": 0}, "T": {"temperature": 20}
+        }}}));
+        let (report, changes) = night.apply(&mut home, &registry).unwrap();
+        assert_eq!(
+            vec![DevicePath {
+                room: "R".into(),
+                device: "T".into()
+            }],
+            report.unchanged
+        );
+        assert_eq!(1, report.changed.len());
+        assert_eq!(
+            PropertyChange {
+                old: Some("on".into()),
+                new: Some("off".into())
+            },
+            report.changed[0].properties["state"]
+        );
+        assert_eq!(3, changes.len());
+        assert_eq!(
+            &Device::Socket(Socket::new(220., 0., false)),
+            home.get_device_by_path("R", "S").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_apply_is_atomic() {
+        let registry = DeviceRegistry::default();
+        let mut home = Home::restore();
+        let before = Scene::capture(&home, &registry, None).unwrap();
+        for broken in [
+            serde_json::json!({"devices": {"R": {"S": {"on": true}, "T": {"temperature": "hot"}}}}),
+            serde_json::json!({"devices": {"R": {"S": {"on": true}, "X": {"on": true}}}}),
+            serde_json::json!({"devices": {"R": {"S": {"on": true}, "T": {"on": true}}}}),
+        ] {
+            assert!(scene(broken).apply(&mut home, &registry).is_err());
+            assert_eq!(before, Scene::capture(&home, &registry, None).unwrap());
+        }
+    }
+}
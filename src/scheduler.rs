@@ -0,0 +1,452 @@
+//! Scheduled actions.
+//!
+//! A schedule runs rule [`Action`]s on a cron expression or once at a local date and time:
+//!
+//! ```json
+//! {"cron": "0 7 * * MON-FRI", "actions": [{"type": "switch", "room": "R", "device": "S", "on": true}]}
+//! {"at": "2024-12-31T23:59", "missed": "run_once", "actions": [{"type": "emit", "message": "bye"}]}
+//! ```
+//!
+//! Schedules are kept in the [`Home`] together with the instant each was last `checked`.
+//! Runs that fell between that instant and now by more than a minute, e.g. while the
+//! server was down, were missed; the `missed` policy decides whether they are skipped
+//! (the default), run once, or run once per missed occurrence.
+//!
+//! The [`Scheduler`] also fires the time triggers of the automation rules.
+//! It reads the time from a [`Clock`], so tests can move time forward by hand.
+
+use crate::clock::Clock;
+use crate::cron::Cron;
+use crate::device_kind::DeviceRegistry;
+use crate::events::{ChangeEvent, EventBus};
+use crate::home::Home;
+use crate::rules::{self, Action};
+use crate::sensors;
+use crate::storage::Storage;
+use crate::SmartHome;
+use actix_web::web;
+use jiff::civil::DateTime;
+use jiff::tz::TimeZone;
+use jiff::{SignedDuration, Timestamp, Zoned};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+/// How often the scheduler looks at the clock.
+const TICK: Duration = Duration::from_secs(1);
+/// How late a run may start and still count as on time.
+const GRACE: SignedDuration = SignedDuration::from_secs(60);
+/// The most missed occurrences `run_all` catches up on.
+pub const MAX_CATCH_UP: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Schedule {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub when: When,
+    #[serde(default)]
+    pub missed: MissedRuns,
+    pub actions: Vec<Action>,
+    /// Every occurrence up to this instant has been run or skipped.
+    /// Set by the server when the schedule is put.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, read_only)]
+    pub checked: Option<Timestamp>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum When {
+    /// Every time the cron expression matches, in local time.
+    Cron {
+        #[schema(value_type = String, example = "0 7 * * MON-FRI")]
+        cron: Cron,
+    },
+    /// Once, at this local date and time.
+    Once {
+        #[schema(value_type = String, example = "2024-12-31T23:59")]
+        at: DateTime,
+    },
+}
+
+/// What to do about occurrences missed while the server was not running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MissedRuns {
+    #[default]
+    Skip,
+    RunOnce,
+    RunAll,
+}
+
+impl When {
+    /// The first occurrence strictly after `after`, in local time.
+    pub fn next_after(&self, after: DateTime) -> Option<DateTime> {
+        match self {
+            When::Cron { cron } => cron.next_after(after),
+            When::Once { at } => (*at > after).then_some(*at),
+        }
+    }
+}
+
+impl Schedule {
+    /// Why the schedule can never run, if it cannot.
+    pub fn validate(&self, now: &Zoned) -> Result<(), String> {
+        if self.actions.is_empty() {
+            return Err("a schedule needs at least one action".into());
+        }
+        match &self.when {
+            When::Once { at } if *at <= now.datetime() => {
+                Err(format!("{at} is already in the past"))
+            }
+            When::Cron { cron } if cron.next_after(now.datetime()).is_none() => {
+                Err(format!("'{cron}' never matches"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The next time the schedule runs, if it ever does.
+    pub fn next_run(&self, now: &Zoned) -> Option<DateTime> {
+        let after = self
+            .checked
+            .map_or(now.datetime(), |checked| local(checked, now.time_zone()));
+        self.enabled.then(|| self.when.next_after(after)).flatten()
+    }
+
+    /// Occurrences after `checked` up to `now`, oldest first and at most `MAX_CATCH_UP + 1`.
+    fn occurrences(&self, now: &Zoned) -> Vec<Timestamp> {
+        let Some(checked) = self.checked else {
+            return Vec::new();
+        };
+        let mut occurrences = Vec::new();
+        let mut after = local(checked, now.time_zone());
+        while occurrences.len() <= MAX_CATCH_UP {
+            let Some(next) = self.when.next_after(after) else {
+                break;
+            };
+            let Ok(instant) = next.to_zoned(now.time_zone().clone()) else {
+                break;
+            };
+            if instant.timestamp() > now.timestamp() {
+                break;
+            }
+            occurrences.push(instant.timestamp());
+            after = next;
+        }
+        occurrences
+    }
+
+    /// How many of the occurrences to run now by the missed-run policy,
+    /// and how many of them were missed.
+    fn runs(&self, occurrences: &[Timestamp], now: &Zoned) -> (usize, usize) {
+        let on_time = occurrences
+            .last()
+            .is_some_and(|last| now.timestamp().duration_since(*last) <= GRACE);
+        let missed = occurrences.len() - usize::from(on_time);
+        let runs = match self.missed {
+            MissedRuns::Skip => usize::from(on_time),
+            MissedRuns::RunOnce => usize::from(!occurrences.is_empty()),
+            MissedRuns::RunAll => missed.min(MAX_CATCH_UP) + usize::from(on_time),
+        };
+        (runs, missed)
+    }
+}
+
+fn local(timestamp: Timestamp, time_zone: &TimeZone) -> DateTime {
+    timestamp.to_zoned(time_zone.clone()).datetime()
+}
+
+/// Runs what is due as the clock moves on.
+#[derive(Debug)]
+pub struct Scheduler {
+    clock: Arc<dyn Clock>,
+    last: Zoned,
+}
+
+impl Scheduler {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let last = clock.now();
+        Self { clock, last }
+    }
+
+    /// Clears timed out motion detections, then runs the rules with time triggers passed
+    /// since the last tick and the schedules with occurrences since they were checked.
+    /// Returns what changed, or `None` if the home was not touched and does not need saving.
+    pub fn tick(&mut self, home: &mut Home, registry: &DeviceRegistry) -> Option<Vec<ChangeEvent>> {
+        let now = self.clock.now();
+        let reset = sensors::reset_motion(home, registry, &now);
+        let mut touched = !reset.is_empty();
+        let mut changes = rules::evaluate(home, registry, reset);
+        changes.extend(rules::fire_scheduled(
+            home,
+            registry,
+            self.last.datetime(),
+            now.datetime(),
+        ));
+        touched |= !changes.is_empty();
+        self.last = now.clone();
+
+        let due: Vec<_> = home
+            .schedules()
+            .iter()
+            .filter(|(_, schedule)| schedule.enabled)
+            .map(|(name, schedule)| (name.clone(), schedule.occurrences(&now)))
+            .filter(|(_, occurrences)| !occurrences.is_empty())
+            .collect();
+        for (name, occurrences) in due {
+            let Some(schedule) = home.get_schedule_mut(&name) else {
+                continue;
+            };
+            schedule.checked = Some(now.timestamp());
+            let (runs, missed) = schedule.runs(&occurrences, &now);
+            if missed > 0 {
+                log::info!("Schedule '{name}' missed {missed} run(s), running {runs} now.");
+            }
+            let actions = schedule.actions.clone();
+            touched = true;
+            for _ in 0..runs {
+                let mut ran = Vec::new();
+                for action in &actions {
+                    match action.run(&name, home, registry) {
+                        Ok(events) => ran.extend(events),
+                        Err(e) => log::warn!("Schedule '{name}' failed: {e}"),
+                    }
+                }
+                changes.extend(rules::evaluate(home, registry, ran));
+            }
+        }
+        let stamped = sensors::stamp(home, registry, &now);
+        touched |= !stamped.is_empty();
+        changes.extend(stamped);
+        touched.then_some(changes)
+    }
+
+    /// Whether `tick` would find anything to do now; needs only to read the home.
+    pub fn due(&self, home: &Home) -> bool {
+        let now = self.clock.now();
+        sensors::due(home, &now)
+            || rules::any_scheduled(home, self.last.datetime(), now.datetime())
+            || home
+                .schedules()
+                .values()
+                .any(|schedule| schedule.enabled && !schedule.occurrences(&now).is_empty())
+    }
+
+    pub fn now(&self) -> Zoned {
+        self.clock.now()
+    }
+}
+
+/// Ticks the scheduler for as long as the server runs, saving and publishing what it changes.
+///
+/// The home is only locked for writing when something is due, and the state file
+/// is written after the lock is released.
+pub async fn run(
+    mut scheduler: Scheduler,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) {
+    let mut ticks = tokio::time::interval(TICK);
+    loop {
+        ticks.tick().await;
+        if !scheduler.due(&*home.read().await) {
+            continue;
+        }
+        let mut home = home.write().await;
+        let Some(changes) = scheduler.tick(&mut home, &registry) else {
+            continue;
+        };
+        home.meter_energy(&scheduler.now());
+        let snapshot = storage.snapshot(&home);
+        bus.publish(changes);
+        drop(home);
+        let saved = match snapshot {
+            Ok(snapshot) => storage.write(snapshot).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = saved {
+            log::error!("Cannot save home after scheduled actions: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::smart_device::PropertyValue;
+
+    fn schedule(json: serde_json::Value, checked: &str) -> Schedule {
+        let mut schedule: Schedule = serde_json::from_value(json).unwrap();
+        schedule.checked = Some(checked.parse().unwrap());
+        schedule
+    }
+
+    fn socket_on(home: &Home) -> bool {
+        home.get_device_by_path("R", "S").unwrap().property("on") == Some(PropertyValue::Bool(true))
+    }
+
+    fn setup(now: &str) -> (Arc<ManualClock>, Scheduler, Home) {
+        let clock = Arc::new(ManualClock::new(now.parse().unwrap()));
+        let scheduler = Scheduler::new(clock.clone());
+        (clock, scheduler, Home::restore())
+    }
+
+    #[test]
+    fn test_cron_schedule() {
+        let registry = DeviceRegistry::default();
+        // 2024-03-01 is a Friday.
+        let (clock, mut scheduler, mut home) = setup("2024-03-01T06:59:30[UTC]");
+        home.put_schedule(
+            "morning",
+            schedule(
+                serde_json::json!({
+                    "cron": "0 7 * * MON-FRI",
+                    "actions": [{"type": "switch", "room": "R", "device": "S", "on": true}]
+                }),
+                "2024-03-01T06:59:30Z",
+            ),
+        );
+        assert!(!scheduler.due(&home));
+        assert_eq!(None, scheduler.tick(&mut home, &registry));
+        assert_eq!(
+            Some("2024-03-01T07:00:00".parse().unwrap()),
+            home.get_schedule("morning")
+                .unwrap()
+                .next_run(&scheduler.now())
+        );
+
+        clock.advance(SignedDuration::from_secs(31));
+        assert!(scheduler.due(&home));
+        let changes = scheduler.tick(&mut home, &registry).unwrap();
+        assert_eq!(1, changes.len());
+        assert!(socket_on(&home));
+        assert_eq!(None, scheduler.tick(&mut home, &registry));
+        assert_eq!(
+            Some("2024-03-04T07:00:00".parse().unwrap()),
+            home.get_schedule("morning")
+                .unwrap()
+                .next_run(&scheduler.now())
+        );
+    }
+
+    #[test]
+    fn test_missed_runs() {
+        let registry = DeviceRegistry::default();
+        let emit = |missed: &str| {
+            schedule(
+                serde_json::json!({
+                    "cron": "0 * * * *",
+                    "missed": missed,
+                    "actions": [{"type": "emit", "message": "hourly"}]
+                }),
+                // Down since a little before 3 o'clock.
+                "2024-03-01T02:50:00Z",
+            )
+        };
+        let (_, mut scheduler, mut home) = setup("2024-03-01T06:30[UTC]");
+        for missed in ["skip", "run_once", "run_all"] {
+            home.put_schedule(missed, emit(missed));
+        }
+        let changes = scheduler.tick(&mut home, &registry).unwrap();
+        let runs = |name: &str| {
+            changes
+                .iter()
+                .filter(|event| matches!(event, ChangeEvent::Notification { source, .. } if source == name))
+                .count()
+        };
+        // 03:00, 04:00, 05:00 and 06:00 were missed.
+        assert_eq!(0, runs("skip"));
+        assert_eq!(1, runs("run_once"));
+        assert_eq!(4, runs("run_all"));
+        for name in ["skip", "run_once", "run_all"] {
+            assert_eq!(
+                Some("2024-03-01T06:30:00Z".parse().unwrap()),
+                home.get_schedule(name).unwrap().checked
+            );
+        }
+        // Nothing is missed twice.
+        assert_eq!(None, scheduler.tick(&mut home, &registry));
+    }
+
+    #[test]
+    fn test_once() {
+        let registry = DeviceRegistry::default();
+        let (clock, mut scheduler, mut home) = setup("2024-03-01T12:00[UTC]");
+        let once = schedule(
+            serde_json::json!({
+                "at": "2024-03-01T12:30",
+                "actions": [{"type": "switch", "room": "R", "device": "S", "on": true}]
+            }),
+            "2024-03-01T12:00:00Z",
+        );
+        assert_eq!(Ok(()), once.validate(&scheduler.now()));
+        home.put_schedule("once", once.clone());
+        clock.advance(SignedDuration::from_mins(45));
+        // 15 minutes late is missed, and skipped by default.
+        assert_eq!(Some(vec![]), scheduler.tick(&mut home, &registry));
+        assert!(!socket_on(&home));
+        assert_eq!(
+            None,
+            home.get_schedule("once")
+                .unwrap()
+                .next_run(&scheduler.now())
+        );
+        assert!(once.validate(&scheduler.now()).is_err());
+    }
+
+    #[test]
+    fn test_rule_time_triggers() {
+        let registry = DeviceRegistry::default();
+        let (clock, mut scheduler, mut home) = setup("2024-03-01T22:59:59[UTC]");
+        let rule = serde_json::from_value(serde_json::json!({
+            "triggers": [{"type": "time", "at": "23:00"}],
+            "actions": [{"type": "switch", "room": "R", "device": "S", "on": true}]
+        }))
+        .unwrap();
+        home.put_rule("night", rule);
+        assert!(!scheduler.due(&home));
+        clock.advance(SignedDuration::from_secs(1));
+        assert!(scheduler.due(&home));
+        assert_eq!(1, scheduler.tick(&mut home, &registry).unwrap().len());
+        assert!(socket_on(&home));
+    }
+
+    #[test]
+    fn test_motion_reset() {
+        use crate::smart_device::{Device, Motion};
+        let registry = DeviceRegistry::default();
+        let (clock, mut scheduler, mut home) = setup("2024-03-01T07:00[UTC]");
+        let rule = serde_json::from_value(serde_json::json!({
+            "triggers": [{"type": "property", "room": "R", "device": "M", "property": "state"}],
+            "actions": [{"type": "switch", "room": "R", "device": "S", "on": true}]
+        }))
+        .unwrap();
+        home.put_rule("hall", rule);
+        let mut motion = Motion::new(true);
+        motion.set_reset_after(60.).unwrap();
+        home.add_device("R", "M", motion.into()).unwrap();
+        assert!(scheduler.tick(&mut home, &registry).is_some());
+
+        clock.advance(SignedDuration::from_secs(30));
+        assert!(!scheduler.due(&home));
+        assert!(scheduler.tick(&mut home, &registry).is_none());
+        clock.advance(SignedDuration::from_secs(30));
+        assert!(scheduler.due(&home));
+        assert!(scheduler.tick(&mut home, &registry).is_some());
+        assert!(matches!(
+            home.get_device_by_path("R", "M"),
+            Some(Device::Motion(motion)) if !motion.is_detected()
+        ));
+        assert!(socket_on(&home), "rules see the reset");
+    }
+}
@@ -0,0 +1,228 @@
+//! Binary sensors: when they changed, motion timeouts and alerts.
+//!
+//! Contact, motion and leak sensors only note that their state was set. Once the change
+//! is saved, [`stamp`] records the time on the server clock as `changed` and, when the
+//! sensor is active, as `last_triggered`.
+//!
+//! An open contact or a wet leak detector is an alert: an `alert` event is published
+//! when it starts and when it ends, and `GET /api/v1/alerts` lists the ones still on.
+//! Motion detections with a `reset_after` timeout are cleared by the scheduler with
+//! [`reset_motion`].
+
+use crate::device_kind::DeviceRegistry;
+use crate::events::{self, ChangeEvent};
+use crate::home::Home;
+use crate::smart_device::Device;
+use jiff::{Timestamp, Zoned};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A sensor that is alerting now.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Alert {
+    pub room: String,
+    pub device: String,
+    pub device_type: String,
+    /// `open` or `leak`.
+    pub alert: String,
+    /// When the sensor became active; absent until the change is stamped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, format = DateTime)]
+    pub since: Option<Timestamp>,
+}
+
+/// Records `now` on every sensor set since the last stamp, returning what changed
+/// and the alerts that started or ended.
+pub fn stamp(home: &mut Home, registry: &DeviceRegistry, now: &Zoned) -> Vec<ChangeEvent> {
+    let pending = sensors(home, |device| {
+        device
+            .binary_state()
+            .is_some_and(|state| state.is_pending())
+    });
+    let mut changes = Vec::new();
+    for (room, name) in pending {
+        let Some(device) = home.get_device_by_path_mut(&room, &name) else {
+            continue;
+        };
+        let old = device.clone();
+        let Some(state) = device.binary_state_mut() else {
+            continue;
+        };
+        let was_active = state.stamp(now.timestamp());
+        let active = state.is_active();
+        changes.extend(events::device_changed(registry, &room, &name, &old, device));
+        if let (Some(was_active), Some(alert)) = (was_active, device.alert()) {
+            if active != was_active {
+                changes.push(ChangeEvent::Alert {
+                    room: room.clone(),
+                    device: name.clone(),
+                    device_type: registry.type_name(device).into(),
+                    alert: alert.into(),
+                    active,
+                });
+            }
+        }
+    }
+    changes
+}
+
+/// Clears the motion detections whose `reset_after` has passed at `now`.
+/// They still need to be stamped.
+pub fn reset_motion(home: &mut Home, registry: &DeviceRegistry, now: &Zoned) -> Vec<ChangeEvent> {
+    let due = sensors(
+        home,
+        |device| matches!(device, Device::Motion(motion) if motion.reset_due(now.timestamp())),
+    );
+    let mut changes = Vec::new();
+    for (room, name) in due {
+        if let Some(device) = home.get_device_by_path_mut(&room, &name) {
+            let old = device.clone();
+            if let Device::Motion(motion) = device {
+                motion.set_detected(false);
+            }
+            changes.extend(events::device_changed(registry, &room, &name, &old, device));
+        }
+    }
+    changes
+}
+
+/// Whether [`reset_motion`] or [`stamp`] would change anything at `now`.
+pub fn due(home: &Home, now: &Zoned) -> bool {
+    !sensors(home, |device| match device {
+        Device::Motion(motion) if motion.reset_due(now.timestamp()) => true,
+        _ => device
+            .binary_state()
+            .is_some_and(|state| state.is_pending()),
+    })
+    .is_empty()
+}
+
+/// Alerts on now, by room and device name.
+pub fn alerts(home: &Home, registry: &DeviceRegistry) -> Vec<Alert> {
+    let mut alerts: Vec<_> = home
+        .room_names_list()
+        .filter_map(|room_name| Some((room_name, home.get_room_by_name(room_name)?)))
+        .flat_map(|(room_name, room)| {
+            room.devices().filter_map(move |(name, device)| {
+                let state = device.binary_state().filter(|state| state.is_active())?;
+                Some(Alert {
+                    room: room_name.clone(),
+                    device: name.clone(),
+                    device_type: registry.type_name(device).into(),
+                    alert: device.alert()?.into(),
+                    since: state.changed(),
+                })
+            })
+        })
+        .collect();
+    alerts.sort_by(|a, b| (&a.room, &a.device).cmp(&(&b.room, &b.device)));
+    alerts
+}
+
+/// Paths of the devices matching `filter`, sorted.
+fn sensors(home: &Home, filter: impl Fn(&Device) -> bool) -> Vec<(String, String)> {
+    let mut paths: Vec<_> = home
+        .room_names_list()
+        .filter_map(|room_name| Some((room_name, home.get_room_by_name(room_name)?)))
+        .flat_map(|(room_name, room)| {
+            room.devices()
+                .filter(|(_, device)| filter(device))
+                .map(|(name, _)| (room_name.clone(), name.clone()))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smart_device::{Contact, DeviceDict, Leak, Motion};
+
+    fn at(time: &str) -> Zoned {
+        format!("2024-03-01T{time}[UTC]").parse().unwrap()
+    }
+
+    fn alert_events(changes: &[ChangeEvent]) -> Vec<(&str, bool)> {
+        changes
+            .iter()
+            .filter_map(|event| match event {
+                ChangeEvent::Alert { device, active, .. } => Some((device.as_str(), *active)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_stamp_and_alerts() {
+        let registry = DeviceRegistry::default();
+        let mut home = Home::new("H");
+        home.add_room("R").unwrap();
+        home.add_device("R", "Door", Contact::new(false).into())
+            .unwrap();
+        home.add_device("R", "Sink", Leak::new(true).into())
+            .unwrap();
+        let changes = stamp(&mut home, &registry, &at("07:00"));
+        assert_eq!(vec![("Sink", true)], alert_events(&changes));
+        assert!(stamp(&mut home, &registry, &at("07:01")).is_empty());
+
+        let Some(Device::Contact(door)) = home.get_device_by_path_mut("R", "Door") else {
+            panic!("door is gone");
+        };
+        door.set_open(true);
+        let changes = stamp(&mut home, &registry, &at("07:05"));
+        assert_eq!(vec![("Door", true)], alert_events(&changes));
+        assert!(changes.iter().any(|event| matches!(
+            event,
+            ChangeEvent::PropertyChanged { property, .. } if property == "changed"
+        )));
+        let alerts = alerts(&home, &registry);
+        assert_eq!(
+            vec![
+                ("Door", "open", Some(at("07:05").timestamp())),
+                ("Sink", "leak", Some(at("07:00").timestamp()))
+            ],
+            alerts
+                .iter()
+                .map(|alert| (alert.device.as_str(), alert.alert.as_str(), alert.since))
+                .collect::<Vec<_>>()
+        );
+
+        home.replace_device("R", "Sink", Leak::new(false).into())
+            .unwrap();
+        let changes = stamp(&mut home, &registry, &at("07:10"));
+        assert!(
+            alert_events(&changes).is_empty(),
+            "a new sensor has no alert"
+        );
+        assert_eq!(1, super::alerts(&home, &registry).len());
+    }
+
+    #[test]
+    fn test_reset_motion() {
+        let registry = DeviceRegistry::default();
+        let mut home = Home::new("H");
+        home.add_room("R").unwrap();
+        let mut motion = Motion::new(true);
+        motion.set_reset_after(300.).unwrap();
+        home.add_device("R", "M", motion.into()).unwrap();
+        stamp(&mut home, &registry, &at("07:00"));
+        assert!(reset_motion(&mut home, &registry, &at("07:04")).is_empty());
+
+        // Triggered again, the timeout starts over.
+        let Some(Device::Motion(motion)) = home.get_device_by_path_mut("R", "M") else {
+            panic!("motion sensor is gone");
+        };
+        motion.set_detected(true);
+        stamp(&mut home, &registry, &at("07:04"));
+        assert!(reset_motion(&mut home, &registry, &at("07:06")).is_empty());
+        let changes = reset_motion(&mut home, &registry, &at("07:09"));
+        assert_eq!(1, changes.len());
+        stamp(&mut home, &registry, &at("07:09"));
+        let dict = home.get_device_by_path("R", "M").unwrap().device_dict();
+        assert_eq!("clear", dict["state"]);
+        assert_eq!("2024-03-01T07:09:00Z", dict["changed"]);
+        assert_eq!("2024-03-01T07:04:00Z", dict["last_triggered"]);
+    }
+}
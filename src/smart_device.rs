@@ -1,27 +1,232 @@
 #![allow(unused, dead_code)]
 
+use crate::error::{HomeError, HomeResult};
+use jiff::Timestamp;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 use std::{collections::HashMap, fmt::format};
+use utoipa::ToSchema;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "device", rename_all = "lowercase")]
 #[non_exhaustive]
 pub enum Device {
     Socket(Socket),
     Thermometer(Thermometer),
+    Light(Light),
+    Thermostat(Thermostat),
+    Climate(ClimateSensor),
+    Contact(Contact),
+    Motion(Motion),
+    Leak(Leak),
+    #[serde(rename = "power_strip")]
+    PowerStrip(PowerStrip),
+    Generic(Generic),
     Unknown,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Socket {
     voltage: f64,
     current: f64,
     on: bool,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Thermometer {
     temperature: f64,
 }
 
+/// A dimmable lamp, optionally tunable white or full colour.
+/// Colour temperature and RGB colour are exclusive: setting one clears the other.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    on: bool,
+    brightness: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color_temperature: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    color: Option<Rgb>,
+    /// Seconds the latest change fades in over.
+    #[serde(default)]
+    transition: f64,
+}
+
+/// An RGB colour, written `#rrggbb`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Measures the air of a room: temperature, relative humidity, CO2 and,
+/// when the sensor has one, a VOC index. Dew point and comfort are derived from them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClimateSensor {
+    temperature: f64,
+    humidity: f64,
+    co2: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    voc: Option<f64>,
+}
+
+/// How the air of a room feels, from the most pressing problem down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comfort {
+    Stuffy,
+    Cold,
+    Hot,
+    Dry,
+    Humid,
+    Comfortable,
+}
+
+/// On or off state of a binary sensor and when it changed.
+///
+/// Devices cannot tell the time, so a change is only marked as pending here;
+/// [`crate::sensors::stamp`] records when it happened once the change is saved.
+/// Setting the state to active again counts as a new trigger even without a change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BinaryState {
+    active: bool,
+    /// When the state last changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    changed: Option<Timestamp>,
+    /// When the sensor last became or was reported active.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_triggered: Option<Timestamp>,
+    /// State at the last stamp, absent before the first one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stamped: Option<bool>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pending: bool,
+}
+
+/// A door or window contact, active while open.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Contact {
+    #[serde(flatten)]
+    state: BinaryState,
+}
+
+/// A motion detector. With `reset_after`, a detection clears by itself
+/// that many seconds after the last trigger.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Motion {
+    #[serde(flatten)]
+    state: BinaryState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reset_after: Option<f64>,
+}
+
+/// A water-leak detector, active while wet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Leak {
+    #[serde(flatten)]
+    state: BinaryState,
+}
+
+/// A strip of outlets sharing one supply voltage, each switched on and off by itself.
+/// The outlets switched on may together draw at most `max_current`, the rating of the strip.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PowerStrip {
+    voltage: f64,
+    max_current: f64,
+    channels: Vec<Channel>,
+}
+
+/// One outlet of a power strip and the current drawn by what is plugged into it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Channel {
+    on: bool,
+    current: f64,
+}
+
+/// Keeps the temperature measured by a thermometer at `setpoint` by switching a socket,
+/// e.g. one a heater is plugged into. Both are given by their path within the home.
+///
+/// In heat mode the socket is switched on once the temperature falls below
+/// `setpoint - hysteresis` and off once it rises above `setpoint + hysteresis`;
+/// cool mode works the other way round. Inside the band the socket keeps its state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Thermostat {
+    setpoint: f64,
+    hysteresis: f64,
+    mode: ThermostatMode,
+    sensor: DevicePath,
+    actuator: DevicePath,
+    /// Whether the actuator is switched on to reach the setpoint.
+    #[serde(default)]
+    active: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ThermostatMode {
+    #[default]
+    Heat,
+    Cool,
+    Off,
+}
+
+/// What a thermostat is doing about the temperature right now.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Demand {
+    Idle,
+    Heating,
+    Cooling,
+}
+
+/// Where a device is in the home, written `room/device`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct DevicePath {
+    pub room: String,
+    pub device: String,
+}
+
+/// A device without a dedicated type: a `kind` label and a bag of typed properties.
+/// When a `schema` is declared, every property must be declared in it and match its type.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Generic {
+    kind: String,
+    properties: BTreeMap<String, PropertyValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    schema: Option<BTreeMap<String, PropertySchema>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum PropertyValue {
+    Bool(bool),
+    Number(f64),
+    Text(String),
+}
+
+/// Text accepted for `true` and `false` by a bool property, e.g. from a query string.
+pub const TRUE_WORDS: [&str; 3] = ["true", "on", "вкл"];
+pub const FALSE_WORDS: [&str; 3] = ["false", "off", "выкл"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum PropertySchema {
+    Number {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        min: Option<f64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max: Option<f64>,
+    },
+    Bool,
+    String,
+    Enum {
+        values: Vec<String>,
+    },
+}
+
 pub trait DeviceInfo {
     fn device_info(&self) -> Vec<String>;
 }
@@ -39,9 +244,165 @@ impl Device {
         Device::Thermometer(Thermometer::new(20_f64))
     }
 
+    pub fn new_light() -> Self {
+        Device::Light(
+            Light::new(false, Light::MAX_BRIGHTNESS).expect("full brightness is in range"),
+        )
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Device::Socket(_) => "socket",
+            Device::Thermometer(_) => "thermometer",
+            Device::Light(_) => "light",
+            Device::Thermostat(_) => "thermostat",
+            Device::Climate(_) => "climate",
+            Device::Contact(_) => "contact",
+            Device::Motion(_) => "motion",
+            Device::Leak(_) => "leak",
+            Device::PowerStrip(_) => "power_strip",
+            Device::Generic(_) => "generic",
+            _ => "unknown",
+        }
+    }
+
     pub fn report(&self) -> String {
         String::from("Device...")
     }
+
+    /// State of a contact, motion or leak sensor.
+    pub fn binary_state(&self) -> Option<&BinaryState> {
+        match self {
+            Device::Contact(contact) => Some(&contact.state),
+            Device::Motion(motion) => Some(&motion.state),
+            Device::Leak(leak) => Some(&leak.state),
+            _ => None,
+        }
+    }
+
+    pub fn binary_state_mut(&mut self) -> Option<&mut BinaryState> {
+        match self {
+            Device::Contact(contact) => Some(&mut contact.state),
+            Device::Motion(motion) => Some(&mut motion.state),
+            Device::Leak(leak) => Some(&mut leak.state),
+            _ => None,
+        }
+    }
+
+    /// What an active sensor of this type alerts about, if it does.
+    pub fn alert(&self) -> Option<&'static str> {
+        match self {
+            Device::Contact(_) => Some("open"),
+            Device::Leak(_) => Some("leak"),
+            _ => None,
+        }
+    }
+
+    /// The current value of a property, read through the typed getters.
+    pub fn property(&self, name: &str) -> Option<PropertyValue> {
+        match (self, name) {
+            (Device::Socket(socket), "on" | "state") => Some(PropertyValue::Bool(socket.is_on())),
+            (Device::Socket(socket), "voltage") => {
+                Some(PropertyValue::Number(socket.get_voltage()))
+            }
+            (Device::Socket(socket), "current") => {
+                Some(PropertyValue::Number(socket.get_current()))
+            }
+            (Device::Socket(socket), "power") => {
+                Some(PropertyValue::Number(socket.get_current_power()))
+            }
+            (Device::Thermometer(thermometer), "temperature") => {
+                Some(PropertyValue::Number(thermometer.get_temperature()))
+            }
+            (Device::Light(light), "on" | "state") => Some(PropertyValue::Bool(light.is_on())),
+            (Device::Light(light), "brightness") => {
+                Some(PropertyValue::Number(light.get_brightness()))
+            }
+            (Device::Light(light), "color_temperature") => {
+                light.get_color_temperature().map(PropertyValue::Number)
+            }
+            (Device::Light(light), "color") => light
+                .get_color()
+                .map(|color| PropertyValue::Text(color.to_string())),
+            (Device::Light(light), "transition") => {
+                Some(PropertyValue::Number(light.get_transition()))
+            }
+            (Device::Thermostat(thermostat), "setpoint") => {
+                Some(PropertyValue::Number(thermostat.get_setpoint()))
+            }
+            (Device::Thermostat(thermostat), "hysteresis") => {
+                Some(PropertyValue::Number(thermostat.get_hysteresis()))
+            }
+            (Device::Thermostat(thermostat), "mode") => {
+                Some(PropertyValue::Text(thermostat.get_mode().to_string()))
+            }
+            (Device::Thermostat(thermostat), "sensor") => {
+                Some(PropertyValue::Text(thermostat.get_sensor().to_string()))
+            }
+            (Device::Thermostat(thermostat), "actuator") => {
+                Some(PropertyValue::Text(thermostat.get_actuator().to_string()))
+            }
+            (Device::Thermostat(thermostat), "demand") => {
+                Some(PropertyValue::Text(thermostat.demand().to_string()))
+            }
+            (Device::Climate(climate), "temperature") => {
+                Some(PropertyValue::Number(climate.get_temperature()))
+            }
+            (Device::Climate(climate), "humidity") => {
+                Some(PropertyValue::Number(climate.get_humidity()))
+            }
+            (Device::Climate(climate), "co2") => Some(PropertyValue::Number(climate.get_co2())),
+            (Device::Climate(climate), "voc") => climate.get_voc().map(PropertyValue::Number),
+            (Device::Climate(climate), "dew_point") => {
+                climate.dew_point().map(PropertyValue::Number)
+            }
+            (Device::Climate(climate), "comfort") => {
+                Some(PropertyValue::Text(climate.comfort().to_string()))
+            }
+            (Device::Contact(contact), "open" | "state") => {
+                Some(PropertyValue::Bool(contact.is_open()))
+            }
+            (Device::Motion(motion), "detected" | "state") => {
+                Some(PropertyValue::Bool(motion.is_detected()))
+            }
+            (Device::Motion(motion), "reset_after") => {
+                motion.get_reset_after().map(PropertyValue::Number)
+            }
+            (Device::Leak(leak), "wet" | "state") => Some(PropertyValue::Bool(leak.is_wet())),
+            (Device::PowerStrip(strip), "voltage") => {
+                Some(PropertyValue::Number(strip.get_voltage()))
+            }
+            (Device::PowerStrip(strip), "max_current") => {
+                Some(PropertyValue::Number(strip.get_max_current()))
+            }
+            (Device::PowerStrip(strip), "channels") => {
+                Some(PropertyValue::Number(strip.channel_count() as f64))
+            }
+            (Device::PowerStrip(strip), "current") => Some(PropertyValue::Number(strip.load())),
+            (Device::PowerStrip(strip), "power") => Some(PropertyValue::Number(strip.power())),
+            (Device::PowerStrip(strip), name) => {
+                let (number, is_current) = PowerStrip::parse_channel_field(name)?;
+                let channel = strip.channel(number)?;
+                Some(if is_current {
+                    PropertyValue::Number(channel.get_current())
+                } else {
+                    PropertyValue::Bool(channel.is_on())
+                })
+            }
+            (device, "changed" | "last_triggered") => {
+                let state = device.binary_state()?;
+                let time = if name == "changed" {
+                    state.changed()
+                } else {
+                    state.last_triggered()
+                };
+                time.map(|time| PropertyValue::Text(time.to_string()))
+            }
+            (Device::Generic(generic), "kind") => Some(PropertyValue::Text(generic.kind.clone())),
+            (Device::Generic(generic), name) => generic.get(name).cloned(),
+            _ => None,
+        }
+    }
 }
 
 impl DeviceInfo for Device {
@@ -49,6 +410,14 @@ impl DeviceInfo for Device {
         match self {
             Device::Socket(s) => s.device_info(),
             Device::Thermometer(t) => t.device_info(),
+            Device::Light(l) => l.device_info(),
+            Device::Thermostat(t) => t.device_info(),
+            Device::Climate(c) => c.device_info(),
+            Device::Contact(c) => c.device_info(),
+            Device::Motion(m) => m.device_info(),
+            Device::Leak(l) => l.device_info(),
+            Device::PowerStrip(p) => p.device_info(),
+            Device::Generic(g) => g.device_info(),
             _ => vec![String::from("Unknown device.")],
         }
     }
@@ -66,6 +435,10 @@ impl DeviceDict for Device {
                 );
                 result.insert(String::from("current"), socket.get_current().to_string());
                 result.insert(String::from("voltage"), socket.get_voltage().to_string());
+                result.insert(
+                    String::from("power"),
+                    socket.get_current_power().to_string(),
+                );
             }
             Device::Thermometer(thermometer) => {
                 result.insert(String::from("device"), String::from("thermometer"));
@@ -74,6 +447,120 @@ impl DeviceDict for Device {
                     thermometer.get_temperature().to_string(),
                 );
             }
+            Device::Light(light) => {
+                result.insert(String::from("device"), String::from("light"));
+                result.insert(
+                    String::from("state"),
+                    String::from(if light.is_on() { "on" } else { "off" }),
+                );
+                result.insert(
+                    String::from("brightness"),
+                    light.get_brightness().to_string(),
+                );
+                if let Some(kelvin) = light.get_color_temperature() {
+                    result.insert(String::from("color_temperature"), kelvin.to_string());
+                }
+                if let Some(color) = light.get_color() {
+                    result.insert(String::from("color"), color.to_string());
+                }
+                result.insert(
+                    String::from("transition"),
+                    light.get_transition().to_string(),
+                );
+            }
+            Device::Thermostat(thermostat) => {
+                result.insert(String::from("device"), String::from("thermostat"));
+                result.insert(
+                    String::from("setpoint"),
+                    thermostat.get_setpoint().to_string(),
+                );
+                result.insert(
+                    String::from("hysteresis"),
+                    thermostat.get_hysteresis().to_string(),
+                );
+                result.insert(String::from("mode"), thermostat.get_mode().to_string());
+                result.insert(String::from("sensor"), thermostat.get_sensor().to_string());
+                result.insert(
+                    String::from("actuator"),
+                    thermostat.get_actuator().to_string(),
+                );
+                result.insert(String::from("demand"), thermostat.demand().to_string());
+            }
+            Device::Climate(climate) => {
+                result.insert(String::from("device"), String::from("climate"));
+                result.insert(
+                    String::from("temperature"),
+                    climate.get_temperature().to_string(),
+                );
+                result.insert(String::from("humidity"), climate.get_humidity().to_string());
+                result.insert(String::from("co2"), climate.get_co2().to_string());
+                if let Some(voc) = climate.get_voc() {
+                    result.insert(String::from("voc"), voc.to_string());
+                }
+                if let Some(dew_point) = climate.dew_point() {
+                    result.insert(String::from("dew_point"), dew_point.to_string());
+                }
+                result.insert(String::from("comfort"), climate.comfort().to_string());
+            }
+            Device::Contact(contact) => {
+                result.insert(String::from("device"), String::from("contact"));
+                result.insert(
+                    String::from("state"),
+                    String::from(if contact.is_open() { "open" } else { "closed" }),
+                );
+                contact.state.insert_times(&mut result);
+            }
+            Device::Motion(motion) => {
+                result.insert(String::from("device"), String::from("motion"));
+                result.insert(
+                    String::from("state"),
+                    String::from(if motion.is_detected() {
+                        "detected"
+                    } else {
+                        "clear"
+                    }),
+                );
+                if let Some(seconds) = motion.get_reset_after() {
+                    result.insert(String::from("reset_after"), seconds.to_string());
+                }
+                motion.state.insert_times(&mut result);
+            }
+            Device::Leak(leak) => {
+                result.insert(String::from("device"), String::from("leak"));
+                result.insert(
+                    String::from("state"),
+                    String::from(if leak.is_wet() { "wet" } else { "dry" }),
+                );
+                leak.state.insert_times(&mut result);
+            }
+            Device::PowerStrip(strip) => {
+                result.insert(String::from("device"), String::from("power_strip"));
+                result.insert(String::from("voltage"), strip.get_voltage().to_string());
+                result.insert(
+                    String::from("max_current"),
+                    strip.get_max_current().to_string(),
+                );
+                result.insert(String::from("channels"), strip.channel_count().to_string());
+                result.insert(String::from("current"), strip.load().to_string());
+                result.insert(String::from("power"), strip.power().to_string());
+                for (number, channel) in strip.channels() {
+                    result.insert(
+                        PowerStrip::channel_field(number),
+                        String::from(if channel.is_on() { "on" } else { "off" }),
+                    );
+                    result.insert(
+                        PowerStrip::channel_current_field(number),
+                        channel.get_current().to_string(),
+                    );
+                }
+            }
+            Device::Generic(generic) => {
+                result.insert(String::from("device"), String::from("generic"));
+                result.insert(String::from("kind"), generic.get_kind().into());
+                for (name, value) in generic.properties() {
+                    result.insert(name.clone(), value.to_string());
+                }
+            }
             _ => {
                 result.insert("device".into(), "unknown".into());
             }
@@ -94,6 +581,54 @@ impl From<Thermometer> for Device {
     }
 }
 
+impl From<Light> for Device {
+    fn from(l: Light) -> Self {
+        Device::Light(l)
+    }
+}
+
+impl From<Thermostat> for Device {
+    fn from(t: Thermostat) -> Self {
+        Device::Thermostat(t)
+    }
+}
+
+impl From<ClimateSensor> for Device {
+    fn from(c: ClimateSensor) -> Self {
+        Device::Climate(c)
+    }
+}
+
+impl From<Contact> for Device {
+    fn from(c: Contact) -> Self {
+        Device::Contact(c)
+    }
+}
+
+impl From<Motion> for Device {
+    fn from(m: Motion) -> Self {
+        Device::Motion(m)
+    }
+}
+
+impl From<Leak> for Device {
+    fn from(l: Leak) -> Self {
+        Device::Leak(l)
+    }
+}
+
+impl From<PowerStrip> for Device {
+    fn from(p: PowerStrip) -> Self {
+        Device::PowerStrip(p)
+    }
+}
+
+impl From<Generic> for Device {
+    fn from(g: Generic) -> Self {
+        Device::Generic(g)
+    }
+}
+
 impl Socket {
     pub fn new(voltage: f64, current: f64, on: bool) -> Self {
         Self {
@@ -171,39 +706,1245 @@ impl DeviceInfo for Thermometer {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl Light {
+    pub const MAX_BRIGHTNESS: f64 = 100.;
+    /// Range of white a tunable light can show, from candle light to blue sky.
+    pub const COLOR_TEMPERATURE: (f64, f64) = (1000., 10000.);
+    pub const MAX_TRANSITION: f64 = 3600.;
 
-    #[test]
-    fn test_socket() {
-        let mut device = Device::new_socket();
-        if let Device::Socket(mut socket) = device {
-            assert_eq!(220_f64, socket.voltage);
-            assert_eq!(0_f64, socket.current);
-            assert!(!socket.is_on());
-            assert_eq!(0_f64, socket.get_current_power());
-            socket.set_voltage(225_f64);
-            socket.set_current(3_f64);
-            socket.switch(true);
-            assert_eq!(225_f64, socket.voltage);
-            assert_eq!(3_f64, socket.current);
-            assert!(socket.is_on());
-            assert!((socket.get_current_power() - 675_f64).abs() < 1e-6);
-        } else {
-            panic!("Device::new_socket gives unexpected result.");
+    pub fn new(on: bool, brightness: f64) -> HomeResult<Self> {
+        check_range("brightness", brightness, 0., Self::MAX_BRIGHTNESS)?;
+        Ok(Self {
+            on,
+            brightness,
+            color_temperature: None,
+            color: None,
+            transition: 0.,
+        })
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    pub fn switch(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    /// Brightness in percent.
+    pub fn get_brightness(&self) -> f64 {
+        self.brightness
+    }
+
+    pub fn set_brightness(&mut self, brightness: f64) -> HomeResult<()> {
+        check_range("brightness", brightness, 0., Self::MAX_BRIGHTNESS)?;
+        self.brightness = brightness;
+        Ok(())
+    }
+
+    /// Colour temperature in kelvin, when showing white.
+    pub fn get_color_temperature(&self) -> Option<f64> {
+        self.color_temperature
+    }
+
+    pub fn set_color_temperature(&mut self, kelvin: f64) -> HomeResult<()> {
+        let (min, max) = Self::COLOR_TEMPERATURE;
+        check_range("color_temperature", kelvin, min, max)?;
+        self.color_temperature = Some(kelvin);
+        self.color = None;
+        Ok(())
+    }
+
+    pub fn get_color(&self) -> Option<Rgb> {
+        self.color
+    }
+
+    pub fn set_color(&mut self, color: Rgb) {
+        self.color = Some(color);
+        self.color_temperature = None;
+    }
+
+    /// Seconds the latest change fades in over.
+    pub fn get_transition(&self) -> f64 {
+        self.transition
+    }
+
+    pub fn set_transition(&mut self, seconds: f64) -> HomeResult<()> {
+        check_range("transition", seconds, 0., Self::MAX_TRANSITION)?;
+        self.transition = seconds;
+        Ok(())
+    }
+}
+
+fn check_range(field: &str, value: f64, min: f64, max: f64) -> HomeResult<()> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(HomeError::invalid_value(
+            field,
+            &format!("{value} is out of range {min}..{max}"),
+        ))
+    }
+}
+
+impl DeviceInfo for Light {
+    fn device_info(&self) -> Vec<String> {
+        let mut result = vec![];
+        result.push("light".into());
+        result.push((if self.on { "on" } else { "off" }).into());
+        result.push(format!("{}%", self.brightness));
+        if let Some(kelvin) = self.color_temperature {
+            result.push(format!("{kelvin}K"));
+        }
+        if let Some(color) = self.color {
+            result.push(color.to_string());
         }
+        result
     }
+}
 
-    #[test]
-    fn test_thermometer() {
-        let device = Device::new_thermometer();
-        if let Device::Thermometer(mut thermometer) = device {
-            assert_eq!(20_f64, thermometer.temperature);
-            let thermometer = thermometer.temperature(25_f64);
-            assert_eq!(25_f64, thermometer.temperature);
+impl FromStr for Rgb {
+    type Err = String;
+
+    /// Parses `#rrggbb`; the `#` may be left out, e.g. in a query string.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let hex = text.strip_prefix('#').unwrap_or(text);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("'{text}' is not a colour like #ff8800"));
+        }
+        let channel =
+            |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).expect("checked hex digits");
+        Ok(Self {
+            r: channel(0),
+            g: channel(2),
+            b: channel(4),
+        })
+    }
+}
+
+impl TryFrom<String> for Rgb {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<Rgb> for String {
+    fn from(color: Rgb) -> Self {
+        color.to_string()
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl ClimateSensor {
+    /// Range the sensor measures, in degrees Celsius.
+    pub const TEMPERATURE: (f64, f64) = (-40., 85.);
+    pub const MAX_HUMIDITY: f64 = 100.;
+    /// Parts per million; outdoor air has about 420.
+    pub const MAX_CO2: f64 = 10000.;
+    /// VOC index where 100 is the average of the last day.
+    pub const MAX_VOC: f64 = 500.;
+
+    pub fn new(temperature: f64, humidity: f64, co2: f64) -> HomeResult<Self> {
+        let mut sensor = Self {
+            temperature: 0.,
+            humidity: 0.,
+            co2: 0.,
+            voc: None,
+        };
+        sensor.set_temperature(temperature)?;
+        sensor.set_humidity(humidity)?;
+        sensor.set_co2(co2)?;
+        Ok(sensor)
+    }
+
+    /// Degrees Celsius.
+    pub fn get_temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    pub fn set_temperature(&mut self, temperature: f64) -> HomeResult<()> {
+        let (min, max) = Self::TEMPERATURE;
+        check_range("temperature", temperature, min, max)?;
+        self.temperature = temperature;
+        Ok(())
+    }
+
+    /// Relative humidity in percent.
+    pub fn get_humidity(&self) -> f64 {
+        self.humidity
+    }
+
+    pub fn set_humidity(&mut self, humidity: f64) -> HomeResult<()> {
+        check_range("humidity", humidity, 0., Self::MAX_HUMIDITY)?;
+        self.humidity = humidity;
+        Ok(())
+    }
+
+    /// CO2 concentration in ppm.
+    pub fn get_co2(&self) -> f64 {
+        self.co2
+    }
+
+    pub fn set_co2(&mut self, co2: f64) -> HomeResult<()> {
+        check_range("co2", co2, 0., Self::MAX_CO2)?;
+        self.co2 = co2;
+        Ok(())
+    }
+
+    pub fn get_voc(&self) -> Option<f64> {
+        self.voc
+    }
+
+    pub fn set_voc(&mut self, voc: f64) -> HomeResult<()> {
+        check_range("voc", voc, 0., Self::MAX_VOC)?;
+        self.voc = Some(voc);
+        Ok(())
+    }
+
+    /// Temperature at which the air would start to condense, in degrees Celsius
+    /// to one decimal, by the Magnus formula. Absent for perfectly dry air.
+    pub fn dew_point(&self) -> Option<f64> {
+        const A: f64 = 17.62;
+        const B: f64 = 243.12;
+        if self.humidity <= 0. {
+            return None;
+        }
+        let gamma = (self.humidity / 100.).ln() + A * self.temperature / (B + self.temperature);
+        Some((B * gamma / (A - gamma) * 10.).round() / 10.)
+    }
+
+    pub fn comfort(&self) -> Comfort {
+        if self.co2 > 1000. || self.voc.is_some_and(|voc| voc > 250.) {
+            Comfort::Stuffy
+        } else if self.temperature < 18. {
+            Comfort::Cold
+        } else if self.temperature > 26. {
+            Comfort::Hot
+        } else if self.humidity < 30. {
+            Comfort::Dry
+        } else if self.humidity > 60. {
+            Comfort::Humid
         } else {
-            panic!("Device::new_thermometer gives unexpected result.");
+            Comfort::Comfortable
         }
     }
 }
+
+impl DeviceInfo for ClimateSensor {
+    fn device_info(&self) -> Vec<String> {
+        let mut result = vec![
+            "climate".into(),
+            format!("{}°C", self.temperature),
+            format!("{}%", self.humidity),
+            format!("{}ppm", self.co2),
+        ];
+        if let Some(voc) = self.voc {
+            result.push(format!("VOC {voc}"));
+        }
+        result.push(self.comfort().to_string());
+        result
+    }
+}
+
+impl Comfort {
+    pub const NAMES: [&'static str; 6] = ["stuffy", "cold", "hot", "dry", "humid", "comfortable"];
+}
+
+impl fmt::Display for Comfort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Stuffy => "stuffy",
+            Self::Cold => "cold",
+            Self::Hot => "hot",
+            Self::Dry => "dry",
+            Self::Humid => "humid",
+            Self::Comfortable => "comfortable",
+        })
+    }
+}
+
+impl BinaryState {
+    /// A state not stamped yet.
+    pub fn new(active: bool) -> Self {
+        Self {
+            active,
+            changed: None,
+            last_triggered: None,
+            stamped: None,
+            pending: true,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set(&mut self, active: bool) {
+        self.pending |= active || active != self.active;
+        self.active = active;
+    }
+
+    pub fn changed(&self) -> Option<Timestamp> {
+        self.changed
+    }
+
+    pub fn last_triggered(&self) -> Option<Timestamp> {
+        self.last_triggered
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+
+    /// Records `now` as the time of the pending change. Returns whether the sensor
+    /// was active before if the state changed, a new sensor counting as inactive.
+    pub fn stamp(&mut self, now: Timestamp) -> Option<bool> {
+        if !self.pending {
+            return None;
+        }
+        self.pending = false;
+        if self.active {
+            self.last_triggered = Some(now);
+        }
+        let was_active = self.stamped;
+        self.stamped = Some(self.active);
+        if was_active == Some(self.active) {
+            return None;
+        }
+        self.changed = Some(now);
+        Some(was_active.unwrap_or(false))
+    }
+
+    fn insert_times(&self, dict: &mut HashMap<String, String>) {
+        if let Some(changed) = self.changed {
+            dict.insert(String::from("changed"), changed.to_string());
+        }
+        if let Some(triggered) = self.last_triggered {
+            dict.insert(String::from("last_triggered"), triggered.to_string());
+        }
+    }
+}
+
+impl Contact {
+    pub fn new(open: bool) -> Self {
+        Self {
+            state: BinaryState::new(open),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.is_active()
+    }
+
+    pub fn set_open(&mut self, open: bool) {
+        self.state.set(open);
+    }
+}
+
+impl DeviceInfo for Contact {
+    fn device_info(&self) -> Vec<String> {
+        let state = if self.is_open() { "open" } else { "closed" };
+        vec!["contact".into(), state.into()]
+    }
+}
+
+impl Motion {
+    pub const MAX_RESET_AFTER: f64 = 86400.;
+
+    pub fn new(detected: bool) -> Self {
+        Self {
+            state: BinaryState::new(detected),
+            reset_after: None,
+        }
+    }
+
+    pub fn is_detected(&self) -> bool {
+        self.state.is_active()
+    }
+
+    pub fn set_detected(&mut self, detected: bool) {
+        self.state.set(detected);
+    }
+
+    /// Seconds after the last trigger a detection clears by itself.
+    pub fn get_reset_after(&self) -> Option<f64> {
+        self.reset_after
+    }
+
+    /// Zero turns the automatic reset off.
+    pub fn set_reset_after(&mut self, seconds: f64) -> HomeResult<()> {
+        check_range("reset_after", seconds, 0., Self::MAX_RESET_AFTER)?;
+        self.reset_after = (seconds > 0.).then_some(seconds);
+        Ok(())
+    }
+
+    /// Whether a detection is due to clear by itself at `now`.
+    pub fn reset_due(&self, now: Timestamp) -> bool {
+        let (Some(seconds), Some(triggered)) = (self.reset_after, self.state.last_triggered) else {
+            return false;
+        };
+        self.is_detected()
+            && !self.state.is_pending()
+            && now.duration_since(triggered).as_secs_f64() >= seconds
+    }
+}
+
+impl DeviceInfo for Motion {
+    fn device_info(&self) -> Vec<String> {
+        let state = if self.is_detected() {
+            "detected"
+        } else {
+            "clear"
+        };
+        vec!["motion".into(), state.into()]
+    }
+}
+
+impl Leak {
+    pub fn new(wet: bool) -> Self {
+        Self {
+            state: BinaryState::new(wet),
+        }
+    }
+
+    pub fn is_wet(&self) -> bool {
+        self.state.is_active()
+    }
+
+    pub fn set_wet(&mut self, wet: bool) {
+        self.state.set(wet);
+    }
+}
+
+impl DeviceInfo for Leak {
+    fn device_info(&self) -> Vec<String> {
+        let state = if self.is_wet() { "wet" } else { "dry" };
+        vec!["leak".into(), state.into()]
+    }
+}
+
+impl PowerStrip {
+    pub const MAX_CHANNELS: usize = 8;
+    pub const MAX_VOLTAGE: f64 = 1000.;
+    pub const MAX_CURRENT: f64 = 125.;
+
+    /// A strip with `channels` outlets, all switched off and drawing nothing.
+    pub fn new(voltage: f64, max_current: f64, channels: usize) -> HomeResult<Self> {
+        check_range("channels", channels as f64, 1., Self::MAX_CHANNELS as f64)?;
+        check_range("voltage", voltage, 0., Self::MAX_VOLTAGE)?;
+        check_range("max_current", max_current, 0., Self::MAX_CURRENT)?;
+        Ok(Self {
+            voltage,
+            max_current,
+            channels: vec![Channel::default(); channels],
+        })
+    }
+
+    /// Volts, the same at every outlet.
+    pub fn get_voltage(&self) -> f64 {
+        self.voltage
+    }
+
+    pub fn set_voltage(&mut self, voltage: f64) -> HomeResult<()> {
+        check_range("voltage", voltage, 0., Self::MAX_VOLTAGE)?;
+        self.voltage = voltage;
+        Ok(())
+    }
+
+    /// Amperes the outlets switched on may draw together.
+    pub fn get_max_current(&self) -> f64 {
+        self.max_current
+    }
+
+    /// Fails, leaving the rating as it is, if the outlets switched on draw more.
+    pub fn set_max_current(&mut self, max_current: f64) -> HomeResult<()> {
+        check_range("max_current", max_current, 0., Self::MAX_CURRENT)?;
+        let previous = std::mem::replace(&mut self.max_current, max_current);
+        self.check_load()
+            .inspect_err(|_| self.max_current = previous)
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// The outlet numbered `number`, counting from 1 as printed on the strip.
+    pub fn channel(&self, number: usize) -> Option<&Channel> {
+        self.channels.get(number.checked_sub(1)?)
+    }
+
+    /// Every outlet with its number.
+    pub fn channels(&self) -> impl Iterator<Item = (usize, &Channel)> {
+        self.channels
+            .iter()
+            .enumerate()
+            .map(|(index, channel)| (index + 1, channel))
+    }
+
+    pub fn switch(&mut self, number: usize, on: bool) -> HomeResult<()> {
+        self.channel_mut(number, Self::channel_field)?.on = on;
+        Ok(())
+    }
+
+    pub fn set_current(&mut self, number: usize, current: f64) -> HomeResult<()> {
+        if current < 0. {
+            return Err(HomeError::invalid_value(
+                &Self::channel_current_field(number),
+                "must not be negative",
+            ));
+        }
+        self.channel_mut(number, Self::channel_current_field)?
+            .current = current;
+        Ok(())
+    }
+
+    /// Amperes drawn through the outlets switched on.
+    pub fn load(&self) -> f64 {
+        self.channels
+            .iter()
+            .filter(|channel| channel.on)
+            .map(|channel| channel.current)
+            .sum()
+    }
+
+    /// Watts drawn through the whole strip.
+    pub fn power(&self) -> f64 {
+        self.load() * self.voltage
+    }
+
+    /// Watts drawn through one outlet, nothing while it is off.
+    pub fn channel_power(&self, channel: &Channel) -> f64 {
+        if channel.on {
+            channel.current * self.voltage
+        } else {
+            0.
+        }
+    }
+
+    /// Fails if the outlets switched on draw more than the strip is rated for.
+    /// Switching and setting currents do not check it by themselves, so that
+    /// several changes can be made before the load is checked as a whole.
+    pub fn check_load(&self) -> HomeResult<()> {
+        let load = self.load();
+        if load > self.max_current {
+            return Err(HomeError::invalid_value(
+                "max_current",
+                &format!(
+                    "{load} A drawn through the outlets switched on exceeds the rating of {} A",
+                    self.max_current
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Name of the property switching outlet `number`, e.g. `channel_1`.
+    pub fn channel_field(number: usize) -> String {
+        format!("channel_{number}")
+    }
+
+    /// Name of the property with the current of outlet `number`, e.g. `channel_1_current`.
+    pub fn channel_current_field(number: usize) -> String {
+        format!("channel_{number}_current")
+    }
+
+    /// The outlet number in a channel property name and whether it names the current.
+    pub fn parse_channel_field(name: &str) -> Option<(usize, bool)> {
+        let rest = name.strip_prefix("channel_")?;
+        let (number, is_current) = match rest.strip_suffix("_current") {
+            Some(number) => (number, true),
+            None => (rest, false),
+        };
+        if number.starts_with('0') {
+            return None;
+        }
+        Some((number.parse().ok()?, is_current))
+    }
+
+    fn channel_mut(
+        &mut self,
+        number: usize,
+        field: fn(usize) -> String,
+    ) -> HomeResult<&mut Channel> {
+        let count = self.channels.len();
+        number
+            .checked_sub(1)
+            .and_then(|index| self.channels.get_mut(index))
+            .ok_or_else(|| {
+                HomeError::invalid_value(
+                    &field(number),
+                    &format!("the strip has channels 1..{count}"),
+                )
+            })
+    }
+}
+
+impl Channel {
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    /// Amperes drawn by what is plugged in while the outlet is on.
+    pub fn get_current(&self) -> f64 {
+        self.current
+    }
+}
+
+impl DeviceInfo for PowerStrip {
+    fn device_info(&self) -> Vec<String> {
+        let mut result = vec!["power_strip".into()];
+        result.extend(
+            self.channels
+                .iter()
+                .map(|channel| String::from(if channel.on { "on" } else { "off" })),
+        );
+        result.push(format!("{}", self.load()));
+        result.push(format!("{}", self.voltage));
+        result
+    }
+}
+
+impl Thermostat {
+    /// Range of setpoints, in degrees Celsius.
+    pub const SETPOINT: (f64, f64) = (-20., 60.);
+    pub const MAX_HYSTERESIS: f64 = 10.;
+    pub const DEFAULT_HYSTERESIS: f64 = 0.5;
+
+    /// A thermostat heating to `setpoint`, not yet switching anything on.
+    pub fn new(setpoint: f64, sensor: DevicePath, actuator: DevicePath) -> HomeResult<Self> {
+        let mut thermostat = Self {
+            setpoint: 0.,
+            hysteresis: Self::DEFAULT_HYSTERESIS,
+            mode: ThermostatMode::Heat,
+            sensor,
+            actuator,
+            active: false,
+        };
+        thermostat.set_setpoint(setpoint)?;
+        Ok(thermostat)
+    }
+
+    /// Target temperature in degrees Celsius.
+    pub fn get_setpoint(&self) -> f64 {
+        self.setpoint
+    }
+
+    pub fn set_setpoint(&mut self, setpoint: f64) -> HomeResult<()> {
+        let (min, max) = Self::SETPOINT;
+        check_range("setpoint", setpoint, min, max)?;
+        self.setpoint = setpoint;
+        Ok(())
+    }
+
+    /// How far the temperature may stray from the setpoint before the actuator switches.
+    pub fn get_hysteresis(&self) -> f64 {
+        self.hysteresis
+    }
+
+    pub fn set_hysteresis(&mut self, hysteresis: f64) -> HomeResult<()> {
+        check_range("hysteresis", hysteresis, 0., Self::MAX_HYSTERESIS)?;
+        self.hysteresis = hysteresis;
+        Ok(())
+    }
+
+    pub fn get_mode(&self) -> ThermostatMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ThermostatMode) {
+        self.mode = mode;
+    }
+
+    /// The thermometer read.
+    pub fn get_sensor(&self) -> &DevicePath {
+        &self.sensor
+    }
+
+    pub fn set_sensor(&mut self, sensor: DevicePath) {
+        self.sensor = sensor;
+    }
+
+    /// The socket switched.
+    pub fn get_actuator(&self) -> &DevicePath {
+        &self.actuator
+    }
+
+    pub fn set_actuator(&mut self, actuator: DevicePath) {
+        self.actuator = actuator;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn demand(&self) -> Demand {
+        match (self.active, self.mode) {
+            (true, ThermostatMode::Heat) => Demand::Heating,
+            (true, ThermostatMode::Cool) => Demand::Cooling,
+            _ => Demand::Idle,
+        }
+    }
+
+    /// Updates the demand for a new `temperature` reading and tells whether
+    /// the actuator should be on.
+    pub fn control(&mut self, temperature: f64) -> bool {
+        let (low, high) = (
+            self.setpoint - self.hysteresis,
+            self.setpoint + self.hysteresis,
+        );
+        self.active = match self.mode {
+            ThermostatMode::Heat if temperature < low => true,
+            ThermostatMode::Heat if temperature > high => false,
+            ThermostatMode::Cool if temperature > high => true,
+            ThermostatMode::Cool if temperature < low => false,
+            ThermostatMode::Off => false,
+            _ => self.active,
+        };
+        self.active
+    }
+
+    /// Stops demanding when the sensor cannot be read.
+    pub fn idle(&mut self) {
+        self.active = false;
+    }
+}
+
+impl DeviceInfo for Thermostat {
+    fn device_info(&self) -> Vec<String> {
+        vec![
+            "thermostat".into(),
+            self.mode.to_string(),
+            format!("{}", self.setpoint),
+            self.demand().to_string(),
+        ]
+    }
+}
+
+impl ThermostatMode {
+    pub const NAMES: [&'static str; 3] = ["heat", "cool", "off"];
+}
+
+impl FromStr for ThermostatMode {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "heat" => Ok(Self::Heat),
+            "cool" => Ok(Self::Cool),
+            "off" => Ok(Self::Off),
+            _ => Err(format!("'{text}' is not one of heat, cool, off")),
+        }
+    }
+}
+
+impl fmt::Display for ThermostatMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Heat => "heat",
+            Self::Cool => "cool",
+            Self::Off => "off",
+        })
+    }
+}
+
+impl fmt::Display for Demand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Idle => "idle",
+            Self::Heating => "heating",
+            Self::Cooling => "cooling",
+        })
+    }
+}
+
+impl DevicePath {
+    pub fn new(room: &str, device: &str) -> Self {
+        Self {
+            room: room.into(),
+            device: device.into(),
+        }
+    }
+}
+
+impl FromStr for DevicePath {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text.split_once('/') {
+            Some((room, device)) if !room.is_empty() && !device.is_empty() => {
+                Ok(Self::new(room, device))
+            }
+            _ => Err(format!("'{text}' is not a device path like room/device")),
+        }
+    }
+}
+
+impl TryFrom<String> for DevicePath {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.parse()
+    }
+}
+
+impl From<DevicePath> for String {
+    fn from(path: DevicePath) -> Self {
+        path.to_string()
+    }
+}
+
+impl fmt::Display for DevicePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.room, self.device)
+    }
+}
+
+/// Property names taken by the device dict itself.
+const RESERVED_PROPERTIES: [&str; 2] = ["device", "kind"];
+
+impl Generic {
+    pub fn new(
+        kind: &str,
+        properties: BTreeMap<String, PropertyValue>,
+        schema: Option<BTreeMap<String, PropertySchema>>,
+    ) -> HomeResult<Self> {
+        if kind.is_empty() {
+            return Err(HomeError::invalid_value("kind", "must not be empty"));
+        }
+        let mut generic = Self {
+            kind: kind.into(),
+            properties: BTreeMap::new(),
+            schema,
+        };
+        if let Some(schema) = &generic.schema {
+            if let Some(missing) = schema.keys().find(|name| !properties.contains_key(*name)) {
+                return Err(HomeError::invalid_value(missing, "missing field"));
+            }
+        }
+        for (name, value) in properties {
+            generic.set(&name, value)?;
+        }
+        Ok(generic)
+    }
+
+    pub fn get_kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn get_schema(&self) -> Option<&BTreeMap<String, PropertySchema>> {
+        self.schema.as_ref()
+    }
+
+    pub fn properties(&self) -> impl Iterator<Item = (&String, &PropertyValue)> {
+        self.properties.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&PropertyValue> {
+        self.properties.get(name)
+    }
+
+    /// Sets the property, converting the value to the declared type if there is a schema.
+    pub fn set(&mut self, name: &str, value: PropertyValue) -> HomeResult<()> {
+        if RESERVED_PROPERTIES.contains(&name) {
+            return Err(HomeError::invalid_value(name, "reserved property name"));
+        }
+        let value = match &self.schema {
+            Some(schema) => schema
+                .get(name)
+                .ok_or_else(|| HomeError::invalid_value(name, "not declared in schema"))?
+                .check(name, value)?,
+            None => value,
+        };
+        if let PropertyValue::Number(n) = value {
+            if !n.is_finite() {
+                return Err(HomeError::invalid_value(name, "must be a finite number"));
+            }
+        }
+        self.properties.insert(name.into(), value);
+        Ok(())
+    }
+}
+
+impl DeviceInfo for Generic {
+    fn device_info(&self) -> Vec<String> {
+        let mut result = vec![String::from("generic"), self.kind.clone()];
+        for (name, value) in &self.properties {
+            result.push(format!("{name}={value}"));
+        }
+        result
+    }
+}
+
+impl PropertyValue {
+    /// Guesses the type of a value that came as text, e.g. from a query string.
+    pub fn infer(text: &str) -> Self {
+        match text {
+            "true" => PropertyValue::Bool(true),
+            "false" => PropertyValue::Bool(false),
+            _ => match text.parse::<f64>() {
+                Ok(n) if n.is_finite() => PropertyValue::Number(n),
+                _ => PropertyValue::Text(text.into()),
+            },
+        }
+    }
+}
+
+impl fmt::Display for PropertyValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PropertyValue::Bool(b) => write!(f, "{b}"),
+            PropertyValue::Number(n) => write!(f, "{n}"),
+            PropertyValue::Text(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+impl PropertySchema {
+    /// Converts `value` to this type, or explains why it does not fit.
+    pub fn check(&self, name: &str, value: PropertyValue) -> HomeResult<PropertyValue> {
+        let text = value.to_string();
+        match (self, value) {
+            (PropertySchema::Number { min, max }, value) => {
+                let n = match value {
+                    PropertyValue::Number(n) => n,
+                    PropertyValue::Text(s) => s
+                        .parse()
+                        .map_err(|_| HomeError::invalid_value(name, "expected a number"))?,
+                    PropertyValue::Bool(_) => {
+                        return Err(HomeError::invalid_value(name, "expected a number"))
+                    }
+                };
+                if !n.is_finite() {
+                    return Err(HomeError::invalid_value(name, "expected a finite number"));
+                }
+                if min.is_some_and(|min| n < min) || max.is_some_and(|max| n > max) {
+                    return Err(HomeError::invalid_value(
+                        name,
+                        &format!("{n} is out of range {}..{}", fmt_bound(min), fmt_bound(max)),
+                    ));
+                }
+                Ok(PropertyValue::Number(n))
+            }
+            (PropertySchema::Bool, PropertyValue::Bool(b)) => Ok(PropertyValue::Bool(b)),
+            (PropertySchema::Bool, PropertyValue::Text(s)) => {
+                let s = s.to_lowercase();
+                if TRUE_WORDS.contains(&s.as_str()) {
+                    Ok(PropertyValue::Bool(true))
+                } else if FALSE_WORDS.contains(&s.as_str()) {
+                    Ok(PropertyValue::Bool(false))
+                } else {
+                    Err(HomeError::invalid_value(name, "expected a boolean"))
+                }
+            }
+            (PropertySchema::Bool, _) => Err(HomeError::invalid_value(name, "expected a boolean")),
+            (PropertySchema::String, _) => Ok(PropertyValue::Text(text)),
+            (PropertySchema::Enum { values }, _) => {
+                if values.contains(&text) {
+                    Ok(PropertyValue::Text(text))
+                } else {
+                    Err(HomeError::invalid_value(
+                        name,
+                        &format!("expected one of {}", values.join(", ")),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+fn fmt_bound(bound: &Option<f64>) -> String {
+    bound.map(|b| b.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_socket() {
+        let mut device = Device::new_socket();
+        if let Device::Socket(mut socket) = device {
+            assert_eq!(220_f64, socket.voltage);
+            assert_eq!(0_f64, socket.current);
+            assert!(!socket.is_on());
+            assert_eq!(0_f64, socket.get_current_power());
+            socket.set_voltage(225_f64);
+            socket.set_current(3_f64);
+            socket.switch(true);
+            assert_eq!(225_f64, socket.voltage);
+            assert_eq!(3_f64, socket.current);
+            assert!(socket.is_on());
+            assert!((socket.get_current_power() - 675_f64).abs() < 1e-6);
+        } else {
+            panic!("Device::new_socket gives unexpected result.");
+        }
+    }
+
+    #[test]
+    fn test_property() {
+        let socket: Device = Socket::new(220., 2., true).into();
+        assert_eq!(Some(PropertyValue::Bool(true)), socket.property("state"));
+        assert_eq!(Some(PropertyValue::Number(440.)), socket.property("power"));
+        assert_eq!(None, socket.property("temperature"));
+        let thermometer = Device::new_thermometer();
+        assert_eq!(
+            Some(PropertyValue::Number(20.)),
+            thermometer.property("temperature")
+        );
+    }
+
+    #[test]
+    fn test_thermometer() {
+        let device = Device::new_thermometer();
+        if let Device::Thermometer(mut thermometer) = device {
+            assert_eq!(20_f64, thermometer.temperature);
+            let thermometer = thermometer.temperature(25_f64);
+            assert_eq!(25_f64, thermometer.temperature);
+        } else {
+            panic!("Device::new_thermometer gives unexpected result.");
+        }
+    }
+
+    #[test]
+    fn test_light() {
+        assert!(Light::new(true, 101.).is_err());
+        assert!(Light::new(true, f64::NAN).is_err());
+        let mut light = Light::new(true, 80.).unwrap();
+        light.set_color_temperature(2700.).unwrap();
+        light.set_color("#FF8800".parse().unwrap());
+        assert_eq!(None, light.get_color_temperature());
+        assert_eq!("#ff8800", light.get_color().unwrap().to_string());
+        assert!(light.set_brightness(101.).is_err());
+        assert!(light.set_color_temperature(500.).is_err());
+        assert!(light.set_transition(-1.).is_err());
+        assert_eq!(80., light.get_brightness());
+        assert!("#ff88".parse::<Rgb>().is_err());
+        assert!("gg8800".parse::<Rgb>().is_err());
+
+        let device = Device::from(light);
+        let dict = device.device_dict();
+        assert_eq!("light", dict["device"]);
+        assert_eq!("on", dict["state"]);
+        assert_eq!("80", dict["brightness"]);
+        assert_eq!("#ff8800", dict["color"]);
+        assert!(!dict.contains_key("color_temperature"));
+        assert_eq!(vec!["light", "on", "80%", "#ff8800"], device.device_info());
+        assert_eq!(
+            Some(PropertyValue::Text("#ff8800".into())),
+            device.property("color")
+        );
+    }
+
+    #[test]
+    fn test_thermostat() {
+        let mut thermostat =
+            Thermostat::new(21., "R/T".parse().unwrap(), DevicePath::new("R", "S")).unwrap();
+        assert!(
+            Thermostat::new(100., DevicePath::new("R", "T"), DevicePath::new("R", "S")).is_err()
+        );
+        assert!(thermostat.set_hysteresis(-1.).is_err());
+        assert!(!thermostat.control(20.8));
+        assert!(thermostat.control(20.4));
+        assert_eq!(Demand::Heating, thermostat.demand());
+        assert!(thermostat.control(21.5));
+        assert!(!thermostat.control(21.6));
+
+        thermostat.set_mode(ThermostatMode::Cool);
+        assert!(thermostat.control(21.6));
+        assert_eq!(Demand::Cooling, thermostat.demand());
+        thermostat.set_mode(ThermostatMode::Off);
+        assert!(!thermostat.control(30.));
+
+        let device = Device::from(thermostat);
+        let dict = device.device_dict();
+        assert_eq!("thermostat", dict["device"]);
+        assert_eq!("off", dict["mode"]);
+        assert_eq!("R/T", dict["sensor"]);
+        assert_eq!("idle", dict["demand"]);
+        assert_eq!(
+            vec!["thermostat", "off", "21", "idle"],
+            device.device_info()
+        );
+        assert!("R".parse::<DevicePath>().is_err());
+        assert!("/T".parse::<DevicePath>().is_err());
+    }
+
+    #[test]
+    fn test_climate_sensor() {
+        let mut sensor = ClimateSensor::new(21., 50., 600.).unwrap();
+        assert_eq!(Some(10.2), sensor.dew_point());
+        assert_eq!(Comfort::Comfortable, sensor.comfort());
+        assert!(ClimateSensor::new(21., 101., 600.).is_err());
+        assert!(sensor.set_co2(-1.).is_err());
+        assert!(sensor.set_voc(501.).is_err());
+        sensor.set_voc(300.).unwrap();
+        assert_eq!(Comfort::Stuffy, sensor.comfort());
+        sensor.set_voc(80.).unwrap();
+        sensor.set_humidity(0.).unwrap();
+        assert_eq!(None, sensor.dew_point());
+        assert_eq!(Comfort::Dry, sensor.comfort());
+
+        let device = Device::from(sensor);
+        let dict = device.device_dict();
+        assert_eq!("climate", dict["device"]);
+        assert_eq!("80", dict["voc"]);
+        assert_eq!("dry", dict["comfort"]);
+        assert!(!dict.contains_key("dew_point"));
+        assert_eq!(
+            vec!["climate", "21°C", "0%", "600ppm", "VOC 80", "dry"],
+            device.device_info()
+        );
+    }
+
+    #[test]
+    fn test_power_strip() {
+        assert!(PowerStrip::new(230., 16., 0).is_err());
+        assert!(PowerStrip::new(230., 16., 9).is_err());
+        assert!(PowerStrip::new(-230., 16., 4).is_err());
+        assert!(PowerStrip::new(230., f64::NAN, 4).is_err());
+        assert!(PowerStrip::new(f64::INFINITY, 16., 4).is_err());
+        let mut strip = PowerStrip::new(230., 16., 4).unwrap();
+        strip.set_current(1, 10.).unwrap();
+        strip.set_current(2, 8.).unwrap();
+        strip.switch(1, true).unwrap();
+        assert_eq!(10., strip.load());
+        assert_eq!(2300., strip.power());
+        strip.check_load().unwrap();
+        strip.switch(2, true).unwrap();
+        assert_eq!(18., strip.load());
+        assert!(strip.check_load().is_err());
+        strip.switch(1, false).unwrap();
+        strip.check_load().unwrap();
+        assert!(strip.set_max_current(5.).is_err());
+        assert_eq!(16., strip.get_max_current());
+        strip.set_max_current(8.).unwrap();
+        assert!(strip.set_voltage(f64::NAN).is_err());
+        assert!(strip.set_voltage(-1.).is_err());
+        strip.set_voltage(230.).unwrap();
+        assert!(strip.switch(0, true).is_err());
+        assert!(strip.switch(5, true).is_err());
+        assert!(strip.set_current(3, -1.).is_err());
+
+        assert_eq!(
+            Some((12, true)),
+            PowerStrip::parse_channel_field("channel_12_current")
+        );
+        assert_eq!(
+            Some((3, false)),
+            PowerStrip::parse_channel_field("channel_3")
+        );
+        assert_eq!(None, PowerStrip::parse_channel_field("channel_03"));
+        assert_eq!(None, PowerStrip::parse_channel_field("channel_x"));
+
+        let device = Device::from(strip);
+        let dict = device.device_dict();
+        assert_eq!("power_strip", dict["device"]);
+        assert_eq!("4", dict["channels"]);
+        assert_eq!("8", dict["current"]);
+        assert_eq!("off", dict["channel_1"]);
+        assert_eq!("on", dict["channel_2"]);
+        assert_eq!("8", dict["channel_2_current"]);
+        assert_eq!(
+            Some(PropertyValue::Number(10.)),
+            device.property("channel_1_current")
+        );
+        assert_eq!(
+            Some(PropertyValue::Bool(true)),
+            device.property("channel_2")
+        );
+        assert_eq!(None, device.property("channel_5"));
+        assert_eq!(
+            vec!["power_strip", "off", "on", "off", "off", "8", "230"],
+            device.device_info()
+        );
+    }
+
+    #[test]
+    fn test_binary_state() {
+        let at = |time: &str| -> Timestamp { time.parse().unwrap() };
+        let mut state = BinaryState::new(false);
+        assert_eq!(Some(false), state.stamp(at("2024-03-01T07:00:00Z")));
+        assert_eq!(None, state.stamp(at("2024-03-01T07:01:00Z")));
+        state.set(false);
+        assert!(!state.is_pending());
+
+        state.set(true);
+        assert_eq!(Some(false), state.stamp(at("2024-03-01T07:02:00Z")));
+        state.set(true);
+        assert_eq!(None, state.stamp(at("2024-03-01T07:03:00Z")));
+        assert_eq!(Some(at("2024-03-01T07:02:00Z")), state.changed());
+        assert_eq!(Some(at("2024-03-01T07:03:00Z")), state.last_triggered());
+        state.set(false);
+        assert_eq!(Some(true), state.stamp(at("2024-03-01T07:04:00Z")));
+
+        let mut motion = Motion::new(true);
+        motion.set_reset_after(60.).unwrap();
+        assert!(!motion.reset_due(at("2024-03-01T08:00:00Z")));
+        motion.state.stamp(at("2024-03-01T07:00:00Z"));
+        assert!(!motion.reset_due(at("2024-03-01T07:00:59Z")));
+        assert!(motion.reset_due(at("2024-03-01T07:01:00Z")));
+        assert!(motion.set_reset_after(-1.).is_err());
+
+        let device = Device::from(motion);
+        let dict = device.device_dict();
+        assert_eq!("detected", dict["state"]);
+        assert_eq!("60", dict["reset_after"]);
+        assert_eq!("2024-03-01T07:00:00Z", dict["last_triggered"]);
+        assert_eq!(Some(PropertyValue::Bool(true)), device.property("detected"));
+        assert_eq!(vec!["leak", "dry"], Leak::new(false).device_info());
+        assert_eq!(Some("open"), Device::from(Contact::new(true)).alert());
+    }
+
+    #[test]
+    fn test_generic() {
+        let schema = BTreeMap::from([
+            (
+                "level".to_string(),
+                PropertySchema::Number {
+                    min: Some(0.),
+                    max: Some(100.),
+                },
+            ),
+            (
+                "mode".to_string(),
+                PropertySchema::Enum {
+                    values: vec!["auto".into(), "manual".into()],
+                },
+            ),
+        ]);
+        let properties = BTreeMap::from([
+            ("level".to_string(), PropertyValue::infer("40")),
+            ("mode".to_string(), PropertyValue::Text("auto".into())),
+        ]);
+        let mut dimmer = Generic::new("dimmer", properties, Some(schema)).unwrap();
+        assert_eq!(Some(&PropertyValue::Number(40.)), dimmer.get("level"));
+        assert!(dimmer.set("level", PropertyValue::Number(101.)).is_err());
+        assert!(dimmer
+            .set("mode", PropertyValue::Text("off".into()))
+            .is_err());
+        assert!(dimmer
+            .set("colour", PropertyValue::Text("red".into()))
+            .is_err());
+        dimmer
+            .set("level", PropertyValue::Text("75".into()))
+            .unwrap();
+        let dict = Device::from(dimmer).device_dict();
+        assert_eq!("generic", dict["device"]);
+        assert_eq!("dimmer", dict["kind"]);
+        assert_eq!("75", dict["level"]);
+        assert_eq!("auto", dict["mode"]);
+
+        let mut free = Generic::new("sensor", BTreeMap::new(), None).unwrap();
+        free.set("open", PropertyValue::infer("true")).unwrap();
+        assert_eq!(Some(&PropertyValue::Bool(true)), free.get("open"));
+        assert!(free.set("kind", PropertyValue::infer("x")).is_err());
+        assert!(Generic::new("", BTreeMap::new(), None).is_err());
+    }
+}
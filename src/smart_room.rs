@@ -1,8 +1,10 @@
+use crate::error::{HomeError, HomeResult};
 use crate::smart_device::Device;
+use serde::{Deserialize, Serialize};
 use std::collections::{hash_map::Entry, HashMap};
 
 #[allow(dead_code, unused)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Room {
     devices: HashMap<String, Device>,
 }
@@ -23,15 +25,33 @@ impl Room {
         self.devices.values()
     }
 
-    pub fn add_device(&mut self, unique_name: &str, device: Device) -> Option<&Device> {
+    pub fn devices(&self) -> impl Iterator<Item = (&String, &Device)> {
+        self.devices.iter()
+    }
+
+    /// Adds the device, failing if the name is taken.
+    ///
+    /// The room does not know its own name, so errors name no room
+    /// until `Home` fills it in with [`HomeError::in_room`].
+    pub fn add_device(&mut self, unique_name: &str, device: Device) -> HomeResult<&Device> {
         match self.devices.entry(unique_name.into()) {
-            Entry::Occupied(_) => None,
-            Entry::Vacant(entry) => Some(entry.insert(device)),
+            Entry::Occupied(_) => Err(HomeError::DeviceExists {
+                room: String::new(),
+                device: unique_name.into(),
+            }),
+            Entry::Vacant(entry) => Ok(entry.insert(device)),
         }
     }
 
-    pub fn remove_device(&mut self, device_name: &str) -> Option<Device> {
-        self.devices.remove(device_name)
+    /// Inserts the device or replaces the one with the same name, returning the old one.
+    pub fn replace_device(&mut self, name: &str, device: Device) -> Option<Device> {
+        self.devices.insert(name.into(), device)
+    }
+
+    pub fn remove_device(&mut self, device_name: &str) -> HomeResult<Device> {
+        self.devices
+            .remove(device_name)
+            .ok_or_else(|| HomeError::device_not_found("", device_name))
     }
 
     pub fn get_device_by_name(&self, device_name: &str) -> Option<&Device> {
@@ -62,10 +82,10 @@ mod tests {
     #[test]
     fn test_add_get_device() {
         let mut room = Room::new();
-        assert!(room.add_device("S1", Device::new_socket()).is_some());
-        assert!(room.add_device("S2", Device::new_socket()).is_some());
-        assert!(room.add_device("T", Device::new_thermometer()).is_some());
-        assert!(room.add_device("S1", Device::new_thermometer()).is_none());
+        assert!(room.add_device("S1", Device::new_socket()).is_ok());
+        assert!(room.add_device("S2", Device::new_socket()).is_ok());
+        assert!(room.add_device("T", Device::new_thermometer()).is_ok());
+        assert!(room.add_device("S1", Device::new_thermometer()).is_err());
         assert_eq!(3, room.device_names_list().count());
         assert_eq!(3, room.device_list().count());
         assert_eq!(
@@ -86,15 +106,15 @@ mod tests {
     #[test]
     fn test_remove_device() {
         let mut room = Room::new();
-        assert!(room.add_device("S1", Device::new_socket()).is_some());
-        assert!(room.add_device("S2", Device::new_socket()).is_some());
-        assert!(room.add_device("T", Device::new_thermometer()).is_some());
+        assert!(room.add_device("S1", Device::new_socket()).is_ok());
+        assert!(room.add_device("S2", Device::new_socket()).is_ok());
+        assert!(room.add_device("T", Device::new_thermometer()).is_ok());
         assert_eq!(3, room.device_names_list().count());
         assert_eq!(3, room.device_list().count());
-        assert!(room.remove_device("S1").is_some());
-        assert!(room.remove_device("S2").is_some());
-        assert!(room.remove_device("T").is_some());
-        assert!(room.remove_device("No device").is_none());
+        assert!(room.remove_device("S1").is_ok());
+        assert!(room.remove_device("S2").is_ok());
+        assert!(room.remove_device("T").is_ok());
+        assert!(room.remove_device("No device").is_err());
         assert!(room.device_names_list().next().is_none());
         assert!(room.device_list().next().is_none())
     }
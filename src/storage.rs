@@ -0,0 +1,254 @@
+use crate::home::Home;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error("I/O error {0}.")]
+    Io(#[from] io::Error),
+    #[error("JSON error {0}.")]
+    Json(#[from] serde_json::Error),
+}
+
+type StorageResult<T> = Result<T, StorageError>;
+
+/// Where the server keeps its `Home` between restarts.
+/// `Storage::memory()` keeps nothing and every save is a no-op.
+#[derive(Debug, Clone, Default)]
+pub struct Storage {
+    path: Option<PathBuf>,
+    /// Number of the last snapshot taken.
+    taken: Arc<AtomicU64>,
+    /// Number of the last snapshot written; held while a snapshot is written.
+    written: Arc<Mutex<u64>>,
+}
+
+/// The home encoded for the state file, numbered in the order the snapshots were taken.
+#[derive(Debug)]
+pub struct Snapshot {
+    number: u64,
+    contents: Option<Vec<u8>>,
+}
+
+impl Storage {
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn memory() -> Self {
+        Self::default()
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Loads the home from the state file, or returns `None`
+    /// if there is no file yet (or the storage is in-memory).
+    pub fn load(&self) -> StorageResult<Option<Home>> {
+        match &self.path {
+            Some(path) if path.exists() => Home::load(path).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn save(&self, home: &Home) -> StorageResult<()> {
+        match &self.path {
+            Some(path) => home.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Like [`Storage::save`], but the file is written and synced on a blocking thread
+    /// so the async executor keeps serving while the disk is slow.
+    /// The home is encoded before the first await.
+    pub async fn save_async(&self, home: &Home) -> StorageResult<()> {
+        self.write(self.snapshot(home)?).await
+    }
+
+    /// Encodes the home for [`Storage::write`]. Taken under the lock the home was
+    /// changed under, snapshots are numbered in the order of the changes.
+    pub fn snapshot(&self, home: &Home) -> StorageResult<Snapshot> {
+        let contents = match self.path {
+            Some(_) => Some(encode_home(home)?),
+            None => None,
+        };
+        Ok(Snapshot {
+            number: self.taken.fetch_add(1, Ordering::SeqCst) + 1,
+            contents,
+        })
+    }
+
+    /// Writes and syncs the snapshot on a blocking thread, unless a later one has been
+    /// written already, so the lock on the home can be released before the write.
+    pub async fn write(&self, snapshot: Snapshot) -> StorageResult<()> {
+        let (Some(path), Some(contents)) = (self.path.clone(), snapshot.contents) else {
+            return Ok(());
+        };
+        let mut written = self.written.lock().await;
+        if *written > snapshot.number {
+            return Ok(());
+        }
+        tokio::task::spawn_blocking(move || write_file(&path, &contents))
+            .await
+            .map_err(io::Error::other)??;
+        *written = snapshot.number;
+        Ok(())
+    }
+}
+
+pub(crate) fn read_home(path: &Path) -> StorageResult<Home> {
+    let file = File::open(path)?;
+    Ok(serde_json::from_reader(io::BufReader::new(file))?)
+}
+
+pub(crate) fn write_home(path: &Path, home: &Home) -> StorageResult<()> {
+    write_file(path, &encode_home(home)?)
+}
+
+fn encode_home(home: &Home) -> StorageResult<Vec<u8>> {
+    let mut contents = serde_json::to_vec_pretty(home)?;
+    contents.push(b'\n');
+    Ok(contents)
+}
+
+/// Writes into a sibling temporary file first and renames it over `path`,
+/// so a crash never leaves a half-written state file behind. The directory is
+/// synced after the rename so that the rename itself survives a power loss.
+fn write_file(path: &Path, contents: &[u8]) -> StorageResult<()> {
+    let tmp_path = tmp_path_for(path);
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    sync_dir(path)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories cannot be opened as files on other platforms; the rename is what we have.
+#[cfg(not(unix))]
+fn sync_dir(_: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+fn tmp_path_for(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Rule;
+    use crate::smart_device::{Device, Generic, PropertyValue, Socket};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_save_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("home.json");
+        let storage = Storage::file(&path);
+        assert!(storage.load().unwrap().is_none());
+
+        let mut home = Home::restore();
+        home.add_room("Kitchen").unwrap();
+        home.add_device("Kitchen", "Kettle", Socket::new(230., 8., true).into())
+            .unwrap();
+        let properties = BTreeMap::from([
+            ("level".to_string(), PropertyValue::Number(40.)),
+            ("on".to_string(), PropertyValue::Bool(true)),
+            ("mode".to_string(), PropertyValue::Text("auto".into())),
+        ]);
+        let dimmer = Generic::new("dimmer", properties, None).unwrap();
+        home.add_device("Kitchen", "Dimmer", dimmer.clone().into())
+            .unwrap();
+        let rule: Rule = serde_json::from_value(serde_json::json!({
+            "triggers": [{"type": "time", "at": "07:30"}],
+            "actions": [{"type": "switch", "room": "Kitchen", "device": "Kettle", "on": true}]
+        }))
+        .unwrap();
+        home.put_rule("tea", rule.clone());
+        storage.save(&home).unwrap();
+        assert!(path.exists());
+        assert!(!tmp_path_for(&path).exists());
+
+        let loaded = storage.load().unwrap().unwrap();
+        assert_eq!(home.get_name(), loaded.get_name());
+        assert_eq!(
+            &Device::Socket(Socket::new(230., 8., true)),
+            loaded.get_device_by_path("Kitchen", "Kettle").unwrap()
+        );
+        assert_eq!(
+            &Device::new_thermometer(),
+            loaded.get_device_by_path("R", "T").unwrap()
+        );
+        assert_eq!(
+            &Device::Generic(dimmer),
+            loaded.get_device_by_path("Kitchen", "Dimmer").unwrap()
+        );
+        assert_eq!(Some(&rule), loaded.get_rule("tea"));
+    }
+
+    #[actix_web::test]
+    async fn test_save_async() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("home.json");
+        let home = Home::restore();
+        Storage::file(&path).save_async(&home).await.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), encode_home(&home).unwrap());
+        let missing = Storage::file(dir.path().join("missing").join("home.json"));
+        assert!(matches!(
+            missing.save_async(&home).await,
+            Err(StorageError::Io(_))
+        ));
+    }
+
+    #[actix_web::test]
+    async fn test_write_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("home.json");
+        let storage = Storage::file(&path);
+        let mut home = Home::restore();
+        let old = storage.snapshot(&home).unwrap();
+        home.add_room("Kitchen").unwrap();
+        let new = storage.snapshot(&home).unwrap();
+        storage.write(new).await.unwrap();
+        storage.write(old).await.unwrap();
+        assert_eq!(fs::read(&path).unwrap(), encode_home(&home).unwrap());
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let storage = Storage::memory();
+        storage.save(&Home::restore()).unwrap();
+        assert!(storage.load().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_load_broken_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("home.json");
+        fs::write(&path, "{ \"name\": ").unwrap();
+        assert!(matches!(
+            Storage::file(&path).load(),
+            Err(StorageError::Json(_))
+        ));
+    }
+}
@@ -0,0 +1,382 @@
+//! Telemetry: the history of device properties, for plotting temperature and power over time.
+//!
+//! [`Telemetry`] is attached to the [`EventBus`] as a sink and records the new value
+//! of every `property_changed` event with the time it was published. Each property keeps
+//! its latest [`SAMPLES`] values; a removed device loses its history.
+//!
+//! `GET /api/v1/rooms/{room_name}/devices/{device_name}/history` returns it:
+//!
+//! ```json
+//! {"room": "R", "device": "T", "properties": {"temperature": [
+//!   {"time": "2024-03-01T07:00:00Z", "value": 20.5}
+//! ]}}
+//! ```
+//!
+//! `?from=` and `?to=` narrow it down to a time range and `?property=` to one property.
+//! `?bucket=5m` downsamples numeric properties into the count, min, max and average
+//! of every five minutes, counted from the Unix epoch; other values are left out.
+
+use crate::events::{ChangeEvent, EventBus, EventSink, SequencedEvent};
+use crate::smart_device::PropertyValue;
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use utoipa::{IntoParams, ToSchema};
+
+/// How many values of each property are kept.
+pub const SAMPLES: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Sample {
+    #[schema(value_type = String, format = DateTime)]
+    pub time: Timestamp,
+    pub value: PropertyValue,
+}
+
+/// The numeric values of a property within one bucket.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Bucket {
+    #[schema(value_type = String, format = DateTime)]
+    pub start: Timestamp,
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum Series {
+    Samples(Vec<Sample>),
+    Buckets(Vec<Bucket>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct DeviceHistory {
+    pub room: String,
+    pub device: String,
+    /// Values of each property, oldest first.
+    pub properties: BTreeMap<String, Series>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct HistoryQuery {
+    /// Only values recorded at or after this time, e.g. `2024-03-01T07:00:00Z`.
+    #[param(value_type = Option<String>, format = DateTime)]
+    pub from: Option<Timestamp>,
+    /// Only values recorded before this time.
+    #[param(value_type = Option<String>, format = DateTime)]
+    pub to: Option<Timestamp>,
+    /// Only the values of this property.
+    pub property: Option<String>,
+    /// Downsample numeric values into buckets this long, e.g. `5m`, `1h` or `PT15M`.
+    #[param(value_type = Option<String>)]
+    pub bucket: Option<SignedDuration>,
+}
+
+impl HistoryQuery {
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from > to {
+                return Err(format!("'from' {from} is after 'to' {to}"));
+            }
+        }
+        if let Some(bucket) = self.bucket {
+            if !bucket.is_positive() {
+                return Err(format!("bucket '{bucket:#}' is not positive"));
+            }
+        }
+        Ok(())
+    }
+
+    fn contains(&self, time: Timestamp) -> bool {
+        self.from.is_none_or(|from| time >= from) && self.to.is_none_or(|to| time < to)
+    }
+}
+
+/// Bounded history of every device property, recorded from the change events.
+#[derive(Debug)]
+pub struct Telemetry {
+    recorder: Arc<Mutex<Recorder>>,
+}
+
+#[derive(Debug)]
+struct Recorder {
+    devices: HashMap<(String, String), BTreeMap<String, VecDeque<Sample>>>,
+    capacity: usize,
+}
+
+impl Telemetry {
+    /// Starts recording the events published on `bus` from now on.
+    pub fn new(bus: &EventBus) -> Self {
+        Self::with_capacity(bus, SAMPLES)
+    }
+
+    /// A store keeping the latest `capacity` values of each property.
+    pub fn with_capacity(bus: &EventBus, capacity: usize) -> Self {
+        let recorder = Arc::new(Mutex::new(Recorder {
+            devices: HashMap::new(),
+            capacity,
+        }));
+        bus.attach(recorder.clone());
+        Self { recorder }
+    }
+
+    /// What is recorded about a device, up to the latest published event.
+    pub fn history(&self, room: &str, device: &str, query: &HistoryQuery) -> DeviceHistory {
+        let recorder = self.recorder();
+        let properties = recorder
+            .devices
+            .get(&(room.to_string(), device.to_string()))
+            .into_iter()
+            .flatten()
+            .filter(|(property, _)| query.property.as_ref().is_none_or(|name| name == *property))
+            .map(|(property, samples)| {
+                let samples = samples.iter().filter(|sample| query.contains(sample.time));
+                let series = match query.bucket {
+                    Some(bucket) => Series::Buckets(downsample(samples, bucket)),
+                    None => Series::Samples(samples.cloned().collect()),
+                };
+                (property.clone(), series)
+            })
+            .collect();
+        DeviceHistory {
+            room: room.into(),
+            device: device.into(),
+            properties,
+        }
+    }
+
+    fn recorder(&self) -> MutexGuard<'_, Recorder> {
+        self.recorder.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl EventSink for Mutex<Recorder> {
+    fn record(&self, event: &SequencedEvent) {
+        self.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .record(event.time, &event.event);
+    }
+}
+
+impl Recorder {
+    fn record(&mut self, time: Timestamp, event: &ChangeEvent) {
+        match event {
+            ChangeEvent::PropertyChanged {
+                room,
+                device,
+                property,
+                new: Some(new),
+                ..
+            } => {
+                let samples = self
+                    .devices
+                    .entry((room.clone(), device.clone()))
+                    .or_default()
+                    .entry(property.clone())
+                    .or_default();
+                if samples.len() == self.capacity {
+                    samples.pop_front();
+                }
+                if self.capacity > 0 {
+                    samples.push_back(Sample {
+                        time,
+                        value: parse_value(new.clone()),
+                    });
+                }
+            }
+            ChangeEvent::DeviceRemoved { room, device, .. } => {
+                self.devices.remove(&(room.clone(), device.clone()));
+            }
+            ChangeEvent::RoomRemoved { room } => {
+                self.devices
+                    .retain(|(device_room, _), _| device_room != room);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Device dicts hold text; numbers are turned back into numbers so they can be plotted.
+fn parse_value(text: String) -> PropertyValue {
+    match text.parse() {
+        Ok(number) => PropertyValue::Number(number),
+        Err(_) => PropertyValue::Text(text),
+    }
+}
+
+fn downsample<'a>(
+    samples: impl Iterator<Item = &'a Sample>,
+    bucket: SignedDuration,
+) -> Vec<Bucket> {
+    let size = bucket.as_nanos();
+    let mut buckets: Vec<(Bucket, f64)> = Vec::new();
+    for sample in samples {
+        let PropertyValue::Number(value) = sample.value else {
+            continue;
+        };
+        let start = sample.time.as_nanosecond().div_euclid(size) * size;
+        match buckets.last_mut() {
+            Some((last, sum)) if last.start.as_nanosecond() == start => {
+                last.count += 1;
+                last.min = last.min.min(value);
+                last.max = last.max.max(value);
+                *sum += value;
+            }
+            _ => {
+                let Ok(start) = Timestamp::from_nanosecond(start) else {
+                    continue;
+                };
+                let bucket = Bucket {
+                    start,
+                    count: 1,
+                    min: value,
+                    max: value,
+                    avg: value,
+                };
+                buckets.push((bucket, value));
+            }
+        }
+    }
+    buckets
+        .into_iter()
+        .map(|(bucket, sum)| Bucket {
+            avg: sum / bucket.count as f64,
+            ..bucket
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use std::sync::Arc;
+
+    fn temperature(value: &str) -> ChangeEvent {
+        ChangeEvent::PropertyChanged {
+            room: "R".into(),
+            device: "T".into(),
+            device_type: "thermometer".into(),
+            property: "temperature".into(),
+            old: None,
+            new: Some(value.into()),
+        }
+    }
+
+    fn values(series: &Series) -> Vec<PropertyValue> {
+        match series {
+            Series::Samples(samples) => samples.iter().map(|s| s.value.clone()).collect(),
+            Series::Buckets(_) => panic!("expected samples"),
+        }
+    }
+
+    fn recorded(clock: &Arc<ManualClock>, capacity: usize) -> (EventBus, Telemetry) {
+        let bus = EventBus::with_clock(clock.clone());
+        let telemetry = Telemetry::with_capacity(&bus, capacity);
+        for value in ["20", "21", "23", "22"] {
+            bus.publish([temperature(value)]);
+            clock.advance(SignedDuration::from_mins(1));
+        }
+        (bus, telemetry)
+    }
+
+    #[test]
+    fn test_history() {
+        let clock = Arc::new(ManualClock::new("2024-03-01T07:00[UTC]".parse().unwrap()));
+        let (bus, telemetry) = recorded(&clock, 3);
+        let history = telemetry.history("R", "T", &HistoryQuery::default());
+        assert_eq!(
+            [21., 23., 22.].map(PropertyValue::Number).to_vec(),
+            values(&history.properties["temperature"])
+        );
+
+        let query = HistoryQuery {
+            from: Some("2024-03-01T07:02Z".parse().unwrap()),
+            to: Some("2024-03-01T07:03Z".parse().unwrap()),
+            ..Default::default()
+        };
+        let history = telemetry.history("R", "T", &query);
+        assert_eq!(
+            vec![PropertyValue::Number(23.)],
+            values(&history.properties["temperature"])
+        );
+        let query = HistoryQuery {
+            property: Some("state".into()),
+            ..Default::default()
+        };
+        assert!(telemetry.history("R", "T", &query).properties.is_empty());
+
+        bus.publish([ChangeEvent::DeviceRemoved {
+            room: "R".into(),
+            device: "T".into(),
+            device_type: "thermometer".into(),
+        }]);
+        assert!(telemetry
+            .history("R", "T", &HistoryQuery::default())
+            .properties
+            .is_empty());
+    }
+
+    #[test]
+    fn test_burst() {
+        let bus = EventBus::new();
+        let telemetry = Telemetry::new(&bus);
+        // More than a lagging subscriber could keep up with.
+        bus.publish((0..5000).map(|value| temperature(&value.to_string())));
+        let history = telemetry.history("R", "T", &HistoryQuery::default());
+        assert_eq!(5000, values(&history.properties["temperature"]).len());
+    }
+
+    #[test]
+    fn test_downsample() {
+        let clock = Arc::new(ManualClock::new("2024-03-01T07:00[UTC]".parse().unwrap()));
+        let (bus, telemetry) = recorded(&clock, SAMPLES);
+        bus.publish([temperature("broken")]);
+        let query = HistoryQuery {
+            bucket: Some(SignedDuration::from_mins(2)),
+            ..Default::default()
+        };
+        let history = telemetry.history("R", "T", &query);
+        let Series::Buckets(buckets) = &history.properties["temperature"] else {
+            panic!("expected buckets");
+        };
+        assert_eq!(
+            vec![
+                Bucket {
+                    start: "2024-03-01T07:00Z".parse().unwrap(),
+                    count: 2,
+                    min: 20.,
+                    max: 21.,
+                    avg: 20.5,
+                },
+                Bucket {
+                    start: "2024-03-01T07:02Z".parse().unwrap(),
+                    count: 2,
+                    min: 22.,
+                    max: 23.,
+                    avg: 22.5,
+                },
+            ],
+            *buckets
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let query: HistoryQuery = serde_json::from_value(serde_json::json!({
+            "from": "2024-03-01T08:00:00Z", "to": "2024-03-01T07:00:00Z"
+        }))
+        .unwrap();
+        assert!(query.validate().is_err());
+        let query: HistoryQuery =
+            serde_json::from_value(serde_json::json!({"bucket": "0s"})).unwrap();
+        assert!(query.validate().is_err());
+        let query: HistoryQuery =
+            serde_json::from_value(serde_json::json!({"bucket": "5m"})).unwrap();
+        assert_eq!(Ok(()), query.validate());
+    }
+}
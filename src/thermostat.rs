@@ -0,0 +1,193 @@
+//! Closed-loop control by thermostats.
+//!
+//! Whenever the home changes, every [`Thermostat`] reads the thermometer it is bound to
+//! and switches its socket to match its demand. While a thermostat is off, or its
+//! thermometer is gone, the socket is left alone, except that it is switched off when
+//! the thermostat stops demanding.
+
+use crate::device_kind::DeviceRegistry;
+use crate::events::{self, ChangeEvent};
+use crate::home::Home;
+use crate::smart_device::{Device, Thermostat, ThermostatMode};
+
+/// Lets every thermostat react to the current readings, returning what changed.
+pub fn regulate(home: &mut Home, registry: &DeviceRegistry) -> Vec<ChangeEvent> {
+    let mut thermostats: Vec<_> = home
+        .room_names_list()
+        .filter_map(|room_name| Some((room_name, home.get_room_by_name(room_name)?)))
+        .flat_map(|(room_name, room)| {
+            room.devices()
+                .filter(|(_, device)| matches!(device, Device::Thermostat(_)))
+                .map(move |(name, _)| (room_name.clone(), name.clone()))
+        })
+        .collect();
+    thermostats.sort();
+    thermostats
+        .iter()
+        .flat_map(|(room, name)| regulate_one(home, registry, room, name))
+        .collect()
+}
+
+fn regulate_one(
+    home: &mut Home,
+    registry: &DeviceRegistry,
+    room: &str,
+    name: &str,
+) -> Vec<ChangeEvent> {
+    let Some(Device::Thermostat(old)) = home.get_device_by_path(room, name).cloned() else {
+        return Vec::new();
+    };
+    let mut thermostat = old.clone();
+    let sensor = thermostat.get_sensor();
+    let temperature = match home.get_device_by_path(&sensor.room, &sensor.device) {
+        Some(Device::Thermometer(thermometer)) => Some(thermometer.get_temperature()),
+        _ => None,
+    };
+    match temperature {
+        Some(temperature) => thermostat.control(temperature),
+        None => {
+            thermostat.idle();
+            false
+        }
+    };
+    let controlling = temperature.is_some() && thermostat.get_mode() != ThermostatMode::Off;
+    let mut changes = Vec::new();
+    if controlling || old.is_active() {
+        changes.extend(switch_actuator(home, registry, &thermostat));
+    }
+    if thermostat != old {
+        changes.extend(replace(home, registry, room, name, thermostat.into()));
+    }
+    changes
+}
+
+fn switch_actuator(
+    home: &mut Home,
+    registry: &DeviceRegistry,
+    thermostat: &Thermostat,
+) -> Vec<ChangeEvent> {
+    let actuator = thermostat.get_actuator();
+    match home.get_device_by_path(&actuator.room, &actuator.device) {
+        Some(Device::Socket(socket)) if socket.is_on() != thermostat.is_active() => {
+            let mut socket = socket.clone();
+            socket.switch(thermostat.is_active());
+            replace(
+                home,
+                registry,
+                &actuator.room,
+                &actuator.device,
+                socket.into(),
+            )
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn replace(
+    home: &mut Home,
+    registry: &DeviceRegistry,
+    room: &str,
+    name: &str,
+    new: Device,
+) -> Vec<ChangeEvent> {
+    match home.get_device_by_path_mut(room, name) {
+        Some(device) => {
+            let changes = events::device_changed(registry, room, name, device, &new);
+            *device = new;
+            changes
+        }
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::smart_device::{DevicePath, Socket, Thermometer};
+
+    fn home(temperature: f64) -> Home {
+        let mut home = Home::new("H");
+        home.add_room("R").unwrap();
+        home.add_device("R", "T", Thermometer::new(temperature).into())
+            .unwrap();
+        home.add_device("R", "S", Socket::new(220., 5., false).into())
+            .unwrap();
+        let thermostat =
+            Thermostat::new(21., DevicePath::new("R", "T"), DevicePath::new("R", "S")).unwrap();
+        home.add_device("R", "H", thermostat.into()).unwrap();
+        home
+    }
+
+    fn set_temperature(home: &mut Home, temperature: f64) {
+        home.replace_device("R", "T", Thermometer::new(temperature).into())
+            .unwrap();
+    }
+
+    fn heater_on(home: &Home) -> bool {
+        matches!(home.get_device_by_path("R", "S"), Some(Device::Socket(s)) if s.is_on())
+    }
+
+    #[test]
+    fn test_regulate() {
+        let registry = DeviceRegistry::default();
+        let mut home = home(19.);
+        let changes = regulate(&mut home, &registry);
+        assert!(heater_on(&home));
+        let changed: Vec<_> = changes
+            .iter()
+            .filter_map(|event| match event {
+                ChangeEvent::PropertyChanged {
+                    device,
+                    property,
+                    new,
+                    ..
+                } => Some((device.as_str(), property.as_str(), new.as_deref())),
+                _ => None,
+            })
+            .collect();
+        assert!(changed.contains(&("S", "state", Some("on"))));
+        assert!(changed.contains(&("H", "demand", Some("heating"))));
+        assert!(regulate(&mut home, &registry).is_empty());
+
+        // Inside the band the heater keeps running, above it it stops.
+        set_temperature(&mut home, 21.3);
+        regulate(&mut home, &registry);
+        assert!(heater_on(&home));
+        set_temperature(&mut home, 21.6);
+        regulate(&mut home, &registry);
+        assert!(!heater_on(&home));
+
+        // Turning the thermostat off while heating switches the heater off,
+        // then leaves it to be switched by hand.
+        set_temperature(&mut home, 18.);
+        regulate(&mut home, &registry);
+        assert!(heater_on(&home));
+        let Some(Device::Thermostat(mut thermostat)) = home.get_device_by_path("R", "H").cloned()
+        else {
+            panic!("thermostat is gone");
+        };
+        thermostat.set_mode(ThermostatMode::Off);
+        home.replace_device("R", "H", thermostat.into()).unwrap();
+        regulate(&mut home, &registry);
+        assert!(!heater_on(&home));
+        home.replace_device("R", "S", Socket::new(220., 5., true).into())
+            .unwrap();
+        assert!(regulate(&mut home, &registry).is_empty());
+        assert!(heater_on(&home));
+    }
+
+    #[test]
+    fn test_bindings() {
+        let mut home = home(20.);
+        let wrong = Thermostat::new(21., DevicePath::new("R", "S"), DevicePath::new("R", "S"));
+        let err = home
+            .add_device("R", "H2", wrong.unwrap().into())
+            .unwrap_err();
+        assert_eq!(
+            "Invalid value for 'sensor': 'R/S' is a socket, not a thermometer.",
+            err.to_string()
+        );
+        let missing = Thermostat::new(21., DevicePath::new("R", "T"), DevicePath::new("X", "S"));
+        assert!(home.add_device("R", "H2", missing.unwrap().into()).is_err());
+    }
+}
@@ -1,23 +1,187 @@
-use crate::smart_device::{Device, DeviceDict, Socket, Thermometer};
+use crate::clock::Clock;
+use crate::device_kind::DeviceRegistry;
+use crate::error::{HomeError, HomeResult};
+use crate::events::{self, ChangeEvent, EventBus};
+use crate::home::Home;
+use crate::payload::DevicePayload;
+use crate::problem::Problem;
+use crate::rules;
+use crate::sensors;
+use crate::storage::{Storage, StorageError};
 use crate::SmartHome;
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use actix_web::error::JsonPayloadError;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Responder, ResponseError};
 use querystring::querify;
 use std::collections::HashMap;
-use std::num::ParseFloatError;
+use std::ops::{Deref, DerefMut};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum HandleRequestError {
-    #[error("Device not found {0}.")]
-    DeviceNotFound(String),
-    #[error("Error in device dict {0}.")]
-    BadDeviceDict(String),
-    #[error("Parse float error {0}.")]
-    ParseFloatError(#[from] ParseFloatError),
+    #[error(transparent)]
+    Home(#[from] HomeError),
+    /// The change is in effect, but will be lost on restart.
+    #[error("The change was applied but could not be saved: {0}")]
+    Storage(#[from] StorageError),
+    #[error("Server is in read-only mode.")]
+    ReadOnly,
+    #[error("Unknown device type '{0}'.")]
+    UnknownDeviceType(String),
+    #[error("Rule not found '{0}'.")]
+    RuleNotFound(String),
+    #[error("Invalid rule: {0}.")]
+    InvalidRule(String),
+    #[error("Schedule not found '{0}'.")]
+    ScheduleNotFound(String),
+    #[error("Invalid schedule: {0}.")]
+    InvalidSchedule(String),
+    #[error("Invalid scene: {0}.")]
+    InvalidScene(String),
+    #[error("Invalid query: {0}.")]
+    InvalidQuery(String),
+    #[error("No energy tariff is set.")]
+    TariffNotFound,
+    #[error("Invalid tariff: {0}.")]
+    InvalidTariff(String),
+    #[error("Channel not found '{0}'.")]
+    ChannelNotFound(String),
 }
 
-type HandleRequestResult<T> = Result<T, HandleRequestError>;
+pub(crate) type HandleRequestResult<T> = Result<T, HandleRequestError>;
 
+impl HandleRequestError {
+    /// Stable code used as the problem `type`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Home(e) => match e {
+                HomeError::RoomNotFound { .. } => "room-not-found",
+                HomeError::RoomExists { .. } => "room-exists",
+                HomeError::DeviceNotFound { .. } => "device-not-found",
+                HomeError::DeviceExists { .. } => "device-exists",
+                HomeError::TypeMismatch { .. } => "device-type-mismatch",
+                HomeError::DeviceInUse { .. } => "device-in-use",
+                HomeError::InvalidValue { .. } => "invalid-field",
+                HomeError::BadJson { .. } => "bad-json",
+                HomeError::SceneNotFound { .. } => "scene-not-found",
+            },
+            Self::Storage(_) => "storage-error",
+            Self::ReadOnly => "read-only",
+            Self::UnknownDeviceType(_) => "device-type-not-found",
+            Self::RuleNotFound(_) => "rule-not-found",
+            Self::InvalidRule(_) => "invalid-rule",
+            Self::ScheduleNotFound(_) => "schedule-not-found",
+            Self::InvalidSchedule(_) => "invalid-schedule",
+            Self::InvalidScene(_) => "invalid-scene",
+            Self::InvalidQuery(_) => "invalid-query",
+            Self::TariffNotFound => "tariff-not-found",
+            Self::InvalidTariff(_) => "invalid-tariff",
+            Self::ChannelNotFound(_) => "channel-not-found",
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::Home(e) => match e {
+                HomeError::RoomNotFound { .. } => "Room not found",
+                HomeError::RoomExists { .. } => "Room already exists",
+                HomeError::DeviceNotFound { .. } => "Device not found",
+                HomeError::DeviceExists { .. } => "Device already exists",
+                HomeError::TypeMismatch { .. } => "Device type mismatch",
+                HomeError::DeviceInUse { .. } => "Device in use",
+                HomeError::InvalidValue { .. } => "Invalid device field",
+                HomeError::BadJson { .. } => "Malformed JSON body",
+                HomeError::SceneNotFound { .. } => "Scene not found",
+            },
+            Self::Storage(_) => "Applied but not saved",
+            Self::ReadOnly => "Read-only mode",
+            Self::UnknownDeviceType(_) => "Device type not found",
+            Self::RuleNotFound(_) => "Rule not found",
+            Self::InvalidRule(_) => "Invalid rule",
+            Self::ScheduleNotFound(_) => "Schedule not found",
+            Self::InvalidSchedule(_) => "Invalid schedule",
+            Self::InvalidScene(_) => "Invalid scene",
+            Self::InvalidQuery(_) => "Invalid query parameters",
+            Self::TariffNotFound => "Tariff not found",
+            Self::InvalidTariff(_) => "Invalid tariff",
+            Self::ChannelNotFound(_) => "Channel not found",
+        }
+    }
+
+    pub fn problem(&self) -> Problem {
+        let mut problem = Problem::new(
+            self.status_code(),
+            self.code(),
+            self.title(),
+            self.to_string(),
+        );
+        if let Self::Home(e) = self {
+            problem.room = e.room().map(String::from);
+            problem.device = e.device().map(String::from);
+            if let HomeError::InvalidValue { field, .. } = e {
+                problem.field = Some(field.clone());
+            }
+        }
+        problem
+    }
+}
+
+impl ResponseError for HandleRequestError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Home(e) => match e {
+                HomeError::RoomNotFound { .. } | HomeError::DeviceNotFound { .. } => {
+                    StatusCode::NOT_FOUND
+                }
+                HomeError::RoomExists { .. }
+                | HomeError::DeviceExists { .. }
+                | HomeError::TypeMismatch { .. }
+                | HomeError::DeviceInUse { .. } => StatusCode::CONFLICT,
+                HomeError::InvalidValue { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+                HomeError::BadJson { .. } => StatusCode::BAD_REQUEST,
+                HomeError::SceneNotFound { .. } => StatusCode::NOT_FOUND,
+            },
+            Self::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            Self::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ReadOnly => StatusCode::FORBIDDEN,
+            Self::UnknownDeviceType(_)
+            | Self::RuleNotFound(_)
+            | Self::ScheduleNotFound(_)
+            | Self::TariffNotFound
+            | Self::ChannelNotFound(_) => StatusCode::NOT_FOUND,
+            Self::InvalidRule(_)
+            | Self::InvalidSchedule(_)
+            | Self::InvalidScene(_)
+            | Self::InvalidTariff(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        self.problem().response()
+    }
+}
+
+/// Renders malformed JSON bodies as `bad-json` problems;
+/// too large ones and wrong content types keep their status.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default().error_handler(|err, _| match err {
+        JsonPayloadError::Deserialize(_)
+        | JsonPayloadError::Serialize(_)
+        | JsonPayloadError::Payload(_) => HandleRequestError::from(HomeError::bad_json(err)).into(),
+        err => err.into(),
+    })
+}
+
+/// Renders malformed query strings as `invalid-query` problems.
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default()
+        .error_handler(|err, _| HandleRequestError::InvalidQuery(err.to_string()).into())
+}
+
+#[utoipa::path(
+    get, path = "/", tag = "server",
+    responses((status = 200, description = "Greeting with the home name", body = String, content_type = "text/plain"))
+)]
 pub async fn greet(_: HttpRequest, home: web::Data<SmartHome>) -> impl Responder {
     let home = home.read().await;
     format!(
@@ -26,205 +190,310 @@ pub async fn greet(_: HttpRequest, home: web::Data<SmartHome>) -> impl Responder
     )
 }
 
+#[utoipa::path(
+    get, path = "/health_check", tag = "server",
+    responses((status = 200, description = "The server is up"))
+)]
 pub async fn health_check(_: HttpRequest) -> impl Responder {
     HttpResponse::Ok().finish()
 }
 
+pub async fn read_only(_: HttpRequest) -> HandleRequestResult<HttpResponse> {
+    Err(HandleRequestError::ReadOnly)
+}
+
+#[utoipa::path(
+    get, path = "/room_list", tag = "legacy", operation_id = "legacy_room_list",
+    responses((status = 200, description = "Names of all rooms", body = Vec<String>))
+)]
 pub async fn room_list(_: HttpRequest, home: web::Data<SmartHome>) -> HttpResponse {
     let home = home.read().await;
     let room_list: Vec<_> = home.room_names_list().collect();
     HttpResponse::Ok().json(room_list)
 }
 
-pub async fn device_list(req: HttpRequest, home: web::Data<SmartHome>) -> HttpResponse {
+#[utoipa::path(
+    get, path = "/device_list/{room_name}", tag = "legacy", operation_id = "legacy_device_list",
+    params(("room_name" = String, Path, description = "Name of the room")),
+    responses(
+        (status = 200, description = "Names of the devices in the room", body = Vec<String>),
+        (status = 404, description = "No such room", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn device_list(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+) -> HandleRequestResult<HttpResponse> {
     let room_name = req.match_info().get("room_name").unwrap_or("");
     let home = home.read().await;
-    match home.device_names_list(room_name) {
-        Some(list) => HttpResponse::Ok().json(list),
-        None => HttpResponse::BadRequest().body(format!("Room not found {}", room_name)),
-    }
+    let list = home
+        .device_names_list(room_name)
+        .ok_or_else(|| HomeError::room_not_found(room_name))?;
+    Ok(HttpResponse::Ok().json(list))
 }
 
-pub async fn add_room(req: HttpRequest, home: web::Data<SmartHome>) -> HttpResponse {
-    if let Some(room_name) = req.match_info().get("room_name") {
-        let mut home = home.write().await;
-        match home.add_room(room_name) {
-            Some(_) => HttpResponse::Ok().finish(),
-            None => HttpResponse::BadRequest().body(format!(
-                "Cannot add room '{}' because it already exists.",
-                room_name
-            )),
-        }
-    } else {
-        // this code must be unreachable
-        HttpResponse::InternalServerError().body(format!("Unexpected request: '{:#?}'", req))
-    }
+#[utoipa::path(
+    post, path = "/add_room/{room_name}", tag = "legacy", operation_id = "legacy_add_room",
+    params(("room_name" = String, Path, description = "Name of the room")),
+    responses(
+        (status = 200, description = "Room added"),
+        (status = 409, description = "Room already exists", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn add_room(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = req.match_info().get("room_name").unwrap_or_default();
+    let mut home = home.write().await;
+    home.add_room(room_name)?;
+    let added = ChangeEvent::RoomAdded {
+        room: room_name.into(),
+    };
+    save_and_publish(
+        &storage,
+        home,
+        &registry,
+        &bus,
+        [added],
+        HttpResponse::Ok().finish(),
+    )
+    .await
 }
 
-pub async fn get_device(req: HttpRequest, home: web::Data<SmartHome>) -> HttpResponse {
+#[utoipa::path(
+    get, path = "/{room_name}/{device_name}", tag = "legacy", operation_id = "legacy_get_device",
+    params(("room_name" = String, Path, description = "Name of the room"), ("device_name" = String, Path, description = "Name of the device in the room")),
+    responses(
+        (status = 200, description = "Device dict", body = HashMap<String, String>),
+        (status = 404, description = "No such room or device", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_device(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    registry: web::Data<DeviceRegistry>,
+) -> HandleRequestResult<HttpResponse> {
     let room_name = req.match_info().get("room_name").unwrap_or_default();
     let device_name = req.match_info().get("device_name").unwrap_or_default();
     let home = home.read().await;
-    match home.get_device_by_path(room_name, device_name) {
-        Some(device) => {
-            HttpResponse::Ok()
-                .json(device.device_dict())
-        }
-        None => HttpResponse::BadRequest().body(format!("Device not found at {room_name}/{device_name}")),
-    }
+    let device = home
+        .get_device_by_path(room_name, device_name)
+        .ok_or_else(|| HomeError::device_not_found(room_name, device_name))?;
+    Ok(HttpResponse::Ok().json(registry.device_dict(device)))
 }
 
-pub async fn add_device(req: HttpRequest, home: web::Data<SmartHome>) -> HttpResponse {
+#[utoipa::path(
+    post, path = "/add_device/{room_name}/{device_name}", tag = "legacy", operation_id = "legacy_add_device",
+    params(("room_name" = String, Path, description = "Name of the room"), ("device_name" = String, Path, description = "Name of the device in the room")),
+    request_body(content = DevicePayload, description = "Device fields as JSON, or the same fields in the query string"),
+    responses(
+        (status = 200, description = "Device added"),
+        (status = 404, description = "No such room", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "Device already exists", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid or missing device field", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn add_device(
+    req: HttpRequest,
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
     let room_name = req.match_info().get("room_name").unwrap_or_default();
-    if let Some(device_name) = req.match_info().get("device_name") {
-        let mut home = home.write().await;
-        let query_string = req.query_string();
-        let data: HashMap<_, _> = querify(query_string).into_iter().collect();
-        match create_device_from(data) {
-            Ok(new_device) => match home.add_device(room_name, device_name, new_device) {
-                Some(_) => HttpResponse::Ok().finish(),
-                None => HttpResponse::BadRequest().body(format!(
-                    "Room not found '{}' or duplicate device '{}'.",
-                    room_name, device_name
-                )),
-            },
-            Err(e) => match e {
-                HandleRequestError::BadDeviceDict(_) | HandleRequestError::ParseFloatError(_) => {
-                    HttpResponse::BadRequest().body(format!("{}", e))
-                }
-                _ => HttpResponse::InternalServerError()
-                    .body(format!("Unexpected request: '{:#?}'", req)),
-            },
-        }
-    } else {
-        // this code must be unreachable
-        HttpResponse::InternalServerError().body(format!("Unexpected request: '{:#?}'", req))
-    }
+    let device_name = req.match_info().get("device_name").unwrap_or_default();
+    let new_device = legacy_device_payload(&req, &body, &registry)?.into_device()?;
+    let mut home = home.write().await;
+    let device = home.add_device(room_name, device_name, new_device)?;
+    let added = events::device_added(&registry, room_name, device_name, device);
+    save_and_publish(
+        &storage,
+        home,
+        &registry,
+        &bus,
+        [added],
+        HttpResponse::Ok().finish(),
+    )
+    .await
 }
 
-pub async fn remove_device(req: HttpRequest, home: web::Data<SmartHome>) -> HttpResponse {
+#[utoipa::path(
+    post, path = "/remove_device/{room_name}/{device_name}", tag = "legacy", operation_id = "legacy_remove_device",
+    params(("room_name" = String, Path, description = "Name of the room"), ("device_name" = String, Path, description = "Name of the device in the room")),
+    responses(
+        (status = 200, description = "Dict of the removed device", body = HashMap<String, String>),
+        (status = 404, description = "No such room or device", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn remove_device(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
     let room_name = req.match_info().get("room_name").unwrap_or_default();
     let device_name = req.match_info().get("device_name").unwrap_or_default();
     let mut home = home.write().await;
-    match home.remove_device(room_name, device_name) {
-        Some(device) => HttpResponse::Ok().json(device.device_dict()),
-        None => HttpResponse::BadRequest().body(format!(
-            "Device not found for room/device '{room_name}/{device_name}'."
-        )),
-    }
+    let device = home.remove_device(room_name, device_name)?;
+    let removed = events::device_removed(&registry, room_name, device_name, &device);
+    save_and_publish(
+        &storage,
+        home,
+        &registry,
+        &bus,
+        [removed],
+        HttpResponse::Ok().json(registry.device_dict(&device)),
+    )
+    .await
 }
 
-pub async fn remove_room(req: HttpRequest, home: web::Data<SmartHome>) -> HttpResponse {
+#[utoipa::path(
+    post, path = "/remove_room/{room_name}", tag = "legacy", operation_id = "legacy_remove_room",
+    params(("room_name" = String, Path, description = "Name of the room")),
+    responses(
+        (status = 200, description = "Room removed", body = String, content_type = "text/plain"),
+        (status = 404, description = "No such room", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn remove_room(
+    req: HttpRequest,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
     let room_name = req.match_info().get("room_name").unwrap_or_default();
     let mut home = home.write().await;
-    match home.remove_room(room_name) {
-        Some(_) => HttpResponse::Ok().body(format!("Removed room '{room_name}'.")),
-        None => HttpResponse::BadRequest().body(format!("Room not found '{room_name}'.")),
-    }
+    let room = home.remove_room(room_name)?;
+    save_and_publish(
+        &storage,
+        home,
+        &registry,
+        &bus,
+        events::room_removed(&registry, room_name, &room),
+        HttpResponse::Ok().body(format!("Removed room '{room_name}'.")),
+    )
+    .await
 }
 
-pub async fn update(req: HttpRequest, home: web::Data<SmartHome>) -> HttpResponse {
-    let room_name = dbg!(req.match_info().get("room_name").unwrap_or_default());
-    let device_name = dbg!(req.match_info().get("device_name").unwrap_or_default());
-    let query_string = req.query_string();
-    let data: HashMap<_, _> = querify(query_string).into_iter().collect();
-    match dbg!(update_device(home, room_name, device_name, data).await) {
-        Ok(_) => HttpResponse::Ok().finish(),
-        Err(e) => HttpResponse::BadRequest().body(format!("{}", e)),
-    }
+#[utoipa::path(
+    post, path = "/update/{room_name}/{device_name}", tag = "legacy", operation_id = "legacy_update",
+    params(("room_name" = String, Path, description = "Name of the room"), ("device_name" = String, Path, description = "Name of the device in the room")),
+    request_body(content = DevicePayload, description = "Device fields as JSON, or the same fields in the query string"),
+    responses(
+        (status = 200, description = "Device updated"),
+        (status = 404, description = "No such room or device", body = Problem, content_type = "application/problem+json"),
+        (status = 409, description = "The device is of another type", body = Problem, content_type = "application/problem+json"),
+        (status = 422, description = "Invalid device field", body = Problem, content_type = "application/problem+json"),
+    )
+)]
+pub async fn update(
+    req: HttpRequest,
+    body: web::Bytes,
+    home: web::Data<SmartHome>,
+    storage: web::Data<Storage>,
+    registry: web::Data<DeviceRegistry>,
+    bus: web::Data<EventBus>,
+) -> HandleRequestResult<HttpResponse> {
+    let room_name = req.match_info().get("room_name").unwrap_or_default();
+    let device_name = req.match_info().get("device_name").unwrap_or_default();
+    let payload = legacy_device_payload(&req, &body, &registry)?;
+    let mut home = home.write().await;
+    let changes = events::update_device(&mut home, &registry, room_name, device_name, &payload)?;
+    save_and_publish(
+        &storage,
+        home,
+        &registry,
+        &bus,
+        changes,
+        HttpResponse::Ok().finish(),
+    )
+    .await
 }
 
-pub async fn report(_: HttpRequest, home: web::Data<SmartHome>) -> HttpResponse {
+#[utoipa::path(
+    get, path = "/report", tag = "server",
+    responses((status = 200, description = "Text report about every device", body = String, content_type = "text/plain"))
+)]
+pub async fn report(
+    _: HttpRequest,
+    home: web::Data<SmartHome>,
+    registry: web::Data<DeviceRegistry>,
+    clock: web::Data<dyn Clock>,
+) -> HttpResponse {
     let home = home.read().await;
-    HttpResponse::Ok().body(home.report())
+    HttpResponse::Ok().body(home.report_with(&registry, &clock.now()))
 }
 
-async fn update_device(
-    home: web::Data<SmartHome>,
-    room_name: &str,
-    device_name: &str,
-    data: HashMap<&str, &str>,
-) -> HandleRequestResult<()> {
-    let device_string = data
-        .get("device")
-        .ok_or_else(|| HandleRequestError::BadDeviceDict(format!("{:#?}", data)))?
-        .to_lowercase();
-    let mut home = home.write().await;
-    let device = home
-        .get_device_by_path_mut(room_name, device_name)
-        .ok_or_else(|| {
-            HandleRequestError::DeviceNotFound(format!("{}/{}", room_name, device_name))
-        })?;
-    match device {
-        Device::Socket(socket) => {
-            if device_string == "socket" {
-                if let Some(state) = data.get("state") {
-                    match state.to_lowercase().as_str() {
-                        "on" | "вкл" => socket.switch(true),
-                        "off" | "выкл" => socket.switch(false),
-                        _ => return Err(bad_device_dict_error(&data)),
-                    }
-                }
-                if let Some(current) = data.get("current") {
-                    socket.set_current(current.parse()?);
-                }
-                if let Some(voltage) = data.get("voltage") {
-                    socket.set_voltage(voltage.parse()?);
-                }
-            } else {
-                return Err(bad_device_dict_error(&data));
-            }
-        }
-        Device::Thermometer(thermometer) => {
-            if device_string == "thermometer" {
-                if let Some(temperature) = data.get("temperature") {
-                    thermometer.set_temperature(temperature.parse()?);
-                }
-            } else {
-                return Err(bad_device_dict_error(&data));
-            }
-        }
-        _ => todo!(),
-    }
-    Ok(())
-}
-
-fn create_device_from(data: HashMap<&str, &str>) -> HandleRequestResult<Device> {
-    let device_string = data
-        .get("device")
-        .ok_or_else(|| bad_device_dict_error(&data))?;
-    match device_string.to_lowercase().as_str() {
-        "socket" => {
-            let state = data
-                .get("state")
-                .ok_or_else(|| bad_device_dict_error(&data))?;
-            let on = match state.to_lowercase().as_str() {
-                "on" | "вкл" => Some(true),
-                "off" | "выкл" => Some(false),
-                _ => None,
-            }
-            .ok_or_else(|| bad_device_dict_error(&data))?;
-            let voltage = data
-                .get("voltage")
-                .ok_or_else(|| bad_device_dict_error(&data))?
-                .parse::<f64>()?;
-            let current = data
-                .get("current")
-                .ok_or_else(|| bad_device_dict_error(&data))?
-                .parse::<f64>()?;
-            Ok(Device::Socket(Socket::new(voltage, current, on)))
-        }
-        "thermometer" => {
-            let temperature = data
-                .get("temperature")
-                .ok_or_else(|| bad_device_dict_error(&data))?
-                .parse::<f64>()?;
-            Ok(Device::Thermometer(Thermometer::new(temperature)))
-        }
-        _ => Err(bad_device_dict_error(&data)),
+/// Reads device parameters from a JSON body, or from the query string for old clients.
+pub(crate) fn device_payload(
+    req: &HttpRequest,
+    body: &[u8],
+    registry: &DeviceRegistry,
+) -> HandleRequestResult<DevicePayload> {
+    read_payload(req, body, registry, DevicePayload::from_query)
+}
+
+/// Like [`device_payload`], but ignores read-only keys in the query string.
+fn legacy_device_payload(
+    req: &HttpRequest,
+    body: &[u8],
+    registry: &DeviceRegistry,
+) -> HandleRequestResult<DevicePayload> {
+    read_payload(req, body, registry, DevicePayload::from_legacy_query)
+}
+
+fn read_payload(
+    req: &HttpRequest,
+    body: &[u8],
+    registry: &DeviceRegistry,
+    from_query: fn(&HashMap<&str, &str>, &DeviceRegistry) -> HomeResult<DevicePayload>,
+) -> HandleRequestResult<DevicePayload> {
+    if req.content_type() == "application/json" {
+        Ok(DevicePayload::from_json(body, registry)?)
+    } else {
+        let data: HashMap<_, _> = querify(req.query_string()).into_iter().collect();
+        Ok(from_query(&data, registry)?)
     }
 }
 
-fn bad_device_dict_error(data: &HashMap<&str, &str>) -> HandleRequestError {
-    HandleRequestError::BadDeviceDict(format!("{:#?}", data))
+/// Writes the mutated home to the state file before answering with `response`.
+///
+/// The home is encoded under the caller's lock, which is released before the write;
+/// the storage still writes the saves in the order the changes were made. A failed save
+/// leaves the change in effect and answers with a problem saying it was applied but not saved.
+pub(crate) async fn save_home(
+    storage: &Storage,
+    home: impl Deref<Target = Home>,
+    response: HttpResponse,
+) -> HandleRequestResult<HttpResponse> {
+    let snapshot = storage.snapshot(&home);
+    drop(home);
+    storage.write(snapshot?).await?;
+    Ok(response)
+}
+
+/// Runs the automation rules triggered by the changes, stamps the binary sensors set,
+/// meters the energy used until now, tells subscribers what changed while the lock
+/// still orders the events, then saves the home like [`save_home`].
+pub(crate) async fn save_and_publish(
+    storage: &Storage,
+    mut home: impl DerefMut<Target = Home>,
+    registry: &DeviceRegistry,
+    bus: &EventBus,
+    changes: impl IntoIterator<Item = ChangeEvent>,
+    response: HttpResponse,
+) -> HandleRequestResult<HttpResponse> {
+    let now = bus.now();
+    let mut changes = rules::evaluate(&mut home, registry, changes.into_iter().collect());
+    changes.extend(sensors::stamp(&mut home, registry, &now));
+    home.meter_energy(&now);
+    bus.publish(changes);
+    save_home(storage, home, response).await
 }